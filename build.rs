@@ -1,19 +1,194 @@
-use std::path::PathBuf;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+
+const INPUT_DIR: &str = "disk";
+const USER_PROGRAM_DIR: &str = "user_programs";
 
 fn main() {
     let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
     let kernel = PathBuf::from(std::env::var_os("CARGO_BIN_FILE_KERNEL_kernel").unwrap());
 
+    println!("cargo:rerun-if-changed={}", INPUT_DIR);
+    println!("cargo:rerun-if-changed={}", USER_PROGRAM_DIR);
+
+    let programs_path = out_dir.join("user_programs");
+    let programs = compile_user_programs(&programs_path).unwrap();
+
+    let ramdisk_path = out_dir.join("initramfs.cpio");
+    write_initramfs(&ramdisk_path, Path::new(INPUT_DIR), &programs).unwrap();
+
     let uefi_path = out_dir.join("uefi.img");
     bootloader::UefiBoot::new(&kernel)
+        .set_ramdisk(&ramdisk_path)
         .create_disk_image(&uefi_path)
         .unwrap();
 
     let bios_path = out_dir.join("bios.img");
     bootloader::BiosBoot::new(&kernel)
+        .set_ramdisk(&ramdisk_path)
         .create_disk_image(&bios_path)
         .unwrap();
 
     println!("cargo:rustc-env=UEFI_PATH={}", uefi_path.display());
     println!("cargo:rustc-env=BIOS_PATH={}", bios_path.display());
-}
\ No newline at end of file
+    println!(
+        "cargo:rustc-env=USER_PROGRAMS_PATH={}",
+        programs_path.display()
+    );
+}
+
+/// Builds each `user_programs/*.rs` into `out_dir`, returning the binaries.
+/// A program that fails to build is left out with a warning rather than
+/// failing the whole build.
+fn compile_user_programs(out_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(out_dir)?;
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+
+    let mut sources: Vec<PathBuf> = match fs::read_dir(USER_PROGRAM_DIR) {
+        Ok(entries) => entries
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<_>>()?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    sources.retain(|path| path.extension().is_some_and(|e| e == "rs"));
+    sources.sort();
+
+    let mut programs = Vec::new();
+    for source in sources {
+        let Some(stem) = source.file_stem() else {
+            continue;
+        };
+        let output = out_dir.join(stem);
+
+        let status = Command::new(&rustc)
+            .arg("-O")
+            .arg("--target")
+            .arg("x86_64-unknown-none")
+            .arg("--crate-type")
+            .arg("bin")
+            .arg(&source)
+            .arg("-C")
+            .arg("link-arg=--image-base=0x400000")
+            .arg("-o")
+            .arg(&output)
+            .status()?;
+
+        if status.success() {
+            programs.push(output);
+        } else {
+            println!("cargo:warning=Failed to compile {}", source.display());
+        }
+    }
+    Ok(programs)
+}
+
+/// Packs the contents of `disk` and the user programs into a cpio archive in
+/// the "newc" format, which the kernel unpacks as its root filesystem when
+/// it boots without a disk. Programs land at the top level, as the runner
+/// puts them on the disk image, and replace files of the same name.
+fn write_initramfs(path: &Path, disk: &Path, programs: &[PathBuf]) -> io::Result<()> {
+    let mut archive = Cpio::default();
+
+    if disk.is_dir() {
+        archive.add_tree(disk, "", programs)?;
+    }
+    for program in programs {
+        let name = program.file_name().unwrap().to_string_lossy();
+        archive.add_file(&name, program, 0o100755)?;
+    }
+
+    fs::File::create(path)?.write_all(&archive.finish())
+}
+
+#[derive(Default)]
+struct Cpio {
+    data: Vec<u8>,
+    next_ino: u32,
+}
+
+impl Cpio {
+    fn add_tree(&mut self, dir: &Path, prefix: &str, programs: &[PathBuf]) -> io::Result<()> {
+        let mut entries: Vec<fs::DirEntry> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let name = format!("{}{}", prefix, file_name);
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                self.add_entry(&name, 0o040755, mtime(&path), &[]);
+                self.add_tree(&path, &format!("{}/", name), programs)?;
+            } else if file_type.is_symlink() {
+                let target = fs::read_link(&path)?;
+                let target = target.to_string_lossy();
+                self.add_entry(&name, 0o120777, mtime(&path), target.as_bytes());
+            } else if prefix.is_empty() && programs.iter().any(|p| p.ends_with(&file_name)) {
+                // Rebuilt from `user_programs`.
+            } else {
+                self.add_file(&name, &path, 0o100644)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self, name: &str, path: &Path, mode: u32) -> io::Result<()> {
+        let data = fs::read(path)?;
+        self.add_entry(name, mode, mtime(path), &data);
+        Ok(())
+    }
+
+    fn add_entry(&mut self, name: &str, mode: u32, mtime: u32, data: &[u8]) {
+        self.next_ino += 1;
+        let fields = [
+            self.next_ino,
+            mode,
+            0, // uid
+            0, // gid
+            1, // nlink
+            mtime,
+            data.len() as u32,
+            0, // devmajor
+            0, // devminor
+            0, // rdevmajor
+            0, // rdevminor
+            name.len() as u32 + 1,
+            0, // check
+        ];
+
+        self.data.extend_from_slice(b"070701");
+        for field in fields {
+            self.data
+                .extend_from_slice(format!("{:08X}", field).as_bytes());
+        }
+        self.data.extend_from_slice(name.as_bytes());
+        self.data.push(0);
+        self.pad();
+        self.data.extend_from_slice(data);
+        self.pad();
+    }
+
+    fn pad(&mut self) {
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.add_entry("TRAILER!!!", 0, 0, &[]);
+        self.data
+    }
+}
+
+fn mtime(path: &Path) -> u32 {
+    fs::symlink_metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs() as u32)
+}
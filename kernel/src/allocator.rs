@@ -12,7 +12,7 @@ use crate::memory::pmm::PMM;
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 32 * 1024 * 1024;
 
-#[global_allocator]
+#[cfg_attr(not(test), global_allocator)]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
 pub fn init_heap(
@@ -44,3 +44,8 @@ pub fn init_heap(
 
     Ok(())
 }
+
+/// Bytes of the kernel heap in use, and its total size.
+pub fn heap_usage() -> (usize, usize) {
+    (ALLOCATOR.lock().used(), HEAP_SIZE)
+}
@@ -1,15 +1,24 @@
+use alloc::vec;
+use spin::Mutex;
 use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
 
+use super::block::{BlockDevice, check_range};
+use crate::fs::FsError;
+
 pub const SECTOR_SIZE: usize = 512;
 
 const CMD_READ_SECTORS: u8 = 0x20;
 const CMD_WRITE_SECTORS: u8 = 0x30;
 const CMD_IDENTIFY: u8 = 0xEC;
+const CMD_FLUSH_CACHE: u8 = 0xE7;
 
 const STATUS_BSY: u8 = 0x80;
 const STATUS_DRQ: u8 = 0x08;
 const STATUS_ERR: u8 = 0x01;
 
+/// Sectors moved per command by the block device interface.
+const MAX_TRANSFER: usize = 128;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u16)]
 pub enum Bus {
@@ -17,6 +26,19 @@ pub enum Bus {
     Secondary = 0x170,
 }
 
+impl Bus {
+    fn index(self) -> usize {
+        match self {
+            Bus::Primary => 0,
+            Bus::Secondary => 1,
+        }
+    }
+}
+
+/// The master and slave on a bus share its registers, so only one of them
+/// may have a command in flight at a time.
+static BUS_LOCKS: [Mutex<()>; 2] = [Mutex::new(()), Mutex::new(())];
+
 pub struct AtaDrive {
     data_port: Port<u16>,
     error_port: PortReadOnly<u8>,
@@ -27,7 +49,10 @@ pub struct AtaDrive {
     drive_select_port: Port<u8>,
     command_port: PortWriteOnly<u8>,
     status_port: PortReadOnly<u8>,
+    bus: Bus,
     is_master: bool,
+    /// Sector count reported by the last IDENTIFY.
+    sectors: u32,
 }
 
 impl AtaDrive {
@@ -44,7 +69,9 @@ impl AtaDrive {
             drive_select_port: Port::new(base + 6),
             command_port: PortWriteOnly::new(base + 7),
             status_port: PortReadOnly::new(base + 7),
+            bus,
             is_master,
+            sectors: 0,
         }
     }
 
@@ -105,6 +132,23 @@ impl AtaDrive {
         Ok(())
     }
 
+    /// Waits until the drive has written its volatile cache to the medium.
+    pub fn flush_cache(&mut self) -> Result<(), &'static str> {
+        self.wait_busy();
+
+        let drive_select = if self.is_master { 0xE0 } else { 0xF0 };
+        unsafe {
+            self.drive_select_port.write(drive_select);
+            self.command_port.write(CMD_FLUSH_CACHE);
+        }
+
+        self.wait_busy();
+        if unsafe { self.status_port.read() } & STATUS_ERR != 0 {
+            return Err("ATA Drive Error");
+        }
+        Ok(())
+    }
+
     fn wait_busy(&mut self) {
         while unsafe { self.status_port.read() } & STATUS_BSY != 0 {
             core::hint::spin_loop();
@@ -130,6 +174,10 @@ impl AtaDrive {
     }
 
     pub fn identify(&mut self) -> Result<[u16; 256], &'static str> {
+        // A bus with nothing attached floats high and would never leave BSY.
+        if unsafe { self.status_port.read() } == 0xFF {
+            return Err("No drive on bus");
+        }
         self.wait_busy();
 
         let drive_select = if self.is_master { 0xA0 } else { 0xB0 };
@@ -156,13 +204,73 @@ impl AtaDrive {
             buffer[i] = unsafe { self.data_port.read() };
         }
 
+        // Words 60-61: total user-addressable sectors (LBA28)
+        self.sectors = (buffer[60] as u32) | ((buffer[61] as u32) << 16);
         Ok(buffer)
     }
 
     pub fn get_total_sectors(&mut self) -> Result<u32, &'static str> {
-        let data = self.identify()?;
-        // Words 60-61: total user-addressable sectors (LBA28)
-        let sectors = (data[60] as u32) | ((data[61] as u32) << 16);
-        Ok(sectors)
+        self.identify()?;
+        Ok(self.sectors)
+    }
+}
+
+/// The drive as a block device, serialised by its lock and then by its bus.
+/// Its size is the one found by the last `identify`.
+impl BlockDevice for Mutex<AtaDrive> {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.lock().sectors as u64
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        check_range(lba, buf.len(), SECTOR_SIZE, self.block_count())?;
+
+        let mut drive = self.lock();
+        let _bus = BUS_LOCKS[drive.bus.index()].lock();
+        let mut words = vec![0u16; buf.len().min(MAX_TRANSFER * SECTOR_SIZE) / 2];
+        for (i, chunk) in buf.chunks_mut(MAX_TRANSFER * SECTOR_SIZE).enumerate() {
+            let sectors = chunk.len() / SECTOR_SIZE;
+            let words = &mut words[..sectors * 256];
+            let start = (lba + (i * MAX_TRANSFER) as u64) as u32;
+            drive
+                .read(start, sectors as u8, words)
+                .map_err(|_| FsError::Io)?;
+
+            for (bytes, word) in chunk.as_chunks_mut::<2>().0.iter_mut().zip(words.iter()) {
+                *bytes = word.to_le_bytes();
+            }
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), FsError> {
+        check_range(lba, buf.len(), SECTOR_SIZE, self.block_count())?;
+
+        let mut drive = self.lock();
+        let _bus = BUS_LOCKS[drive.bus.index()].lock();
+        let mut words = vec![0u16; buf.len().min(MAX_TRANSFER * SECTOR_SIZE) / 2];
+        for (i, chunk) in buf.chunks(MAX_TRANSFER * SECTOR_SIZE).enumerate() {
+            let sectors = chunk.len() / SECTOR_SIZE;
+            let words = &mut words[..sectors * 256];
+            for (word, bytes) in words.iter_mut().zip(chunk.as_chunks::<2>().0) {
+                *word = u16::from_le_bytes(*bytes);
+            }
+
+            let start = (lba + (i * MAX_TRANSFER) as u64) as u32;
+            drive
+                .write(start, sectors as u8, words)
+                .map_err(|_| FsError::Io)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), FsError> {
+        let mut drive = self.lock();
+        let _bus = BUS_LOCKS[drive.bus.index()].lock();
+        drive.flush_cache().map_err(|_| FsError::Io)
     }
 }
@@ -0,0 +1,254 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::fs::FsError;
+use crate::fs::file::{FileStat, S_IFBLK};
+use crate::fs::vfs::{File, Inode, InodeKind};
+
+/// Storage addressed in fixed-size blocks. Buffers passed to `read_blocks`
+/// and `write_blocks` must be a whole number of blocks long.
+pub trait BlockDevice: Send + Sync {
+    fn block_size(&self) -> usize;
+    fn block_count(&self) -> u64;
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), FsError>;
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), FsError>;
+
+    /// Makes earlier writes durable.
+    fn flush(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+
+    /// Makes earlier writes to `count` blocks from `lba` durable and drops
+    /// any cached copy of them, once the filesystem on them lets go.
+    fn release(&self, _lba: u64, _count: u64) -> Result<(), FsError> {
+        self.flush()
+    }
+}
+
+/// Checks that `len` bytes starting at block `lba` are whole blocks inside a
+/// device of `block_count` blocks, and returns the number of blocks.
+pub fn check_range(
+    lba: u64,
+    len: usize,
+    block_size: usize,
+    block_count: u64,
+) -> Result<u64, FsError> {
+    if !len.is_multiple_of(block_size) {
+        return Err(FsError::InvalidArgument);
+    }
+
+    let blocks = (len / block_size) as u64;
+    match lba.checked_add(blocks) {
+        Some(end) if end <= block_count => Ok(blocks),
+        _ => Err(FsError::InvalidArgument),
+    }
+}
+
+/// A device held entirely in memory.
+pub struct RamDisk {
+    data: Mutex<Vec<u8>>,
+    block_size: usize,
+}
+
+impl RamDisk {
+    pub fn new(block_size: usize, block_count: usize) -> Self {
+        Self::from_vec(vec![0; block_size * block_count], block_size)
+    }
+
+    /// Wraps an existing image. A trailing partial block is dropped.
+    pub fn from_vec(mut data: Vec<u8>, block_size: usize) -> Self {
+        data.truncate(data.len() / block_size * block_size);
+        Self {
+            data: Mutex::new(data),
+            block_size,
+        }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.lock().len() / self.block_size) as u64
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        check_range(lba, buf.len(), self.block_size, self.block_count())?;
+        let start = lba as usize * self.block_size;
+        buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), FsError> {
+        check_range(lba, buf.len(), self.block_size, self.block_count())?;
+        let start = lba as usize * self.block_size;
+        self.data.lock()[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// A disk image stored in a regular file of some mounted filesystem.
+pub struct FileImage {
+    inode: Arc<dyn Inode>,
+    block_size: usize,
+    block_count: u64,
+}
+
+impl FileImage {
+    /// Uses the whole blocks of `inode` as they are when the image is opened.
+    pub fn new(inode: Arc<dyn Inode>, block_size: usize) -> Result<Self, FsError> {
+        let block_count = inode.stat()?.size / block_size as u64;
+        Ok(Self {
+            inode,
+            block_size,
+            block_count,
+        })
+    }
+}
+
+impl BlockDevice for FileImage {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        check_range(lba, buf.len(), self.block_size, self.block_count)?;
+
+        let offset = lba * self.block_size as u64;
+        let mut filled = 0;
+        while filled < buf.len() {
+            let count = self
+                .inode
+                .read_at(offset + filled as u64, &mut buf[filled..])?;
+            if count == 0 {
+                return Err(FsError::Io);
+            }
+            filled += count;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), FsError> {
+        check_range(lba, buf.len(), self.block_size, self.block_count)?;
+
+        let offset = lba * self.block_size as u64;
+        let mut written = 0;
+        while written < buf.len() {
+            let count = self
+                .inode
+                .write_at(offset + written as u64, &buf[written..])?;
+            if count == 0 {
+                return Err(FsError::Io);
+            }
+            written += count;
+        }
+        Ok(())
+    }
+}
+
+/// A block device as a node in `/dev`. Reads and writes may start and end
+/// anywhere; partial blocks are read back and merged before being written,
+/// and each write is flushed before it returns.
+pub struct DeviceNode {
+    device: Arc<dyn BlockDevice>,
+}
+
+impl DeviceNode {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        Self { device }
+    }
+
+    fn capacity(&self) -> u64 {
+        self.device.block_count() * self.device.block_size() as u64
+    }
+}
+
+impl Inode for DeviceNode {
+    fn kind(&self) -> InodeKind {
+        InodeKind::BlockDevice
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        let block_size = self.device.block_size() as u64;
+        Ok(FileStat {
+            mode: S_IFBLK | 0o660,
+            nlink: 1,
+            size: self.capacity(),
+            blksize: block_size,
+            blocks: self.capacity() / 512,
+            ..FileStat::default()
+        })
+    }
+
+    fn open(&self) -> Result<Option<Arc<dyn File>>, FsError> {
+        Ok(Some(Arc::new(DeviceNode::new(self.device.clone()))))
+    }
+}
+
+impl File for DeviceNode {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let capacity = self.capacity();
+        if offset >= capacity {
+            return Ok(0);
+        }
+        let len = buf.len().min((capacity - offset) as usize);
+
+        let block_size = self.device.block_size();
+        let mut block = vec![0u8; block_size];
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let lba = position / block_size as u64;
+            let within = (position % block_size as u64) as usize;
+            let count = (block_size - within).min(len - done);
+
+            self.device.read_blocks(lba, &mut block)?;
+            buf[done..done + count].copy_from_slice(&block[within..within + count]);
+            done += count;
+        }
+        Ok(len)
+    }
+
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        let capacity = self.capacity();
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if offset >= capacity {
+            return Err(FsError::NoSpace);
+        }
+        let len = buf.len().min((capacity - offset) as usize);
+
+        let block_size = self.device.block_size();
+        let mut block = vec![0u8; block_size];
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let lba = position / block_size as u64;
+            let within = (position % block_size as u64) as usize;
+            let count = (block_size - within).min(len - done);
+
+            if count < block_size {
+                self.device.read_blocks(lba, &mut block)?;
+            }
+            block[within..within + count].copy_from_slice(&buf[done..done + count]);
+            self.device.write_blocks(lba, &block)?;
+            done += count;
+        }
+        self.device.flush()?;
+        Ok(len)
+    }
+
+    fn size(&self) -> Result<u64, FsError> {
+        Ok(self.capacity())
+    }
+}
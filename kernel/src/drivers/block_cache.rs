@@ -0,0 +1,348 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use spin::Mutex;
+
+use super::block::{BlockDevice, check_range};
+use crate::fs::FsError;
+
+/// Blocks held across all cached devices.
+const CACHE_CAPACITY: usize = 2048;
+/// Blocks fetched past a miss that continues a sequential run.
+const READ_AHEAD: u64 = 16;
+/// Most blocks written back in one request.
+const WRITE_BACK_RUN: usize = 64;
+
+type Key = (u32, u64);
+
+struct CacheEntry {
+    data: Box<[u8]>,
+    dirty: bool,
+    stamp: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub read_ahead: u64,
+    pub writebacks: u64,
+    pub evictions: u64,
+    pub blocks: usize,
+    pub dirty: usize,
+}
+
+/// Blocks of every cached device, keyed by device and block number. Clean
+/// blocks are evicted least recently used first; dirty ones stay until their
+/// own device writes them back, so the cache itself never does I/O.
+struct BlockCache {
+    entries: BTreeMap<Key, CacheEntry>,
+    lru: BTreeMap<u64, Key>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl BlockCache {
+    const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            stats: CacheStats {
+                hits: 0,
+                misses: 0,
+                read_ahead: 0,
+                writebacks: 0,
+                evictions: 0,
+                blocks: 0,
+                dirty: 0,
+            },
+        }
+    }
+
+    fn touch(&mut self, key: Key) -> Option<&mut CacheEntry> {
+        let entry = self.entries.get_mut(&key)?;
+        self.lru.remove(&entry.stamp);
+        self.clock += 1;
+        entry.stamp = self.clock;
+        self.lru.insert(self.clock, key);
+        Some(entry)
+    }
+
+    /// Stores a block if there is room for it. A block already cached is
+    /// only replaced when `overwrite` is set, so read-ahead never clobbers
+    /// newer data. Returns `false` when the block is not cached and every
+    /// cached block is dirty.
+    fn insert(&mut self, key: Key, data: &[u8], dirty: bool, overwrite: bool) -> bool {
+        if let Some(entry) = self.touch(key) {
+            if overwrite {
+                entry.data.copy_from_slice(data);
+                entry.dirty |= dirty;
+            }
+            return true;
+        }
+
+        while self.entries.len() >= CACHE_CAPACITY {
+            if !self.evict() {
+                return false;
+            }
+        }
+
+        self.clock += 1;
+        self.lru.insert(self.clock, key);
+        self.entries.insert(
+            key,
+            CacheEntry {
+                data: data.into(),
+                dirty,
+                stamp: self.clock,
+            },
+        );
+        true
+    }
+
+    /// Drops the least recently used clean block, if there is one.
+    fn evict(&mut self) -> bool {
+        let Some((&stamp, &key)) = self.lru.iter().find(|(_, key)| !self.entries[*key].dirty)
+        else {
+            return false;
+        };
+
+        self.lru.remove(&stamp);
+        self.entries.remove(&key);
+        self.stats.evictions += 1;
+        true
+    }
+
+    /// The first run of adjacent dirty blocks of device `id` at or after
+    /// `from`, with their contents, up to `WRITE_BACK_RUN` blocks long.
+    fn dirty_run(&self, id: u32, from: u64) -> Option<(u64, Vec<u8>)> {
+        let mut blocks = self
+            .entries
+            .range((id, from)..=(id, u64::MAX))
+            .filter(|(_, entry)| entry.dirty);
+        let (&(_, start), first) = blocks.next()?;
+
+        let mut data = first.data.to_vec();
+        for (next, (&(_, lba), entry)) in (start + 1..).zip(blocks.take(WRITE_BACK_RUN - 1)) {
+            if lba != next {
+                break;
+            }
+            data.extend_from_slice(&entry.data);
+        }
+        Some((start, data))
+    }
+
+    /// Forgets the blocks of device `id` in `range`, dirty or not.
+    fn remove(&mut self, id: u32, range: Range<u64>) {
+        let keys: Vec<Key> = self
+            .entries
+            .range((id, range.start)..(id, range.end))
+            .map(|(&key, _)| key)
+            .collect();
+
+        for key in keys {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.lru.remove(&entry.stamp);
+            }
+        }
+    }
+}
+
+static CACHE: Mutex<BlockCache> = Mutex::new(BlockCache::new());
+static NEXT_DEVICE_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Counters since boot, with the current number of cached and dirty blocks.
+pub fn stats() -> CacheStats {
+    let cache = CACHE.lock();
+    CacheStats {
+        blocks: cache.entries.len(),
+        dirty: cache.entries.values().filter(|e| e.dirty).count(),
+        ..cache.stats
+    }
+}
+
+/// Routes a device's reads and writes through the shared block cache. Writes
+/// stay in memory until `flush`, or until the cache fills with dirty blocks.
+///
+/// Each device has its own I/O lock, held for a whole operation so that its
+/// blocks cannot change while the cache lock is dropped for the underlying
+/// I/O. Devices therefore never wait on each other's I/O, and an image file
+/// on a cached filesystem can be cached in turn.
+pub struct CachedDevice {
+    id: u32,
+    inner: Arc<dyn BlockDevice>,
+    io: Mutex<()>,
+    /// Block after the last miss, to spot sequential reads.
+    next_sequential: AtomicU64,
+}
+
+impl CachedDevice {
+    pub fn new(inner: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            id: NEXT_DEVICE_ID.fetch_add(1, Ordering::Relaxed),
+            inner,
+            io: Mutex::new(()),
+            next_sequential: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    /// How many blocks to fetch for a miss of `count` blocks at `lba`: more
+    /// when it continues the previous miss, up to the next cached block.
+    fn fetch_len(&self, cache: &BlockCache, lba: u64, count: u64) -> u64 {
+        let mut fetch = count;
+        if self.next_sequential.load(Ordering::Relaxed) == lba {
+            let limit = (lba + count + READ_AHEAD).min(self.block_count());
+            while lba + fetch < limit && !cache.entries.contains_key(&(self.id, lba + fetch)) {
+                fetch += 1;
+            }
+        }
+        fetch
+    }
+
+    /// Reads `fetch` blocks from `lba`, the first of them straight into
+    /// `dest`, and keeps a copy of them where there is room. Called without
+    /// the cache lock.
+    fn fill(&self, lba: u64, dest: &mut [u8], fetch: u64) -> Result<(), FsError> {
+        let block_size = self.block_size();
+        let count = (dest.len() / block_size) as u64;
+
+        let mut ahead = vec![0u8; (fetch - count) as usize * block_size];
+        if ahead.is_empty() {
+            self.inner.read_blocks(lba, dest)?;
+        } else {
+            let mut buf = vec![0u8; fetch as usize * block_size];
+            self.inner.read_blocks(lba, &mut buf)?;
+            let (wanted, rest) = buf.split_at(dest.len());
+            dest.copy_from_slice(wanted);
+            ahead.copy_from_slice(rest);
+        }
+
+        let mut cache = CACHE.lock();
+        for (i, block) in dest
+            .chunks(block_size)
+            .chain(ahead.chunks(block_size))
+            .enumerate()
+        {
+            cache.insert((self.id, lba + i as u64), block, false, false);
+        }
+
+        cache.stats.misses += count;
+        cache.stats.read_ahead += fetch - count;
+        self.next_sequential.store(lba + fetch, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Writes back the dirty blocks of this device in block order, merging
+    /// adjacent blocks into single requests. The caller holds the I/O lock.
+    fn write_back(&self) -> Result<(), FsError> {
+        let block_size = self.block_size();
+        let mut from = 0;
+
+        loop {
+            let Some((start, data)) = CACHE.lock().dirty_run(self.id, from) else {
+                return Ok(());
+            };
+            self.inner.write_blocks(start, &data)?;
+
+            let count = (data.len() / block_size) as u64;
+            let mut cache = CACHE.lock();
+            for lba in start..start + count {
+                if let Some(entry) = cache.entries.get_mut(&(self.id, lba)) {
+                    entry.dirty = false;
+                }
+            }
+            cache.stats.writebacks += count;
+            from = start + count;
+        }
+    }
+}
+
+impl BlockDevice for CachedDevice {
+    fn block_size(&self) -> usize {
+        self.inner.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.inner.block_count()
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        let block_size = self.block_size();
+        let blocks = check_range(lba, buf.len(), block_size, self.block_count())?;
+
+        let _io = self.io.lock();
+        let mut i = 0;
+        while i < blocks {
+            let offset = i as usize * block_size;
+            let mut cache = CACHE.lock();
+            if let Some(entry) = cache.touch((self.id, lba + i)) {
+                buf[offset..offset + block_size].copy_from_slice(&entry.data);
+                cache.stats.hits += 1;
+                i += 1;
+                continue;
+            }
+
+            // Fetch the whole run of misses in one request.
+            let mut count = 1;
+            while i + count < blocks && !cache.entries.contains_key(&(self.id, lba + i + count)) {
+                count += 1;
+            }
+            let fetch = self.fetch_len(&cache, lba + i, count);
+            drop(cache);
+
+            let end = offset + count as usize * block_size;
+            self.fill(lba + i, &mut buf[offset..end], fetch)?;
+            i += count;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), FsError> {
+        let block_size = self.block_size();
+        check_range(lba, buf.len(), block_size, self.block_count())?;
+
+        let _io = self.io.lock();
+        for (i, block) in buf.chunks(block_size).enumerate() {
+            let key = (self.id, lba + i as u64);
+            if CACHE.lock().insert(key, block, true, true) {
+                continue;
+            }
+
+            // The cache is full of dirty blocks. Writing back this device's
+            // own makes room, unless they belong to other devices, in which
+            // case the block goes straight to the device.
+            self.write_back()?;
+            if !CACHE.lock().insert(key, block, true, true) {
+                self.inner.write_blocks(key.1, block)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), FsError> {
+        let _io = self.io.lock();
+        self.write_back()?;
+        self.inner.flush()
+    }
+
+    fn release(&self, lba: u64, count: u64) -> Result<(), FsError> {
+        let _io = self.io.lock();
+        self.write_back()?;
+        CACHE.lock().remove(self.id, lba..lba.saturating_add(count));
+        self.inner.flush()
+    }
+}
+
+impl Drop for CachedDevice {
+    /// Nothing can reach the blocks of a dropped device again, so they are
+    /// written back one last time and dropped from the cache.
+    fn drop(&mut self) {
+        let _ = self.write_back();
+        CACHE.lock().remove(self.id, 0..u64::MAX);
+    }
+}
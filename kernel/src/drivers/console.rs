@@ -0,0 +1,138 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use pc_keyboard::{DecodedKey, HandleControl, Keyboard, ScancodeSet1, layouts};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::fs::FsError;
+use crate::fs::devfs;
+use crate::fs::file::{FileStat, S_IFCHR};
+use crate::fs::vfs::{File, Inode, InodeKind};
+use crate::ipc::poll::PollFlags;
+use crate::ipc::wait::WaitQueue;
+use crate::serial::SERIAL1;
+use crate::task::keyboard::{self, KEYBOARD_WAITERS};
+
+struct ConsoleInput {
+    keyboard: Keyboard<layouts::Us104Key, ScancodeSet1>,
+    pending: VecDeque<u8>,
+}
+
+static INPUT: Mutex<ConsoleInput> = Mutex::new(ConsoleInput {
+    keyboard: Keyboard::new(
+        ScancodeSet1::new(),
+        layouts::Us104Key,
+        HandleControl::Ignore,
+    ),
+    pending: VecDeque::new(),
+});
+
+pub fn init() {
+    keyboard::init_queue();
+    devfs::register("console", Arc::new(ConsoleDevice));
+    devfs::register("tty", Arc::new(ConsoleDevice));
+}
+
+/// Decodes every buffered scancode into the pending input bytes.
+fn pump(input: &mut ConsoleInput) {
+    while let Some(scancode) = keyboard::pop_scancode() {
+        let Ok(Some(event)) = input.keyboard.add_byte(scancode) else {
+            continue;
+        };
+        if let Some(DecodedKey::Unicode(c)) = input.keyboard.process_keyevent(event) {
+            let mut utf8 = [0u8; 4];
+            input.pending.extend(c.encode_utf8(&mut utf8).bytes());
+        }
+    }
+}
+
+pub fn try_read(buf: &mut [u8]) -> Option<usize> {
+    let mut input = INPUT.lock();
+    pump(&mut input);
+
+    if input.pending.is_empty() {
+        return None;
+    }
+
+    let count = buf.len().min(input.pending.len());
+    for (dst, src) in buf.iter_mut().zip(input.pending.drain(..count)) {
+        *dst = src;
+    }
+    Some(count)
+}
+
+pub fn read(buf: &mut [u8]) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+    crate::ipc::wait_until(|| try_read(buf))
+}
+
+pub fn write(buf: &[u8]) -> usize {
+    interrupts::without_interrupts(|| {
+        let mut serial = SERIAL1.lock();
+        for &byte in buf {
+            serial.send(byte);
+        }
+    });
+    buf.len()
+}
+
+pub fn readiness() -> PollFlags {
+    let mut input = INPUT.lock();
+    pump(&mut input);
+
+    if input.pending.is_empty() {
+        PollFlags::WRITABLE
+    } else {
+        PollFlags::READABLE | PollFlags::WRITABLE
+    }
+}
+
+pub fn wait_queue() -> &'static WaitQueue {
+    &KEYBOARD_WAITERS
+}
+
+/// The console as a character device: reads come from the keyboard and
+/// writes go out over serial.
+pub struct ConsoleDevice;
+
+impl Inode for ConsoleDevice {
+    fn kind(&self) -> InodeKind {
+        InodeKind::CharDevice
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        Ok(FileStat {
+            mode: S_IFCHR | 0o620,
+            nlink: 1,
+            ..FileStat::default()
+        })
+    }
+
+    fn open(&self) -> Result<Option<Arc<dyn File>>, FsError> {
+        Ok(Some(Arc::new(ConsoleDevice)))
+    }
+}
+
+impl File for ConsoleDevice {
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        Ok(read(buf))
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        Ok(write(buf))
+    }
+
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn readiness(&self) -> PollFlags {
+        readiness()
+    }
+
+    fn wait_queue(&self) -> Option<&'static WaitQueue> {
+        Some(wait_queue())
+    }
+}
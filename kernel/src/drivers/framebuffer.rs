@@ -0,0 +1,183 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader_api::info::{FrameBufferInfo, PixelFormat};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::fs::FsError;
+use crate::fs::devfs;
+use crate::fs::file::{FileStat, S_IFCHR};
+use crate::fs::vfs::{File, Inode, InodeKind};
+use crate::ipc::shm::SharedMemory;
+use crate::memory::vmm;
+
+const PAGE_SIZE: usize = 4096;
+/// Where the kernel maps the framebuffer for itself. The bootloader's mapping
+/// may be in the lower half, which process address spaces do not share.
+const FB_WINDOW: u64 = 0xFFFF_E000_0000_0000;
+
+/// `ioctl` request that fills in an `FbInfo`.
+pub const FBIOGET_INFO: u32 = 0x4600;
+
+pub const FB_FORMAT_RGB: u32 = 0;
+pub const FB_FORMAT_BGR: u32 = 1;
+pub const FB_FORMAT_U8: u32 = 2;
+pub const FB_FORMAT_OTHER: u32 = 3;
+
+/// Layout of the framebuffer as `FBIOGET_INFO` reports it. `stride` is in
+/// pixels and `size` in bytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FbInfo {
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    pub bytes_per_pixel: u32,
+    pub format: u32,
+    pub size: u32,
+}
+
+/// The framebuffer as a character device. Reads and writes address its bytes
+/// directly, and it can be mapped whole if it starts and ends on a page.
+pub struct FramebufferDevice {
+    info: FbInfo,
+    base: VirtAddr,
+    /// The frames holding it, for `mmap`.
+    frames: Vec<PhysAddr>,
+}
+
+impl FramebufferDevice {
+    /// Finds the frames behind `buffer`, which must be mapped in the current
+    /// address space, and maps them again at `FB_WINDOW`.
+    fn new(info: &FrameBufferInfo, buffer: &[u8]) -> Result<Self, &'static str> {
+        let base = VirtAddr::from_ptr(buffer.as_ptr());
+        let first_page = base.align_down(PAGE_SIZE as u64);
+        let pages = (base - first_page + buffer.len() as u64).div_ceil(PAGE_SIZE as u64);
+
+        let frames = (0..pages)
+            .map(|page| vmm::translate(first_page + page * PAGE_SIZE as u64))
+            .collect::<Option<Vec<_>>>()
+            .ok_or("framebuffer is not mapped")?;
+
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        for (i, &frame) in frames.iter().enumerate() {
+            vmm::map_page(
+                VirtAddr::new(FB_WINDOW + (i * PAGE_SIZE) as u64),
+                frame,
+                flags,
+            )?;
+        }
+        let base = VirtAddr::new(FB_WINDOW) + (base - first_page);
+
+        let format = match info.pixel_format {
+            PixelFormat::Rgb => FB_FORMAT_RGB,
+            PixelFormat::Bgr => FB_FORMAT_BGR,
+            PixelFormat::U8 => FB_FORMAT_U8,
+            _ => FB_FORMAT_OTHER,
+        };
+        let info = FbInfo {
+            width: info.width as u32,
+            height: info.height as u32,
+            stride: info.stride as u32,
+            bytes_per_pixel: info.bytes_per_pixel as u32,
+            format,
+            size: buffer.len() as u32,
+        };
+
+        Ok(Self { info, base, frames })
+    }
+
+    /// Clamps `len` bytes at `offset` to the framebuffer, returning the
+    /// address they start at and how many there are.
+    fn span(&self, offset: u64, len: usize) -> (*mut u8, usize) {
+        let size = self.info.size as u64;
+        let len = len.min(size.saturating_sub(offset) as usize);
+        let ptr = if len == 0 {
+            self.base.as_mut_ptr()
+        } else {
+            (self.base + offset).as_mut_ptr()
+        };
+        (ptr, len)
+    }
+}
+
+impl Inode for FramebufferDevice {
+    fn kind(&self) -> InodeKind {
+        InodeKind::CharDevice
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        Ok(FileStat {
+            mode: S_IFCHR | 0o660,
+            nlink: 1,
+            size: self.info.size as u64,
+            ..FileStat::default()
+        })
+    }
+
+    fn open(&self) -> Result<Option<Arc<dyn File>>, FsError> {
+        Ok(Some(Arc::new(Self {
+            info: self.info,
+            base: self.base,
+            frames: self.frames.clone(),
+        })))
+    }
+}
+
+impl File for FramebufferDevice {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let (ptr, len) = self.span(offset, buf.len());
+        unsafe { core::ptr::copy_nonoverlapping(ptr, buf.as_mut_ptr(), len) };
+        Ok(len)
+    }
+
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        let (ptr, len) = self.span(offset, buf.len());
+        if len == 0 && !buf.is_empty() {
+            return Err(FsError::NoSpace);
+        }
+        unsafe { core::ptr::copy_nonoverlapping(buf.as_ptr(), ptr, len) };
+        Ok(len)
+    }
+
+    fn size(&self) -> Result<u64, FsError> {
+        Ok(self.info.size as u64)
+    }
+
+    fn ioctl(&self, request: u32, buf: &mut [u8]) -> Result<usize, FsError> {
+        match request {
+            FBIOGET_INFO => {
+                let len = core::mem::size_of::<FbInfo>();
+                if buf.len() < len {
+                    return Err(FsError::InvalidArgument);
+                }
+                unsafe {
+                    core::ptr::write_unaligned(buf.as_mut_ptr() as *mut FbInfo, self.info);
+                }
+                Ok(len)
+            }
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+
+    /// Mappings cover whole pages, so a framebuffer that does not end on a
+    /// page is refused rather than exposing the memory after it.
+    fn mmap(&self) -> Result<Arc<SharedMemory>, FsError> {
+        if !self.base.is_aligned(PAGE_SIZE as u64)
+            || !(self.info.size as usize).is_multiple_of(PAGE_SIZE)
+        {
+            return Err(FsError::NotSupported);
+        }
+        Ok(Arc::new(SharedMemory::from_frames(self.frames.clone())))
+    }
+}
+
+/// Registers the framebuffer as `fb0`.
+pub fn init(info: &FrameBufferInfo, buffer: &[u8]) {
+    match FramebufferDevice::new(info, buffer) {
+        Ok(device) => devfs::register("fb0", Arc::new(device)),
+        Err(e) => {
+            crate::serial_println!("[FB] No /dev/fb0: {}", e);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use alloc::sync::Arc;
+
+use crate::fs::FsError;
+use crate::fs::devfs;
+use crate::fs::file::{FileStat, S_IFCHR};
+use crate::fs::vfs::{File, Inode, InodeKind};
+use crate::ipc::poll::PollFlags;
+use crate::ipc::wait::WaitQueue;
+use crate::task::keyboard::{self, KEYBOARD_WAITERS};
+
+fn try_read(buf: &mut [u8]) -> Option<usize> {
+    let mut count = 0;
+    while count < buf.len() {
+        let Some(scancode) = keyboard::pop_raw_scancode() else {
+            break;
+        };
+        buf[count] = scancode;
+        count += 1;
+    }
+    (count > 0).then_some(count)
+}
+
+/// The keyboard as a character device: reads return raw set 1 scancodes,
+/// blocking until at least one is available.
+pub struct KeyboardDevice;
+
+impl Inode for KeyboardDevice {
+    fn kind(&self) -> InodeKind {
+        InodeKind::CharDevice
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        Ok(FileStat {
+            mode: S_IFCHR | 0o640,
+            nlink: 1,
+            ..FileStat::default()
+        })
+    }
+
+    fn open(&self) -> Result<Option<Arc<dyn File>>, FsError> {
+        Ok(Some(Arc::new(KeyboardDevice)))
+    }
+}
+
+impl File for KeyboardDevice {
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        Ok(crate::ipc::wait_until(|| try_read(buf)))
+    }
+
+    fn write(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn readiness(&self) -> PollFlags {
+        if keyboard::has_raw_scancode() {
+            PollFlags::READABLE
+        } else {
+            PollFlags::empty()
+        }
+    }
+
+    fn wait_queue(&self) -> Option<&'static WaitQueue> {
+        Some(&KEYBOARD_WAITERS)
+    }
+}
+
+pub fn init() {
+    keyboard::init_raw_queue();
+    devfs::register("keyboard", Arc::new(KeyboardDevice));
+}
@@ -0,0 +1,74 @@
+use alloc::sync::Arc;
+
+use crate::fs::FsError;
+use crate::fs::devfs;
+use crate::fs::file::{FileStat, S_IFCHR};
+use crate::fs::vfs::{File, Inode, InodeKind};
+
+fn char_device_stat() -> Result<FileStat, FsError> {
+    Ok(FileStat {
+        mode: S_IFCHR | 0o666,
+        nlink: 1,
+        ..FileStat::default()
+    })
+}
+
+/// Reads nothing and discards every write.
+pub struct NullDevice;
+
+impl Inode for NullDevice {
+    fn kind(&self) -> InodeKind {
+        InodeKind::CharDevice
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        char_device_stat()
+    }
+
+    fn open(&self) -> Result<Option<Arc<dyn File>>, FsError> {
+        Ok(Some(Arc::new(NullDevice)))
+    }
+}
+
+impl File for NullDevice {
+    fn read(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Ok(0)
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        Ok(buf.len())
+    }
+}
+
+/// Reads an endless run of zeroes and discards every write.
+pub struct ZeroDevice;
+
+impl Inode for ZeroDevice {
+    fn kind(&self) -> InodeKind {
+        InodeKind::CharDevice
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        char_device_stat()
+    }
+
+    fn open(&self) -> Result<Option<Arc<dyn File>>, FsError> {
+        Ok(Some(Arc::new(ZeroDevice)))
+    }
+}
+
+impl File for ZeroDevice {
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        Ok(buf.len())
+    }
+}
+
+pub fn init() {
+    devfs::register("null", Arc::new(NullDevice));
+    devfs::register("zero", Arc::new(ZeroDevice));
+}
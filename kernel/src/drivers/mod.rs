@@ -1 +1,10 @@
 pub mod ata;
+pub mod block;
+pub mod block_cache;
+pub mod console;
+pub mod framebuffer;
+pub mod keyboard;
+pub mod mem;
+pub mod partition;
+pub mod random;
+pub mod rtc;
@@ -0,0 +1,478 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::block::{BlockDevice, check_range};
+use crate::fs::FsError;
+
+const SECTOR_SIZE: usize = 512;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const MBR_TABLE_OFFSET: usize = 446;
+const MBR_PROTECTIVE: u8 = 0xEE;
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+/// Bound on the logical partition chain, which could otherwise loop.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+const GPT_MAX_TABLE_BYTES: usize = 1024 * 1024;
+
+/// A GPT GUID in its on-disk, mixed-endian byte order.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    fn is_zero(&self) -> bool {
+        self.0 == [0; 16]
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let g = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+            u32::from_le_bytes([g[0], g[1], g[2], g[3]]),
+            u16::from_le_bytes([g[4], g[5]]),
+            u16::from_le_bytes([g[6], g[7]]),
+            g[8],
+            g[9]
+        )?;
+        g[10..].iter().try_for_each(|b| write!(f, "{:02X}", b))
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// Partition type ID from an MBR entry.
+    Mbr(u8),
+    /// Partition type GUID from a GPT entry.
+    Gpt(Guid),
+}
+
+/// A range of blocks on a shared disk. Block numbers are relative to its
+/// start, and accesses past its end are refused.
+pub struct Partition {
+    disk: Arc<dyn BlockDevice>,
+    pub kind: PartitionKind,
+    pub name: String,
+    pub start: u64,
+    pub blocks: u64,
+}
+
+impl BlockDevice for Partition {
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        check_range(lba, buf.len(), self.block_size(), self.blocks)?;
+        self.disk.read_blocks(self.start + lba, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), FsError> {
+        check_range(lba, buf.len(), self.block_size(), self.blocks)?;
+        self.disk.write_blocks(self.start + lba, buf)
+    }
+
+    fn flush(&self) -> Result<(), FsError> {
+        self.disk.flush()
+    }
+
+    fn release(&self, lba: u64, count: u64) -> Result<(), FsError> {
+        let count = count.min(self.blocks.saturating_sub(lba));
+        self.disk.release(self.start + lba, count)
+    }
+}
+
+/// Enumerates the partitions on `disk`. A disk holding a bare filesystem
+/// instead of a partition table yields no partitions.
+pub fn scan(disk: &Arc<dyn BlockDevice>) -> Result<Vec<Partition>, FsError> {
+    if disk.block_size() != SECTOR_SIZE {
+        return Err(FsError::NotSupported);
+    }
+
+    let mbr = read_sector(disk, 0)?;
+    if mbr[510..512] != MBR_SIGNATURE || is_boot_sector(&mbr) {
+        return Ok(Vec::new());
+    }
+
+    let entries = mbr_entries(&mbr).ok_or(FsError::Corrupted)?;
+    let layout = if entries.iter().any(|e| e.kind == MBR_PROTECTIVE) {
+        read_gpt(disk)?
+    } else {
+        read_mbr(disk, &entries)?
+    };
+
+    let disk_blocks = disk.block_count();
+    Ok(layout
+        .into_iter()
+        .filter(|&(_, _, start, blocks)| {
+            start > 0
+                && blocks > 0
+                && start
+                    .checked_add(blocks)
+                    .is_some_and(|end| end <= disk_blocks)
+        })
+        .map(|(kind, name, start, blocks)| Partition {
+            disk: disk.clone(),
+            kind,
+            name,
+            start,
+            blocks,
+        })
+        .collect())
+}
+
+type Layout = Vec<(PartitionKind, String, u64, u64)>;
+
+fn read_sector(disk: &Arc<dyn BlockDevice>, lba: u64) -> Result<[u8; 512], FsError> {
+    let mut buf = [0u8; 512];
+    disk.read_blocks(lba, &mut buf)?;
+    Ok(buf)
+}
+
+/// Whether sector 0 is the boot sector of an unpartitioned FAT volume,
+/// whose boot code would otherwise be read as partition entries.
+fn is_boot_sector(sector: &[u8; 512]) -> bool {
+    let jump = sector[0] == 0xE9 || (sector[0] == 0xEB && sector[2] == 0x90);
+    let bytes_per_sector = u16::from_le_bytes([sector[11], sector[12]]);
+    jump && bytes_per_sector.is_power_of_two() && (512..=4096).contains(&bytes_per_sector)
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    read_u32(buf, offset) as u64 | ((read_u32(buf, offset + 4) as u64) << 32)
+}
+
+struct MbrEntry {
+    kind: u8,
+    start: u64,
+    sectors: u64,
+}
+
+/// The four primary entries, or `None` if their boot flags show the sector
+/// is not an MBR.
+fn mbr_entries(sector: &[u8; 512]) -> Option<[MbrEntry; 4]> {
+    let entry = |i: usize| {
+        let raw = &sector[MBR_TABLE_OFFSET + i * 16..MBR_TABLE_OFFSET + (i + 1) * 16];
+        (raw[0] == 0x00 || raw[0] == 0x80).then(|| MbrEntry {
+            kind: raw[4],
+            start: read_u32(raw, 8) as u64,
+            sectors: read_u32(raw, 12) as u64,
+        })
+    };
+    Some([entry(0)?, entry(1)?, entry(2)?, entry(3)?])
+}
+
+fn read_mbr(disk: &Arc<dyn BlockDevice>, entries: &[MbrEntry; 4]) -> Result<Layout, FsError> {
+    let mut layout = Vec::new();
+
+    for entry in entries.iter().filter(|e| e.kind != 0) {
+        if MBR_EXTENDED.contains(&entry.kind) {
+            read_logical(disk, entry.start, &mut layout)?;
+        } else {
+            layout.push((
+                PartitionKind::Mbr(entry.kind),
+                String::new(),
+                entry.start,
+                entry.sectors,
+            ));
+        }
+    }
+    Ok(layout)
+}
+
+/// Follows the chain of extended boot records starting at `extended_start`.
+/// Each holds one logical partition, relative to itself, and a link to the
+/// next record, relative to the extended partition.
+fn read_logical(
+    disk: &Arc<dyn BlockDevice>,
+    extended_start: u64,
+    layout: &mut Layout,
+) -> Result<(), FsError> {
+    let mut ebr_lba = extended_start;
+
+    for _ in 0..MAX_LOGICAL_PARTITIONS {
+        let sector = read_sector(disk, ebr_lba)?;
+        if sector[510..512] != MBR_SIGNATURE {
+            break;
+        }
+        let Some([logical, next, ..]) = mbr_entries(&sector) else {
+            break;
+        };
+
+        if logical.kind != 0 {
+            let start = ebr_lba + logical.start;
+            layout.push((
+                PartitionKind::Mbr(logical.kind),
+                String::new(),
+                start,
+                logical.sectors,
+            ));
+        }
+        if next.kind == 0 || next.start == 0 {
+            break;
+        }
+        ebr_lba = extended_start + next.start;
+    }
+    Ok(())
+}
+
+/// Reads the GPT, falling back to the backup header in the last sector when
+/// the primary one is damaged.
+fn read_gpt(disk: &Arc<dyn BlockDevice>) -> Result<Layout, FsError> {
+    match read_gpt_at(disk, 1) {
+        Ok(layout) => Ok(layout),
+        Err(_) => read_gpt_at(disk, disk.block_count().saturating_sub(1)),
+    }
+}
+
+fn read_gpt_at(disk: &Arc<dyn BlockDevice>, header_lba: u64) -> Result<Layout, FsError> {
+    let header = read_sector(disk, header_lba)?;
+    if &header[0..8] != GPT_SIGNATURE {
+        return Err(FsError::Corrupted);
+    }
+
+    let header_size = read_u32(&header, 12) as usize;
+    if !(92..=512).contains(&header_size) {
+        return Err(FsError::Corrupted);
+    }
+    let mut checked = header;
+    checked[16..20].fill(0);
+    if crc32(&checked[..header_size]) != read_u32(&header, 16) {
+        return Err(FsError::Corrupted);
+    }
+
+    let entries_lba = read_u64(&header, 72);
+    let entry_count = read_u32(&header, 80) as usize;
+    let entry_size = read_u32(&header, 84) as usize;
+    let table_bytes = entry_count.saturating_mul(entry_size);
+    if entry_size < GPT_MIN_ENTRY_SIZE || entry_size % 8 != 0 || table_bytes > GPT_MAX_TABLE_BYTES {
+        return Err(FsError::Corrupted);
+    }
+
+    let mut table = vec![0u8; table_bytes.next_multiple_of(SECTOR_SIZE)];
+    disk.read_blocks(entries_lba, &mut table)?;
+    table.truncate(table_bytes);
+    if crc32(&table) != read_u32(&header, 88) {
+        return Err(FsError::Corrupted);
+    }
+
+    let mut layout = Vec::new();
+    for raw in table.chunks(entry_size) {
+        let mut type_guid = [0u8; 16];
+        type_guid.copy_from_slice(&raw[0..16]);
+        let kind = Guid(type_guid);
+        if kind.is_zero() {
+            continue;
+        }
+
+        let start = read_u64(raw, 32);
+        let last = read_u64(raw, 40);
+        let Some(sectors) = last.checked_sub(start).and_then(|n| n.checked_add(1)) else {
+            continue;
+        };
+
+        let units = raw[56..128]
+            .chunks(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .take_while(|&unit| unit != 0);
+        let name = char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+
+        layout.push((PartitionKind::Gpt(kind), name, start, sectors));
+    }
+    Ok(layout)
+}
+
+/// CRC-32 as used by GPT (IEEE 802.3, reflected).
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::block::RamDisk;
+
+    const DISK_SECTORS: usize = 2048;
+
+    fn disk_with(sectors: &[(u64, [u8; 512])]) -> Arc<dyn BlockDevice> {
+        let disk = RamDisk::new(SECTOR_SIZE, DISK_SECTORS);
+        for (lba, sector) in sectors {
+            disk.write_blocks(*lba, sector).unwrap();
+        }
+        Arc::new(disk)
+    }
+
+    fn set_mbr_entry(sector: &mut [u8; 512], index: usize, kind: u8, start: u32, sectors: u32) {
+        let raw = &mut sector[MBR_TABLE_OFFSET + index * 16..MBR_TABLE_OFFSET + (index + 1) * 16];
+        raw[4] = kind;
+        raw[8..12].copy_from_slice(&start.to_le_bytes());
+        raw[12..16].copy_from_slice(&sectors.to_le_bytes());
+    }
+
+    fn boot_record(entries: &[(u8, u32, u32)]) -> [u8; 512] {
+        let mut sector = [0u8; 512];
+        for (index, &(kind, start, sectors)) in entries.iter().enumerate() {
+            set_mbr_entry(&mut sector, index, kind, start, sectors);
+        }
+        sector[510..512].copy_from_slice(&MBR_SIGNATURE);
+        sector
+    }
+
+    /// A GPT header at `lba` describing a one-sector table at `entries_lba`.
+    fn gpt_header(lba: u64, entries_lba: u64, table: &[u8; 512]) -> [u8; 512] {
+        let mut header = [0u8; 512];
+        header[0..8].copy_from_slice(GPT_SIGNATURE);
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&lba.to_le_bytes());
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&crc32(table).to_le_bytes());
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        header
+    }
+
+    fn gpt_table() -> [u8; 512] {
+        let mut table = [0u8; 512];
+        let raw = &mut table[..128];
+        raw[0..16].copy_from_slice(&[0xAF; 16]);
+        raw[32..40].copy_from_slice(&64u64.to_le_bytes());
+        raw[40..48].copy_from_slice(&1063u64.to_le_bytes());
+        for (i, unit) in "root".encode_utf16().enumerate() {
+            raw[56 + i * 2..58 + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        table
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn mbr_with_logical_chain() {
+        // Two logical partitions in an extended one at 1000: the first EBR
+        // sits at its start, the second 300 sectors in.
+        let mbr = boot_record(&[(0x83, 63, 900), (0x0F, 1000, 1000)]);
+        let first = boot_record(&[(0x0C, 2, 200), (0x05, 300, 400)]);
+        let second = boot_record(&[(0x83, 2, 100)]);
+        let disk = disk_with(&[(0, mbr), (1000, first), (1300, second)]);
+
+        let partitions = scan(&disk).unwrap();
+        let layout: Vec<_> = partitions
+            .iter()
+            .map(|p| (p.kind, p.start, p.blocks))
+            .collect();
+        assert_eq!(
+            layout,
+            [
+                (PartitionKind::Mbr(0x83), 63, 900),
+                (PartitionKind::Mbr(0x0C), 1002, 200),
+                (PartitionKind::Mbr(0x83), 1302, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn partition_reads_are_offset_and_bounded() {
+        let mbr = boot_record(&[(0x83, 100, 10)]);
+        let mut marker = [0u8; 512];
+        marker[0] = 0x5A;
+        let disk = disk_with(&[(0, mbr), (105, marker)]);
+
+        let partition = scan(&disk).unwrap().remove(0);
+        let mut buf = [0u8; 512];
+        partition.read_blocks(5, &mut buf).unwrap();
+        assert_eq!(buf[0], 0x5A);
+        assert!(partition.read_blocks(10, &mut buf).is_err());
+    }
+
+    #[test]
+    fn partitions_past_the_disk_are_dropped() {
+        let mbr = boot_record(&[(0x83, 2000, 100), (0x83, 0, 10)]);
+        let disk = disk_with(&[(0, mbr)]);
+        assert!(scan(&disk).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bare_boot_sector_has_no_partitions() {
+        let mut sector = boot_record(&[(0x83, 63, 100)]);
+        sector[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        sector[11..13].copy_from_slice(&512u16.to_le_bytes());
+        let disk = disk_with(&[(0, sector)]);
+        assert!(scan(&disk).unwrap().is_empty());
+    }
+
+    #[test]
+    fn gpt_falls_back_to_backup_header() {
+        let last = DISK_SECTORS as u64 - 1;
+        let table = gpt_table();
+        let mbr = boot_record(&[(MBR_PROTECTIVE, 1, last as u32)]);
+        let mut primary = gpt_header(1, 2, &table);
+        primary[40] ^= 1;
+        let backup = gpt_header(last, last - 1, &table);
+        let disk = disk_with(&[
+            (0, mbr),
+            (1, primary),
+            (2, table),
+            (last - 1, table),
+            (last, backup),
+        ]);
+
+        let partitions = scan(&disk).unwrap();
+        assert_eq!(partitions.len(), 1);
+        assert_eq!(partitions[0].kind, PartitionKind::Gpt(Guid([0xAF; 16])));
+        assert_eq!(partitions[0].name, "root");
+        assert_eq!((partitions[0].start, partitions[0].blocks), (64, 1000));
+    }
+
+    #[test]
+    fn gpt_without_valid_header_is_corrupted() {
+        let table = gpt_table();
+        let mbr = boot_record(&[(MBR_PROTECTIVE, 1, 2047)]);
+        let mut primary = gpt_header(1, 2, &table);
+        primary[40] ^= 1;
+        let disk = disk_with(&[(0, mbr), (1, primary), (2, table)]);
+        assert!(matches!(scan(&disk), Err(FsError::Corrupted)));
+    }
+}
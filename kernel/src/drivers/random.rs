@@ -0,0 +1,84 @@
+use alloc::sync::Arc;
+use core::arch::x86_64::_rdtsc;
+use spin::Mutex;
+use x86_64::instructions::random::RdRand;
+
+use crate::fs::FsError;
+use crate::fs::devfs;
+use crate::fs::file::{FileStat, S_IFCHR};
+use crate::fs::vfs::{File, Inode, InodeKind};
+
+/// State of the xorshift generator used on processors without RDRAND. It is
+/// not cryptographically secure; the timestamp counter mixed into each draw
+/// only keeps separate boots from repeating the same sequence.
+static FALLBACK: Mutex<u64> = Mutex::new(0x9E37_79B9_7F4A_7C15);
+
+fn fallback_u64() -> u64 {
+    let mut state = FALLBACK.lock();
+    let mut x = *state ^ unsafe { _rdtsc() };
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+fn random_u64() -> u64 {
+    RdRand::new()
+        .and_then(|rdrand| rdrand.get_u64())
+        .unwrap_or_else(fallback_u64)
+}
+
+/// Fills `buf` with random bytes, from RDRAND where the processor has it.
+pub fn fill(buf: &mut [u8]) {
+    let (words, rest) = buf.as_chunks_mut::<8>();
+    for word in words {
+        *word = random_u64().to_ne_bytes();
+    }
+    let last = random_u64().to_ne_bytes();
+    rest.copy_from_slice(&last[..rest.len()]);
+}
+
+/// An endless source of random bytes. Writes are accepted and discarded.
+pub struct RandomDevice;
+
+impl Inode for RandomDevice {
+    fn kind(&self) -> InodeKind {
+        InodeKind::CharDevice
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        Ok(FileStat {
+            mode: S_IFCHR | 0o666,
+            nlink: 1,
+            ..FileStat::default()
+        })
+    }
+
+    fn open(&self) -> Result<Option<Arc<dyn File>>, FsError> {
+        Ok(Some(Arc::new(RandomDevice)))
+    }
+}
+
+impl File for RandomDevice {
+    fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        fill(buf);
+        Ok(buf.len())
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        Ok(buf.len())
+    }
+
+    fn is_seekable(&self) -> bool {
+        false
+    }
+}
+
+pub fn init() {
+    if RdRand::new().is_none() {
+        crate::serial_println!("[RANDOM] No RDRAND; falling back to a non-cryptographic generator");
+    }
+    devfs::register("random", Arc::new(RandomDevice));
+    devfs::register("urandom", Arc::new(RandomDevice));
+}
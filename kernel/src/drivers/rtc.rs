@@ -0,0 +1,224 @@
+use core::hint::spin_loop;
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+const REG_CENTURY: u8 = 0x32;
+
+const STATUS_A_UPDATING: u8 = 0x80;
+const STATUS_B_24_HOUR: u8 = 0x02;
+const STATUS_B_BINARY: u8 = 0x04;
+const HOURS_PM: u8 = 0x80;
+
+const TIME_REGISTERS: [u8; 7] = [
+    REG_SECONDS,
+    REG_MINUTES,
+    REG_HOURS,
+    REG_DAY,
+    REG_MONTH,
+    REG_YEAR,
+    REG_CENTURY,
+];
+
+/// A calendar date and time of day, as kept by the RTC. QEMU runs the RTC
+/// in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00.
+    pub fn to_unix(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        let seconds = self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+        (days * 86400 + seconds).max(0) as u64
+    }
+}
+
+/// Days from 1970-01-01 to the given proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+struct Cmos {
+    address: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Cmos {
+    fn read(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.address.write(register);
+            self.data.read()
+        }
+    }
+
+    fn read_time(&mut self) -> [u8; 7] {
+        while self.read(REG_STATUS_A) & STATUS_A_UPDATING != 0 {
+            spin_loop();
+        }
+        TIME_REGISTERS.map(|register| self.read(register))
+    }
+}
+
+static CMOS: Mutex<Cmos> = Mutex::new(Cmos {
+    address: Port::new(0x70),
+    data: Port::new(0x71),
+});
+
+/// Reads the current wall-clock time. An update can land between two
+/// register reads, so the registers are read until two passes agree.
+pub fn now() -> DateTime {
+    // Host-side tests have no CMOS to read.
+    if cfg!(test) {
+        return DateTime {
+            year: 2024,
+            month: 6,
+            day: 15,
+            hour: 12,
+            minute: 30,
+            second: 0,
+        };
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+
+        let mut raw = cmos.read_time();
+        loop {
+            let again = cmos.read_time();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+
+        decode(raw, cmos.read(REG_STATUS_B))
+    })
+}
+
+/// Seconds since the Unix epoch, kept by the timer from a single reading of
+/// the RTC. Cheap enough for paths too hot to poll the CMOS on every call.
+pub fn unix_time() -> u64 {
+    static BOOT_TIME: Once<u64> = Once::new();
+    let uptime = crate::interrupts::uptime_ms() / 1000;
+    let boot = *BOOT_TIME.call_once(|| now().to_unix().saturating_sub(uptime));
+    boot + uptime
+}
+
+/// Converts the raw registers, which may be BCD and may use a 12-hour clock
+/// depending on status register B.
+fn decode(raw: [u8; 7], status_b: u8) -> DateTime {
+    let value = |v: u8| {
+        if status_b & STATUS_B_BINARY != 0 {
+            v
+        } else {
+            (v >> 4) * 10 + (v & 0x0F)
+        }
+    };
+    let [second, minute, hours, day, month, year, century] = raw;
+
+    let mut hour = value(hours & !HOURS_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        hour %= 12;
+        if hours & HOURS_PM != 0 {
+            hour += 12;
+        }
+    }
+
+    // The century register is not standard; fall back to the 2000s when it
+    // holds nothing sensible.
+    let century = match value(century) {
+        century @ 19..=99 => century as u16,
+        _ => 20,
+    };
+
+    DateTime {
+        year: century * 100 + value(year) as u16,
+        month: value(month),
+        day: value(day),
+        hour,
+        minute: value(minute),
+        second: value(second),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_from_civil_epochs() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19782);
+    }
+
+    #[test]
+    fn unix_time_of_dates() {
+        let time = DateTime {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hour: 23,
+            minute: 59,
+            second: 59,
+        };
+        assert_eq!(time.to_unix(), 1_709_251_199);
+    }
+
+    #[test]
+    fn decodes_bcd_twelve_hour() {
+        // 11:05:09 PM on 2023-12-31, BCD, century register set.
+        let raw = [0x09, 0x05, 0x11 | HOURS_PM, 0x31, 0x12, 0x23, 0x20];
+        let time = decode(raw, 0);
+        assert_eq!(
+            time,
+            DateTime {
+                year: 2023,
+                month: 12,
+                day: 31,
+                hour: 23,
+                minute: 5,
+                second: 9,
+            }
+        );
+
+        // 12 AM is midnight and 12 PM is noon.
+        assert_eq!(decode([0, 0, 0x12, 1, 1, 0x24, 0x20], 0).hour, 0);
+        assert_eq!(
+            decode([0, 0, 0x12 | HOURS_PM, 1, 1, 0x24, 0x20], 0).hour,
+            12
+        );
+    }
+
+    #[test]
+    fn decodes_binary_twenty_four_hour() {
+        let status_b = STATUS_B_BINARY | STATUS_B_24_HOUR;
+        let time = decode([59, 30, 17, 15, 6, 99, 19], status_b);
+        assert_eq!((time.year, time.hour, time.minute), (1999, 17, 30));
+
+        // A century register holding nothing sensible means the 2000s.
+        assert_eq!(decode([0, 0, 0, 1, 1, 5, 0xFF], status_b).year, 2005);
+    }
+}
@@ -0,0 +1,104 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use super::FsError;
+use super::file::{FileStat, S_IFDIR};
+use super::vfs::{DirEntry, FileSystem, Inode, InodeKind};
+
+const ROOT_INO: u64 = 1;
+
+struct Device {
+    /// Handed out at registration, so listings keep it across calls.
+    ino: u64,
+    node: Arc<dyn Inode>,
+}
+
+/// Device nodes by name. Drivers add theirs as they come up, before or after
+/// `/dev` is mounted.
+static DEVICES: Mutex<BTreeMap<String, Device>> = Mutex::new(BTreeMap::new());
+static NEXT_INO: AtomicU64 = AtomicU64::new(ROOT_INO + 1);
+
+/// Makes `node` appear as `/dev/<name>`, replacing any node of that name.
+pub fn register(name: &str, node: Arc<dyn Inode>) {
+    let ino = NEXT_INO.fetch_add(1, Ordering::Relaxed);
+    DEVICES
+        .lock()
+        .insert(name.to_string(), Device { ino, node });
+}
+
+/// A flat directory of every registered device. Nodes can be neither created
+/// nor removed through it.
+pub struct DevFs;
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(DevRoot)
+    }
+}
+
+struct DevRoot;
+
+impl Inode for DevRoot {
+    fn kind(&self) -> InodeKind {
+        InodeKind::Directory
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        Ok(FileStat {
+            mode: S_IFDIR | 0o755,
+            nlink: 2,
+            ino: ROOT_INO,
+            ..FileStat::default()
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        DEVICES
+            .lock()
+            .get(name)
+            .map(|device| device.node.clone())
+            .ok_or(FsError::NotFound)
+    }
+
+    fn create(&self, _name: &str, _kind: InodeKind) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn rmdir(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let devices: Vec<(String, u64, Arc<dyn Inode>)> = DEVICES
+            .lock()
+            .iter()
+            .map(|(name, device)| (name.clone(), device.ino, device.node.clone()))
+            .collect();
+
+        Ok(devices
+            .into_iter()
+            .map(|(name, ino, node)| DirEntry {
+                name,
+                kind: node.kind(),
+                size: node.stat().map_or(0, |stat| stat.size),
+                id: ino,
+            })
+            .collect())
+    }
+}
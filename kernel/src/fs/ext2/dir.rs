@@ -0,0 +1,523 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::disk::{
+    self, DIR_ENTRY_HEADER, DiskInode, FT_DIR, INCOMPAT_FILETYPE, INDEX_FL, S_IFDIR, S_IFLNK,
+};
+use super::{Ext2Driver, now, read_u32, write_u32};
+use crate::fs::FsError;
+
+/// Most links an inode may have.
+const LINK_MAX: u16 = 65000;
+const NAME_MAX: usize = 255;
+
+/// An entry listed by `Ext2Driver::read_dir`.
+pub struct DirRecord {
+    pub name: String,
+    pub ino: u32,
+    /// Directory entry file type, or `FT_UNKNOWN` on volumes that do not
+    /// record it.
+    pub file_type: u8,
+}
+
+/// Where a directory entry lives and what it holds. Entries with inode 0
+/// are unused space.
+#[derive(Clone, Copy)]
+struct Slot {
+    block: u32,
+    offset: usize,
+    /// Offset of the entry before it in the same block.
+    prev: Option<usize>,
+    rec_len: usize,
+    name_len: usize,
+    ino: u32,
+    file_type: u8,
+}
+
+impl Slot {
+    /// Bytes past the end of the name that a new entry could take over.
+    fn spare(&self) -> usize {
+        if self.ino == 0 {
+            self.rec_len
+        } else {
+            self.rec_len - disk::entry_len(self.name_len)
+        }
+    }
+}
+
+fn check_name(name: &str) -> Result<(), FsError> {
+    if name.is_empty() || name.len() > NAME_MAX || name.contains(['/', '\0']) {
+        return Err(FsError::InvalidArgument);
+    }
+    if name == "." || name == ".." {
+        return Err(FsError::AlreadyExists);
+    }
+    Ok(())
+}
+
+fn write_entry(buf: &mut [u8], offset: usize, ino: u32, rec_len: usize, name: &[u8], ft: u8) {
+    write_u32(buf, offset, ino);
+    buf[offset + 4..offset + 6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+    buf[offset + 6] = name.len() as u8;
+    buf[offset + 7] = ft;
+    buf[offset + DIR_ENTRY_HEADER..offset + DIR_ENTRY_HEADER + name.len()].copy_from_slice(name);
+}
+
+impl Ext2Driver {
+    fn file_type_field(&self, ft: u8) -> u8 {
+        if self.superblock.feature_incompat & INCOMPAT_FILETYPE != 0 {
+            ft
+        } else {
+            0
+        }
+    }
+
+    /// Visits the entries of directory `dir` in order, unused ones included,
+    /// until `visit` accepts one, and returns it.
+    fn scan_dir(
+        &mut self,
+        dir: u32,
+        mut visit: impl FnMut(&Slot, &[u8]) -> bool,
+    ) -> Result<Option<Slot>, FsError> {
+        let mut inode = self.read_inode(dir)?;
+        if !inode.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+
+        let goal = self.group_of(dir);
+        let mut buf = vec![0u8; self.block_size];
+        for index in 0..inode.size().div_ceil(self.block_size as u64) {
+            let block = self.map_block(&mut inode, goal, index, false)?;
+            if block == 0 {
+                return Err(FsError::Corrupted);
+            }
+            self.read_block(block, &mut buf)?;
+
+            let mut offset = 0;
+            let mut prev = None;
+            while offset < self.block_size {
+                if offset + DIR_ENTRY_HEADER > self.block_size {
+                    return Err(FsError::Corrupted);
+                }
+                let slot = Slot {
+                    block,
+                    offset,
+                    prev,
+                    rec_len: u16::from_le_bytes([buf[offset + 4], buf[offset + 5]]) as usize,
+                    name_len: buf[offset + 6] as usize,
+                    ino: read_u32(&buf, offset),
+                    file_type: buf[offset + 7],
+                };
+                if slot.rec_len < DIR_ENTRY_HEADER
+                    || !slot.rec_len.is_multiple_of(4)
+                    || offset + slot.rec_len > self.block_size
+                    || (slot.ino != 0 && disk::entry_len(slot.name_len) > slot.rec_len)
+                {
+                    return Err(FsError::Corrupted);
+                }
+
+                let name_end = (offset + DIR_ENTRY_HEADER + slot.name_len).min(self.block_size);
+                if visit(&slot, &buf[offset + DIR_ENTRY_HEADER..name_end]) {
+                    return Ok(Some(slot));
+                }
+                prev = Some(offset);
+                offset += slot.rec_len;
+            }
+        }
+        Ok(None)
+    }
+
+    fn find_slot(&mut self, dir: u32, name: &str) -> Result<Option<Slot>, FsError> {
+        self.scan_dir(dir, |slot, entry_name| {
+            slot.ino != 0 && entry_name == name.as_bytes()
+        })
+    }
+
+    pub fn lookup(&mut self, dir: u32, name: &str) -> Result<u32, FsError> {
+        match self.find_slot(dir, name)? {
+            Some(slot) => Ok(slot.ino),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    /// Lists the entries of directory `dir`, without `.` and `..`.
+    pub fn read_dir(&mut self, dir: u32) -> Result<Vec<DirRecord>, FsError> {
+        let mut records = Vec::new();
+        self.scan_dir(dir, |slot, name| {
+            if slot.ino != 0 && name != b"." && name != b".." {
+                records.push(DirRecord {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    ino: slot.ino,
+                    file_type: slot.file_type,
+                });
+            }
+            false
+        })?;
+        Ok(records)
+    }
+
+    fn is_dir_empty(&mut self, dir: u32) -> Result<bool, FsError> {
+        let other = self.scan_dir(dir, |slot, name| {
+            slot.ino != 0 && name != b"." && name != b".."
+        })?;
+        Ok(other.is_none())
+    }
+
+    /// Records a change to the entries of `dir`. Any hash index no longer
+    /// matches them, so it is dropped and the entries are searched linearly.
+    fn touch_dir(&mut self, dir: u32) -> Result<(), FsError> {
+        let mut inode = self.read_inode(dir)?;
+        inode.flags &= !INDEX_FL;
+        inode.mtime = now();
+        inode.ctime = inode.mtime;
+        self.write_inode(dir, &inode)
+    }
+
+    /// Adds an entry for `ino`, reusing spare space in the directory's blocks
+    /// before growing it by one.
+    fn add_entry(&mut self, dir: u32, name: &str, ino: u32, file_type: u8) -> Result<(), FsError> {
+        let needed = disk::entry_len(name.len());
+        let file_type = self.file_type_field(file_type);
+        let mut buf = vec![0u8; self.block_size];
+
+        if let Some(slot) = self.scan_dir(dir, |slot, _| slot.spare() >= needed)? {
+            self.read_block(slot.block, &mut buf)?;
+            if slot.ino == 0 {
+                write_entry(
+                    &mut buf,
+                    slot.offset,
+                    ino,
+                    slot.rec_len,
+                    name.as_bytes(),
+                    file_type,
+                );
+            } else {
+                let used = disk::entry_len(slot.name_len);
+                buf[slot.offset + 4..slot.offset + 6].copy_from_slice(&(used as u16).to_le_bytes());
+                let offset = slot.offset + used;
+                write_entry(
+                    &mut buf,
+                    offset,
+                    ino,
+                    slot.rec_len - used,
+                    name.as_bytes(),
+                    file_type,
+                );
+            }
+            self.write_block(slot.block, &buf)?;
+            return self.touch_dir(dir);
+        }
+
+        let mut inode = self.read_inode(dir)?;
+        let size = inode.size();
+        let block = self.map_block(
+            &mut inode,
+            self.group_of(dir),
+            size / self.block_size as u64,
+            true,
+        )?;
+        buf.fill(0);
+        write_entry(
+            &mut buf,
+            0,
+            ino,
+            self.block_size,
+            name.as_bytes(),
+            file_type,
+        );
+        self.write_block(block, &buf)?;
+
+        inode.set_size(size + self.block_size as u64);
+        self.write_inode(dir, &inode)?;
+        self.touch_dir(dir)
+    }
+
+    /// Removes the entry `name` from `dir` and returns the inode it named.
+    /// Its space joins the entry before it, or is marked unused when it
+    /// starts a block.
+    fn remove_entry(&mut self, dir: u32, name: &str) -> Result<u32, FsError> {
+        let slot = self.find_slot(dir, name)?.ok_or(FsError::NotFound)?;
+        let mut buf = vec![0u8; self.block_size];
+        self.read_block(slot.block, &mut buf)?;
+
+        match slot.prev {
+            Some(prev) => {
+                let prev_len = u16::from_le_bytes([buf[prev + 4], buf[prev + 5]]) as usize;
+                let merged = (prev_len + slot.rec_len) as u16;
+                buf[prev + 4..prev + 6].copy_from_slice(&merged.to_le_bytes());
+            }
+            None => write_u32(&mut buf, slot.offset, 0),
+        }
+        self.write_block(slot.block, &buf)?;
+        self.touch_dir(dir)?;
+        Ok(slot.ino)
+    }
+
+    /// Points the existing entry `name` in `dir` at another inode.
+    fn retarget_entry(
+        &mut self,
+        dir: u32,
+        name: &str,
+        ino: u32,
+        file_type: u8,
+    ) -> Result<(), FsError> {
+        let slot = self.find_slot(dir, name)?.ok_or(FsError::NotFound)?;
+        let mut buf = vec![0u8; self.block_size];
+        self.read_block(slot.block, &mut buf)?;
+        write_u32(&mut buf, slot.offset, ino);
+        buf[slot.offset + 7] = self.file_type_field(file_type);
+        self.write_block(slot.block, &buf)?;
+        self.touch_dir(dir)
+    }
+
+    /// Gives the new directory `ino` its first block, holding `.` and `..`.
+    pub(super) fn init_dir(
+        &mut self,
+        ino: u32,
+        parent: u32,
+        inode: &mut DiskInode,
+    ) -> Result<(), FsError> {
+        let block = self.map_block(inode, self.group_of(ino), 0, true)?;
+        let file_type = self.file_type_field(FT_DIR);
+        let dot_len = disk::entry_len(1);
+
+        let mut buf = vec![0u8; self.block_size];
+        write_entry(&mut buf, 0, ino, dot_len, b".", file_type);
+        write_entry(
+            &mut buf,
+            dot_len,
+            parent,
+            self.block_size - dot_len,
+            b"..",
+            file_type,
+        );
+        self.write_block(block, &buf)?;
+        inode.set_size(self.block_size as u64);
+        Ok(())
+    }
+
+    /// Fails with `NotFound` for a directory that was removed while still
+    /// open, so nothing is added to it.
+    fn check_dir_linked(&self, dir: u32) -> Result<(), FsError> {
+        if self.read_inode(dir)?.links_count == 0 {
+            return Err(FsError::NotFound);
+        }
+        Ok(())
+    }
+
+    fn adjust_links(&mut self, ino: u32, delta: i32) -> Result<DiskInode, FsError> {
+        let mut inode = self.read_inode(ino)?;
+        let links = inode.links_count as i32 + delta;
+        if links > LINK_MAX as i32 {
+            return Err(FsError::TooManyLinks);
+        }
+        inode.links_count = links.max(0) as u16;
+        inode.ctime = now();
+        self.write_inode(ino, &inode)?;
+        Ok(inode)
+    }
+
+    /// Creates `name` in directory `dir` as a new inode of `mode`, which
+    /// holds both the type and the permission bits.
+    pub fn create(
+        &mut self,
+        dir: u32,
+        name: &str,
+        mode: u16,
+        uid: u32,
+        gid: u32,
+    ) -> Result<u32, FsError> {
+        self.create_node(dir, name, mode, uid, gid, &[])
+    }
+
+    pub fn symlink(&mut self, dir: u32, name: &str, target: &str) -> Result<u32, FsError> {
+        if target.is_empty() || target.len() >= self.block_size {
+            return Err(FsError::InvalidArgument);
+        }
+        self.create_node(dir, name, S_IFLNK | 0o777, 0, 0, target.as_bytes())
+    }
+
+    fn create_node(
+        &mut self,
+        dir: u32,
+        name: &str,
+        mode: u16,
+        uid: u32,
+        gid: u32,
+        link_target: &[u8],
+    ) -> Result<u32, FsError> {
+        self.check_writable()?;
+        check_name(name)?;
+        self.check_dir_linked(dir)?;
+        if self.find_slot(dir, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let is_dir = mode & disk::S_IFMT == S_IFDIR;
+        if is_dir {
+            self.adjust_links(dir, 1)?;
+        }
+
+        let added = self
+            .new_inode(dir, mode, uid, gid, link_target)
+            .and_then(
+                |ino| match self.add_entry(dir, name, ino, disk::file_type_of(mode)) {
+                    Ok(()) => Ok(ino),
+                    Err(e) => {
+                        let mut inode = self.read_inode(ino)?;
+                        self.release_inode(ino, &mut inode)?;
+                        Err(e)
+                    }
+                },
+            );
+        if added.is_err() && is_dir {
+            self.adjust_links(dir, -1)?;
+        }
+        added
+    }
+
+    /// Adds `name` in `dir` as another link to the non-directory `ino`.
+    pub fn link(&mut self, dir: u32, name: &str, ino: u32) -> Result<(), FsError> {
+        self.check_writable()?;
+        check_name(name)?;
+        self.check_dir_linked(dir)?;
+        if self.find_slot(dir, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let inode = self.read_inode(ino)?;
+        if inode.links_count == 0 {
+            return Err(FsError::NotFound);
+        }
+        if inode.is_dir() {
+            return Err(FsError::IsDirectory);
+        }
+
+        // The count goes up first, so a crash in between leaves a leaked
+        // link count rather than an entry to a freed inode.
+        self.adjust_links(ino, 1)?;
+        if let Err(e) = self.add_entry(dir, name, ino, disk::file_type_of(inode.mode)) {
+            self.adjust_links(ino, -1)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Removes the non-directory entry `name`, freeing its inode with the
+    /// last link.
+    pub fn unlink(&mut self, dir: u32, name: &str) -> Result<(), FsError> {
+        self.check_writable()?;
+        let slot = self.find_slot(dir, name)?.ok_or(FsError::NotFound)?;
+        if self.read_inode(slot.ino)?.is_dir() {
+            return Err(FsError::IsDirectory);
+        }
+
+        self.remove_entry(dir, name)?;
+        self.drop_link(slot.ino)
+    }
+
+    fn drop_link(&mut self, ino: u32) -> Result<(), FsError> {
+        let mut inode = self.adjust_links(ino, -1)?;
+        if inode.links_count == 0 {
+            self.drop_inode(ino, &mut inode)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the empty subdirectory `name`.
+    pub fn rmdir(&mut self, dir: u32, name: &str) -> Result<(), FsError> {
+        self.check_writable()?;
+        if name == "." || name == ".." {
+            return Err(FsError::InvalidArgument);
+        }
+        let slot = self.find_slot(dir, name)?.ok_or(FsError::NotFound)?;
+        let mut inode = self.read_inode(slot.ino)?;
+        if !inode.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        if !self.is_dir_empty(slot.ino)? {
+            return Err(FsError::NotEmpty);
+        }
+
+        self.remove_entry(dir, name)?;
+        self.drop_inode(slot.ino, &mut inode)?;
+        self.adjust_links(dir, -1)?;
+        Ok(())
+    }
+
+    /// Moves entry `old_name` of `old_dir` to `new_name` in `new_dir`,
+    /// replacing what is there unless it is a non-empty directory or of the
+    /// other kind. Callers keep directories from moving below themselves.
+    pub fn rename(
+        &mut self,
+        old_dir: u32,
+        old_name: &str,
+        new_dir: u32,
+        new_name: &str,
+    ) -> Result<(), FsError> {
+        self.check_writable()?;
+        check_name(old_name)?;
+        check_name(new_name)?;
+        self.check_dir_linked(new_dir)?;
+
+        let slot = self
+            .find_slot(old_dir, old_name)?
+            .ok_or(FsError::NotFound)?;
+        let ino = slot.ino;
+        let inode = self.read_inode(ino)?;
+        let is_dir = inode.is_dir();
+        let file_type = disk::file_type_of(inode.mode);
+
+        let replaced = match self.find_slot(new_dir, new_name)? {
+            Some(existing) if existing.ino == ino => return Ok(()),
+            Some(existing) => {
+                let target = self.read_inode(existing.ino)?;
+                match (is_dir, target.is_dir()) {
+                    (true, false) => return Err(FsError::NotDirectory),
+                    (false, true) => return Err(FsError::IsDirectory),
+                    (true, true) if !self.is_dir_empty(existing.ino)? => {
+                        return Err(FsError::NotEmpty);
+                    }
+                    _ => {}
+                }
+                Some(existing.ino)
+            }
+            None => None,
+        };
+
+        if is_dir && old_dir != new_dir && replaced.is_none() {
+            self.adjust_links(new_dir, 1)?;
+        }
+
+        // The new name is in place before the old one goes, so a crash
+        // leaves two links rather than none.
+        self.adjust_links(ino, 1)?;
+        match replaced {
+            Some(_) => self.retarget_entry(new_dir, new_name, ino, file_type)?,
+            None => self.add_entry(new_dir, new_name, ino, file_type)?,
+        }
+        self.remove_entry(old_dir, old_name)?;
+        self.adjust_links(ino, -1)?;
+
+        if is_dir && old_dir != new_dir {
+            self.retarget_entry(ino, "..", new_dir, FT_DIR)?;
+            self.adjust_links(old_dir, -1)?;
+        }
+
+        if let Some(target) = replaced {
+            if is_dir {
+                let mut target_inode = self.read_inode(target)?;
+                self.drop_inode(target, &mut target_inode)?;
+                // The replaced directory's `..` no longer counts, while the
+                // moved one's does, unless it was already here.
+                if old_dir == new_dir {
+                    self.adjust_links(new_dir, -1)?;
+                }
+            } else {
+                self.drop_link(target)?;
+            }
+        }
+        Ok(())
+    }
+}
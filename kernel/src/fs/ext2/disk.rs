@@ -0,0 +1,277 @@
+use core::mem::size_of;
+use core::ptr;
+
+pub const SUPERBLOCK_OFFSET: usize = 1024;
+pub const SUPERBLOCK_SIZE: usize = 1024;
+pub const MAGIC: u16 = 0xEF53;
+
+pub const STATE_VALID: u16 = 1;
+pub const STATE_ERRORS: u16 = 2;
+
+pub const ROOT_INO: u32 = 2;
+/// First non-reserved inode of revision 0 filesystems.
+pub const GOOD_OLD_FIRST_INO: u32 = 11;
+pub const GOOD_OLD_INODE_SIZE: usize = 128;
+
+pub const INCOMPAT_FILETYPE: u32 = 0x0002;
+pub const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE;
+
+pub const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+pub const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+pub const SUPPORTED_RO_COMPAT: u32 = RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE;
+
+pub const S_IFMT: u16 = 0o170000;
+pub const S_IFSOCK: u16 = 0o140000;
+pub const S_IFLNK: u16 = 0o120000;
+pub const S_IFREG: u16 = 0o100000;
+pub const S_IFBLK: u16 = 0o060000;
+pub const S_IFDIR: u16 = 0o040000;
+pub const S_IFCHR: u16 = 0o020000;
+pub const S_IFIFO: u16 = 0o010000;
+
+/// Directory uses hashed b-tree lookups. Entries stay readable linearly, but
+/// the index goes stale once they change, so the flag is dropped then.
+pub const INDEX_FL: u32 = 0x1000;
+
+pub const DIRECT_BLOCKS: usize = 12;
+pub const INDIRECT: usize = 12;
+pub const DOUBLE_INDIRECT: usize = 13;
+pub const TRIPLE_INDIRECT: usize = 14;
+
+/// Symlink targets shorter than this live in `block` itself.
+pub const FAST_SYMLINK_MAX: usize = 60;
+
+pub const FT_UNKNOWN: u8 = 0;
+pub const FT_REG_FILE: u8 = 1;
+pub const FT_DIR: u8 = 2;
+pub const FT_CHRDEV: u8 = 3;
+pub const FT_BLKDEV: u8 = 4;
+pub const FT_FIFO: u8 = 5;
+pub const FT_SOCK: u8 = 6;
+pub const FT_SYMLINK: u8 = 7;
+
+/// Size of a directory entry header, before the name.
+pub const DIR_ENTRY_HEADER: usize = 8;
+
+/// The part of the superblock this driver reads or updates.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub r_blocks_count: u32,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    pub first_data_block: u32,
+    pub log_block_size: u32,
+    pub log_frag_size: u32,
+    pub blocks_per_group: u32,
+    pub frags_per_group: u32,
+    pub inodes_per_group: u32,
+    pub mtime: u32,
+    pub wtime: u32,
+    pub mnt_count: u16,
+    pub max_mnt_count: u16,
+    pub magic: u16,
+    pub state: u16,
+    pub errors: u16,
+    pub minor_rev_level: u16,
+    pub lastcheck: u32,
+    pub checkinterval: u32,
+    pub creator_os: u32,
+    pub rev_level: u32,
+    pub def_resuid: u16,
+    pub def_resgid: u16,
+    pub first_ino: u32,
+    pub inode_size: u16,
+    pub block_group_nr: u16,
+    pub feature_compat: u32,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct GroupDescriptor {
+    pub block_bitmap: u32,
+    pub inode_bitmap: u32,
+    pub inode_table: u32,
+    pub free_blocks_count: u16,
+    pub free_inodes_count: u16,
+    pub used_dirs_count: u16,
+    pub pad: u16,
+    pub reserved: [u8; 12],
+}
+
+/// The revision 0 inode. Larger inodes carry extra fields after it, of which
+/// only the creation time is used.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct DiskInode {
+    pub mode: u16,
+    pub uid: u16,
+    pub size: u32,
+    pub atime: u32,
+    pub ctime: u32,
+    pub mtime: u32,
+    pub dtime: u32,
+    pub gid: u16,
+    pub links_count: u16,
+    /// Blocks held, data and metadata, in 512-byte units.
+    pub blocks: u32,
+    pub flags: u32,
+    pub osd1: u32,
+    pub block: [u32; 15],
+    pub generation: u32,
+    pub file_acl: u32,
+    /// High half of the size of a regular file.
+    pub size_high: u32,
+    pub faddr: u32,
+    pub blocks_high: u16,
+    pub file_acl_high: u16,
+    pub uid_high: u16,
+    pub gid_high: u16,
+    pub reserved: u32,
+}
+
+/// Offsets into the extended part of inodes larger than 128 bytes.
+pub const EXTRA_ISIZE_OFFSET: usize = 128;
+pub const CRTIME_OFFSET: usize = 144;
+/// `extra_isize` written to new inodes: everything up to and including the
+/// creation time and its nanoseconds.
+pub const NEW_EXTRA_ISIZE: u16 = 32;
+
+const _: () = assert!(size_of::<Superblock>() == 104);
+const _: () = assert!(size_of::<GroupDescriptor>() == 32);
+const _: () = assert!(size_of::<DiskInode>() == GOOD_OLD_INODE_SIZE);
+
+/// Copies a `T` out of `buf` at `offset`. The structures above have only
+/// naturally aligned fields, so plain `repr(C)` matches the disk layout, but
+/// their place in a block buffer need not be aligned.
+pub fn read<T: Copy>(buf: &[u8], offset: usize) -> T {
+    assert!(offset + size_of::<T>() <= buf.len());
+    unsafe { ptr::read_unaligned(buf[offset..].as_ptr() as *const T) }
+}
+
+/// Copies `value` into `buf` at `offset`.
+pub fn write<T: Copy>(buf: &mut [u8], offset: usize, value: &T) {
+    assert!(offset + size_of::<T>() <= buf.len());
+    unsafe { ptr::write_unaligned(buf[offset..].as_mut_ptr() as *mut T, *value) }
+}
+
+impl DiskInode {
+    pub const fn empty() -> Self {
+        Self {
+            mode: 0,
+            uid: 0,
+            size: 0,
+            atime: 0,
+            ctime: 0,
+            mtime: 0,
+            dtime: 0,
+            gid: 0,
+            links_count: 0,
+            blocks: 0,
+            flags: 0,
+            osd1: 0,
+            block: [0; 15],
+            generation: 0,
+            file_acl: 0,
+            size_high: 0,
+            faddr: 0,
+            blocks_high: 0,
+            file_acl_high: 0,
+            uid_high: 0,
+            gid_high: 0,
+            reserved: 0,
+        }
+    }
+
+    pub fn file_type(&self) -> u16 {
+        self.mode & S_IFMT
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == S_IFDIR
+    }
+
+    /// Size in bytes. Only regular files use the high half; in directories
+    /// it held an ACL block in older revisions.
+    pub fn size(&self) -> u64 {
+        if self.file_type() == S_IFREG {
+            self.size as u64 | (self.size_high as u64) << 32
+        } else {
+            self.size as u64
+        }
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        self.size = size as u32;
+        if self.file_type() == S_IFREG {
+            self.size_high = (size >> 32) as u32;
+        }
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid as u32 | (self.uid_high as u32) << 16
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid as u32 | (self.gid_high as u32) << 16
+    }
+
+    pub fn set_owner(&mut self, uid: u32, gid: u32) {
+        self.uid = uid as u16;
+        self.uid_high = (uid >> 16) as u16;
+        self.gid = gid as u16;
+        self.gid_high = (gid >> 16) as u16;
+    }
+
+    /// Whether this symlink keeps its target in `block` rather than in a
+    /// data block. An extended attribute block, if any, is counted in
+    /// `blocks` without holding data.
+    pub fn is_fast_symlink(&self, block_size: usize) -> bool {
+        let acl_blocks = if self.file_acl != 0 {
+            (block_size / 512) as u32
+        } else {
+            0
+        };
+        self.file_type() == S_IFLNK && self.blocks == acl_blocks
+    }
+
+    /// The bytes of `block`, where fast symlinks keep their target.
+    pub fn inline_data(&self) -> [u8; FAST_SYMLINK_MAX] {
+        let mut data = [0u8; FAST_SYMLINK_MAX];
+        for (chunk, word) in data.as_chunks_mut::<4>().0.iter_mut().zip(self.block) {
+            *chunk = word.to_le_bytes();
+        }
+        data
+    }
+
+    pub fn set_inline_data(&mut self, data: &[u8]) {
+        let mut bytes = [0u8; FAST_SYMLINK_MAX];
+        bytes[..data.len()].copy_from_slice(data);
+        for (word, chunk) in self.block.iter_mut().zip(bytes.as_chunks::<4>().0) {
+            *word = u32::from_le_bytes(*chunk);
+        }
+    }
+}
+
+/// Directory entry file type for an inode mode.
+pub fn file_type_of(mode: u16) -> u8 {
+    match mode & S_IFMT {
+        S_IFREG => FT_REG_FILE,
+        S_IFDIR => FT_DIR,
+        S_IFCHR => FT_CHRDEV,
+        S_IFBLK => FT_BLKDEV,
+        S_IFIFO => FT_FIFO,
+        S_IFSOCK => FT_SOCK,
+        S_IFLNK => FT_SYMLINK,
+        _ => FT_UNKNOWN,
+    }
+}
+
+/// Space a directory entry with a name of `name_len` bytes needs.
+pub fn entry_len(name_len: usize) -> usize {
+    (DIR_ENTRY_HEADER + name_len).next_multiple_of(4)
+}
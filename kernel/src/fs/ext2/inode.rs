@@ -0,0 +1,247 @@
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::{Mutex, MutexGuard};
+
+use super::disk::{self, DiskInode, S_IFDIR, S_IFREG};
+use super::{Ext2Driver, ROOT_INO};
+use crate::fs::FsError;
+use crate::fs::file::FileStat;
+use crate::fs::vfs::{DirEntry, FileSystem, Inode, InodeKind, path};
+
+pub struct Ext2Fs {
+    driver: Mutex<Ext2Driver>,
+    this: Weak<Ext2Fs>,
+}
+
+impl Ext2Fs {
+    pub fn new(driver: Ext2Driver) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            driver: Mutex::new(driver),
+            this: this.clone(),
+        })
+    }
+
+    pub fn driver(&self) -> MutexGuard<'_, Ext2Driver> {
+        self.driver.lock()
+    }
+
+    /// Wraps `ino` in a node that holds it open until dropped.
+    fn inode(&self, driver: &mut Ext2Driver, ino: u32, kind: InodeKind) -> Arc<dyn Inode> {
+        driver.open_inode(ino);
+        Arc::new(Ext2Inode {
+            fs: self.this.upgrade().expect("Ext2Fs dropped while in use"),
+            ino,
+            kind,
+        })
+    }
+
+    /// Resolves a path from the root of the volume to the inode number of
+    /// its parent directory and its final component. Symlinks along the
+    /// way are not followed.
+    fn resolve_parent<'a>(
+        driver: &mut Ext2Driver,
+        path: &'a str,
+    ) -> Result<(u32, &'a str), FsError> {
+        let (parent, name) = path::split_parent(path).ok_or(FsError::Busy)?;
+
+        let mut dir = ROOT_INO;
+        for component in parent.split('/').filter(|c| !c.is_empty()) {
+            dir = driver.lookup(dir, component)?;
+        }
+        Ok((dir, name))
+    }
+}
+
+fn kind_of(inode: &DiskInode) -> InodeKind {
+    match inode.file_type() {
+        disk::S_IFDIR => InodeKind::Directory,
+        disk::S_IFLNK => InodeKind::Symlink,
+        disk::S_IFCHR => InodeKind::CharDevice,
+        disk::S_IFBLK => InodeKind::BlockDevice,
+        _ => InodeKind::File,
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.inode(&mut self.driver(), ROOT_INO, InodeKind::Directory)
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        self.driver().sync()
+    }
+
+    fn unmount(&self) -> Result<(), FsError> {
+        self.driver().unmount()
+    }
+
+    fn rename(&self, old_path: &str, new_path: &str) -> Result<(), FsError> {
+        let mut driver = self.driver();
+        let (old_dir, old_name) = Self::resolve_parent(&mut driver, old_path)?;
+        let (new_dir, new_name) = Self::resolve_parent(&mut driver, new_path)?;
+        driver.rename(old_dir, old_name, new_dir, new_name)
+    }
+
+    fn link(&self, old_path: &str, new_path: &str) -> Result<(), FsError> {
+        let mut driver = self.driver();
+        let (old_dir, old_name) = Self::resolve_parent(&mut driver, old_path)?;
+        let ino = driver.lookup(old_dir, old_name)?;
+        let (new_dir, new_name) = Self::resolve_parent(&mut driver, new_path)?;
+        driver.link(new_dir, new_name, ino)
+    }
+}
+
+/// A node named by its inode number, so it stays valid across renames and
+/// is shared by every hard link to it. It keeps the inode from being freed
+/// while it lives, even once the last link is gone.
+struct Ext2Inode {
+    fs: Arc<Ext2Fs>,
+    ino: u32,
+    kind: InodeKind,
+}
+
+impl Ext2Inode {
+    fn check_dir(&self) -> Result<(), FsError> {
+        if self.kind != InodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+        Ok(())
+    }
+
+    /// Only regular files have data to read and write; device nodes have no
+    /// driver behind them here.
+    fn check_file(&self) -> Result<(), FsError> {
+        match self.kind {
+            InodeKind::File => Ok(()),
+            InodeKind::Directory => Err(FsError::IsDirectory),
+            _ => Err(FsError::NotSupported),
+        }
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn kind(&self) -> InodeKind {
+        self.kind
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        let driver = self.fs.driver();
+        let inode = driver.read_inode(self.ino)?;
+
+        Ok(FileStat {
+            mode: inode.mode as u32,
+            nlink: inode.links_count as u32,
+            size: inode.size(),
+            blksize: driver.block_size() as u64,
+            blocks: inode.blocks as u64,
+            atime: inode.atime as u64,
+            mtime: inode.mtime as u64,
+            btime: driver.creation_time(self.ino)? as u64,
+            ino: self.ino as u64,
+            uid: inode.uid(),
+            gid: inode.gid(),
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_dir()?;
+        let mut driver = self.fs.driver();
+        let ino = driver.lookup(self.ino, name)?;
+        let kind = kind_of(&driver.read_inode(ino)?);
+        Ok(self.fs.inode(&mut driver, ino, kind))
+    }
+
+    fn create(&self, name: &str, kind: InodeKind) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_dir()?;
+        let mode = match kind {
+            InodeKind::File => S_IFREG | 0o644,
+            InodeKind::Directory => S_IFDIR | 0o755,
+            _ => return Err(FsError::NotSupported),
+        };
+
+        let mut driver = self.fs.driver();
+        let ino = driver.create(self.ino, name, mode, 0, 0)?;
+        Ok(self.fs.inode(&mut driver, ino, kind))
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_dir()?;
+        let mut driver = self.fs.driver();
+        let ino = driver.symlink(self.ino, name, target)?;
+        Ok(self.fs.inode(&mut driver, ino, InodeKind::Symlink))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        self.check_dir()?;
+        self.fs.driver().unlink(self.ino, name)
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), FsError> {
+        self.check_dir()?;
+        self.fs.driver().rmdir(self.ino, name)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        self.check_dir()?;
+        let mut driver = self.fs.driver();
+
+        driver
+            .read_dir(self.ino)?
+            .into_iter()
+            .map(|record| {
+                let inode = driver.read_inode(record.ino)?;
+                Ok(DirEntry {
+                    name: record.name,
+                    kind: kind_of(&inode),
+                    size: inode.size(),
+                    id: record.ino as u64,
+                })
+            })
+            .collect()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        self.check_file()?;
+        self.fs.driver().read_at(self.ino, offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        self.check_file()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.fs.driver().write_at(self.ino, offset, buf)
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        self.check_file()?;
+        self.fs.driver().truncate(self.ino, size)
+    }
+
+    fn readlink(&self) -> Result<String, FsError> {
+        if self.kind != InodeKind::Symlink {
+            return Err(FsError::InvalidArgument);
+        }
+        let target = self.fs.driver().read_link(self.ino)?;
+        String::from_utf8(target).map_err(|_| FsError::Corrupted)
+    }
+
+    fn set_mode(&self, mode: u32) -> Result<(), FsError> {
+        self.fs.driver().set_mode(self.ino, mode)
+    }
+
+    fn set_owner(&self, uid: u32, gid: u32) -> Result<(), FsError> {
+        self.fs.driver().set_owner(self.ino, uid, gid)
+    }
+}
+
+impl Drop for Ext2Inode {
+    fn drop(&mut self) {
+        let _ = self.fs.driver().close_inode(self.ino);
+    }
+}
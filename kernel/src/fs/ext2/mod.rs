@@ -0,0 +1,1323 @@
+mod dir;
+mod disk;
+mod inode;
+
+pub use dir::DirRecord;
+pub use inode::Ext2Fs;
+
+use self::disk::{
+    CRTIME_OFFSET, DIRECT_BLOCKS, DOUBLE_INDIRECT, DiskInode, EXTRA_ISIZE_OFFSET,
+    GOOD_OLD_FIRST_INO, GOOD_OLD_INODE_SIZE, GroupDescriptor, INDIRECT, MAGIC, NEW_EXTRA_ISIZE,
+    RO_COMPAT_LARGE_FILE, S_IFDIR, S_IFLNK, S_IFREG, STATE_ERRORS, STATE_VALID, SUPERBLOCK_OFFSET,
+    SUPERBLOCK_SIZE, SUPPORTED_INCOMPAT, SUPPORTED_RO_COMPAT, Superblock, TRIPLE_INDIRECT,
+};
+use crate::drivers::block::BlockDevice;
+use crate::drivers::rtc;
+use crate::fs::FsError;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub use self::disk::ROOT_INO;
+
+/// Largest block size ext2 defines.
+const MAX_BLOCK_SIZE: usize = 65536;
+
+/// Seconds since the Unix epoch, as ext2 stores times.
+fn now() -> u32 {
+    rtc::now().to_unix() as u32
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// First clear bit in `from..to` of `bitmap`.
+fn find_clear_bit(bitmap: &[u8], from: u32, to: u32) -> Option<u32> {
+    let mut bit = from;
+    while bit < to {
+        let byte = bitmap[(bit / 8) as usize];
+        if byte == 0xFF && bit % 8 == 0 {
+            bit += 8;
+            continue;
+        }
+        if byte & (1 << (bit % 8)) == 0 {
+            return Some(bit);
+        }
+        bit += 1;
+    }
+    None
+}
+
+/// An ext2 volume on a block device. Files and directories are addressed by
+/// inode number; the VFS layer in `inode` resolves names to numbers.
+///
+/// Free counts in the superblock are kept in memory and written by `sync`,
+/// while bitmaps, group descriptors and inodes are written as they change.
+pub struct Ext2Driver {
+    device: Arc<dyn BlockDevice>,
+    superblock: Superblock,
+    groups: Vec<GroupDescriptor>,
+    block_size: usize,
+    /// Device blocks per filesystem block.
+    device_blocks: u64,
+    inode_size: usize,
+    first_ino: u32,
+    /// Set when the volume uses features that only allow reading it.
+    read_only: bool,
+    /// Whether the volume was not marked valid when this driver found it.
+    dirty_at_mount: bool,
+    superblock_dirty: bool,
+    /// In-memory handles on each inode. An inode that loses its last link
+    /// while it has some is freed when the last one closes.
+    open: BTreeMap<u32, usize>,
+}
+
+impl Ext2Driver {
+    /// Reads the superblock and group descriptors of `device`. A device
+    /// without the ext2 magic number is `NotSupported`, so callers can try
+    /// other filesystems on it.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        let device_block_size = device.block_size();
+        if !SUPERBLOCK_SIZE.is_multiple_of(device_block_size) {
+            return Err(FsError::NotSupported);
+        }
+
+        let mut raw = vec![0u8; SUPERBLOCK_SIZE];
+        device.read_blocks((SUPERBLOCK_OFFSET / device_block_size) as u64, &mut raw)?;
+        let superblock: Superblock = disk::read(&raw, 0);
+
+        if superblock.magic != MAGIC {
+            return Err(FsError::NotSupported);
+        }
+        if superblock.log_block_size > MAX_BLOCK_SIZE.ilog2() - 10 {
+            return Err(FsError::Corrupted);
+        }
+        let block_size = 1024usize << superblock.log_block_size;
+        if !block_size.is_multiple_of(device_block_size) {
+            return Err(FsError::NotSupported);
+        }
+        let device_blocks = (block_size / device_block_size) as u64;
+
+        let bits_per_block = block_size as u32 * 8;
+        if !(1..=bits_per_block).contains(&superblock.blocks_per_group)
+            || !(1..=bits_per_block).contains(&superblock.inodes_per_group)
+            || superblock.first_data_block >= superblock.blocks_count
+            || superblock.blocks_count as u64 * device_blocks > device.block_count()
+        {
+            return Err(FsError::Corrupted);
+        }
+
+        let (inode_size, first_ino) = if superblock.rev_level == 0 {
+            (GOOD_OLD_INODE_SIZE, GOOD_OLD_FIRST_INO)
+        } else {
+            (superblock.inode_size as usize, superblock.first_ino)
+        };
+        if inode_size < GOOD_OLD_INODE_SIZE
+            || !inode_size.is_power_of_two()
+            || inode_size > block_size
+            || !(disk::ROOT_INO + 1..superblock.inodes_per_group).contains(&first_ino)
+        {
+            return Err(FsError::Corrupted);
+        }
+
+        let (incompat, ro_compat) = if superblock.rev_level == 0 {
+            (0, 0)
+        } else {
+            (superblock.feature_incompat, superblock.feature_ro_compat)
+        };
+        if incompat & !SUPPORTED_INCOMPAT != 0 {
+            return Err(FsError::NotSupported);
+        }
+
+        let group_count = (superblock.blocks_count - superblock.first_data_block)
+            .div_ceil(superblock.blocks_per_group) as usize;
+        if superblock.inodes_count as u64 > group_count as u64 * superblock.inodes_per_group as u64
+        {
+            return Err(FsError::Corrupted);
+        }
+
+        let mut driver = Self {
+            device,
+            superblock,
+            groups: Vec::new(),
+            block_size,
+            device_blocks,
+            inode_size,
+            first_ino,
+            read_only: ro_compat & !SUPPORTED_RO_COMPAT != 0,
+            dirty_at_mount: superblock.state & STATE_VALID == 0
+                || superblock.state & STATE_ERRORS != 0,
+            superblock_dirty: false,
+            open: BTreeMap::new(),
+        };
+        driver.load_groups(group_count)?;
+
+        if !driver.read_only {
+            // Cleared until a clean unmount sets it again.
+            driver.superblock.state &= !STATE_VALID;
+            driver.superblock.mnt_count = driver.superblock.mnt_count.wrapping_add(1);
+            driver.superblock.mtime = now();
+            driver.store_superblock()?;
+            if driver.dirty_at_mount {
+                driver.collect_orphans()?;
+            }
+            driver.device.flush()?;
+        }
+        Ok(driver)
+    }
+
+    fn load_groups(&mut self, count: usize) -> Result<(), FsError> {
+        let table_start = self.superblock.first_data_block + 1;
+        let table_blocks = (count * size_of::<GroupDescriptor>()).div_ceil(self.block_size);
+
+        let mut table = vec![0u8; table_blocks * self.block_size];
+        for (i, block) in table.chunks_mut(self.block_size).enumerate() {
+            self.read_block(table_start + i as u32, block)?;
+        }
+
+        let blocks_count = self.superblock.blocks_count;
+        let table_blocks_needed = self.superblock.inodes_per_group as usize * self.inode_size;
+        let table_blocks_needed = table_blocks_needed.div_ceil(self.block_size) as u32;
+        for i in 0..count {
+            let group: GroupDescriptor = disk::read(&table, i * size_of::<GroupDescriptor>());
+            if group.block_bitmap >= blocks_count
+                || group.inode_bitmap >= blocks_count
+                || group.inode_table.saturating_add(table_blocks_needed) > blocks_count
+            {
+                return Err(FsError::Corrupted);
+            }
+            self.groups.push(group);
+        }
+        Ok(())
+    }
+
+    fn store_superblock(&mut self) -> Result<(), FsError> {
+        let device_block_size = self.device.block_size();
+        let lba = (SUPERBLOCK_OFFSET / device_block_size) as u64;
+
+        let mut raw = vec![0u8; SUPERBLOCK_SIZE];
+        self.device.read_blocks(lba, &mut raw)?;
+        self.superblock.wtime = now();
+        disk::write(&mut raw, 0, &self.superblock);
+        self.device.write_blocks(lba, &raw)?;
+        self.superblock_dirty = false;
+        Ok(())
+    }
+
+    fn store_group(&self, group: usize) -> Result<(), FsError> {
+        let offset = group * size_of::<GroupDescriptor>();
+        let block = self.superblock.first_data_block + 1 + (offset / self.block_size) as u32;
+
+        let mut buf = vec![0u8; self.block_size];
+        self.read_block(block, &mut buf)?;
+        disk::write(&mut buf, offset % self.block_size, &self.groups[group]);
+        self.write_block(block, &buf)
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn free_blocks(&self) -> u32 {
+        self.superblock.free_blocks_count
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Whether the volume was in use or had recorded errors when this driver
+    /// found it.
+    pub fn was_dirty(&self) -> bool {
+        self.dirty_at_mount
+    }
+
+    /// Writes back the free counts, which are only kept in memory, and makes
+    /// everything durable.
+    pub fn sync(&mut self) -> Result<(), FsError> {
+        if self.superblock_dirty {
+            self.store_superblock()?;
+        }
+        self.device.flush()
+    }
+
+    /// Writes back everything and marks the volume valid again. The driver
+    /// must not be used afterwards.
+    pub fn unmount(&mut self) -> Result<(), FsError> {
+        // Handles that outlive the mount no longer count, so inodes
+        // unlinked while open are freed now.
+        let open = core::mem::take(&mut self.open);
+        if !self.read_only {
+            for ino in open.into_keys() {
+                let mut inode = self.read_inode(ino)?;
+                if inode.links_count == 0 {
+                    self.release_inode(ino, &mut inode)?;
+                }
+            }
+            self.device.flush()?;
+            self.superblock.state |= STATE_VALID;
+            self.store_superblock()?;
+        }
+        self.device.release(0, self.device.block_count())
+    }
+
+    fn check_writable(&self) -> Result<(), FsError> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    fn read_block(&self, block: u32, buf: &mut [u8]) -> Result<(), FsError> {
+        if block >= self.superblock.blocks_count {
+            return Err(FsError::Corrupted);
+        }
+        self.device
+            .read_blocks(block as u64 * self.device_blocks, buf)
+    }
+
+    fn write_block(&self, block: u32, buf: &[u8]) -> Result<(), FsError> {
+        if block >= self.superblock.blocks_count {
+            return Err(FsError::Corrupted);
+        }
+        self.device
+            .write_blocks(block as u64 * self.device_blocks, buf)
+    }
+
+    fn sectors_per_block(&self) -> u32 {
+        (self.block_size / 512) as u32
+    }
+
+    fn group_of(&self, ino: u32) -> usize {
+        ((ino - 1) / self.superblock.inodes_per_group) as usize
+    }
+
+    fn inode_location(&self, ino: u32) -> Result<(u32, usize), FsError> {
+        if ino == 0 || ino > self.superblock.inodes_count {
+            return Err(FsError::Corrupted);
+        }
+
+        let index = (ino - 1) % self.superblock.inodes_per_group;
+        let byte = index as usize * self.inode_size;
+        let table = self.groups[self.group_of(ino)].inode_table;
+        Ok((
+            table + (byte / self.block_size) as u32,
+            byte % self.block_size,
+        ))
+    }
+
+    pub fn read_inode(&self, ino: u32) -> Result<DiskInode, FsError> {
+        let (block, offset) = self.inode_location(ino)?;
+        let mut buf = vec![0u8; self.block_size];
+        self.read_block(block, &mut buf)?;
+        Ok(disk::read(&buf, offset))
+    }
+
+    /// Writes the revision 0 part of the inode, leaving any extended fields
+    /// as they are.
+    fn write_inode(&self, ino: u32, inode: &DiskInode) -> Result<(), FsError> {
+        let (block, offset) = self.inode_location(ino)?;
+        let mut buf = vec![0u8; self.block_size];
+        self.read_block(block, &mut buf)?;
+        disk::write(&mut buf, offset, inode);
+        self.write_block(block, &buf)
+    }
+
+    /// Writes a new inode, clearing the whole record and stamping the
+    /// creation time when the inodes are large enough to hold one.
+    fn init_inode(&self, ino: u32, inode: &DiskInode) -> Result<(), FsError> {
+        let (block, offset) = self.inode_location(ino)?;
+        let mut buf = vec![0u8; self.block_size];
+        self.read_block(block, &mut buf)?;
+
+        let record = &mut buf[offset..offset + self.inode_size];
+        record.fill(0);
+        disk::write(record, 0, inode);
+        if self.inode_size >= CRTIME_OFFSET + 4 {
+            record[EXTRA_ISIZE_OFFSET..EXTRA_ISIZE_OFFSET + 2]
+                .copy_from_slice(&NEW_EXTRA_ISIZE.to_le_bytes());
+            write_u32(record, CRTIME_OFFSET, inode.ctime);
+        }
+        self.write_block(block, &buf)
+    }
+
+    /// Creation time of the inode, or 0 where it has none.
+    pub fn creation_time(&self, ino: u32) -> Result<u32, FsError> {
+        if self.inode_size < CRTIME_OFFSET + 4 {
+            return Ok(0);
+        }
+
+        let (block, offset) = self.inode_location(ino)?;
+        let mut buf = vec![0u8; self.block_size];
+        self.read_block(block, &mut buf)?;
+
+        let record = &buf[offset..offset + self.inode_size];
+        let extra =
+            u16::from_le_bytes([record[EXTRA_ISIZE_OFFSET], record[EXTRA_ISIZE_OFFSET + 1]]);
+        if EXTRA_ISIZE_OFFSET + (extra as usize) < CRTIME_OFFSET + 4 {
+            return Ok(0);
+        }
+        Ok(read_u32(record, CRTIME_OFFSET))
+    }
+
+    /// Number of blocks in `group`; the last group may be short.
+    fn blocks_in_group(&self, group: usize) -> u32 {
+        let start =
+            self.superblock.first_data_block + group as u32 * self.superblock.blocks_per_group;
+        (self.superblock.blocks_count - start).min(self.superblock.blocks_per_group)
+    }
+
+    /// Allocates a zeroed block, preferring `goal_group`.
+    fn alloc_block(&mut self, goal_group: usize) -> Result<u32, FsError> {
+        let count = self.groups.len();
+        let mut bitmap = vec![0u8; self.block_size];
+
+        for group in (0..count).map(|i| (goal_group + i) % count) {
+            if self.groups[group].free_blocks_count == 0 {
+                continue;
+            }
+
+            self.read_block(self.groups[group].block_bitmap, &mut bitmap)?;
+            let Some(bit) = find_clear_bit(&bitmap, 0, self.blocks_in_group(group)) else {
+                continue;
+            };
+            bitmap[(bit / 8) as usize] |= 1 << (bit % 8);
+            self.write_block(self.groups[group].block_bitmap, &bitmap)?;
+
+            self.groups[group].free_blocks_count -= 1;
+            self.store_group(group)?;
+            self.superblock.free_blocks_count = self.superblock.free_blocks_count.saturating_sub(1);
+            self.superblock_dirty = true;
+
+            let block = self.superblock.first_data_block
+                + group as u32 * self.superblock.blocks_per_group
+                + bit;
+            self.write_block(block, &vec![0u8; self.block_size])?;
+            return Ok(block);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    fn free_block(&mut self, block: u32) -> Result<(), FsError> {
+        if block < self.superblock.first_data_block || block >= self.superblock.blocks_count {
+            return Err(FsError::Corrupted);
+        }
+
+        let relative = block - self.superblock.first_data_block;
+        let group = (relative / self.superblock.blocks_per_group) as usize;
+        let bit = relative % self.superblock.blocks_per_group;
+
+        let mut bitmap = vec![0u8; self.block_size];
+        self.read_block(self.groups[group].block_bitmap, &mut bitmap)?;
+        let mask = 1 << (bit % 8);
+        if bitmap[(bit / 8) as usize] & mask == 0 {
+            return Err(FsError::Corrupted);
+        }
+        bitmap[(bit / 8) as usize] &= !mask;
+        self.write_block(self.groups[group].block_bitmap, &bitmap)?;
+
+        self.groups[group].free_blocks_count += 1;
+        self.store_group(group)?;
+        self.superblock.free_blocks_count += 1;
+        self.superblock_dirty = true;
+        Ok(())
+    }
+
+    /// Allocates an inode number, preferring `goal_group`. The inode itself
+    /// is left for the caller to initialise.
+    fn alloc_inode(&mut self, goal_group: usize, is_dir: bool) -> Result<u32, FsError> {
+        let count = self.groups.len();
+        let per_group = self.superblock.inodes_per_group;
+        let mut bitmap = vec![0u8; self.block_size];
+
+        for group in (0..count).map(|i| (goal_group + i) % count) {
+            if self.groups[group].free_inodes_count == 0 {
+                continue;
+            }
+
+            // Inodes below `first_ino` are reserved, bitmap or not.
+            let from = if group == 0 { self.first_ino - 1 } else { 0 };
+            let to = per_group.min(self.superblock.inodes_count - group as u32 * per_group);
+            self.read_block(self.groups[group].inode_bitmap, &mut bitmap)?;
+            let Some(bit) = find_clear_bit(&bitmap, from, to) else {
+                continue;
+            };
+            bitmap[(bit / 8) as usize] |= 1 << (bit % 8);
+            self.write_block(self.groups[group].inode_bitmap, &bitmap)?;
+
+            self.groups[group].free_inodes_count -= 1;
+            if is_dir {
+                self.groups[group].used_dirs_count += 1;
+            }
+            self.store_group(group)?;
+            self.superblock.free_inodes_count = self.superblock.free_inodes_count.saturating_sub(1);
+            self.superblock_dirty = true;
+
+            return Ok(group as u32 * per_group + bit + 1);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    fn free_inode(&mut self, ino: u32, is_dir: bool) -> Result<(), FsError> {
+        self.inode_location(ino)?;
+        let group = self.group_of(ino);
+        let bit = (ino - 1) % self.superblock.inodes_per_group;
+
+        let mut bitmap = vec![0u8; self.block_size];
+        self.read_block(self.groups[group].inode_bitmap, &mut bitmap)?;
+        let mask = 1 << (bit % 8);
+        if bitmap[(bit / 8) as usize] & mask == 0 {
+            return Err(FsError::Corrupted);
+        }
+        bitmap[(bit / 8) as usize] &= !mask;
+        self.write_block(self.groups[group].inode_bitmap, &bitmap)?;
+
+        self.groups[group].free_inodes_count += 1;
+        if is_dir {
+            self.groups[group].used_dirs_count =
+                self.groups[group].used_dirs_count.saturating_sub(1);
+        }
+        self.store_group(group)?;
+        self.superblock.free_inodes_count += 1;
+        self.superblock_dirty = true;
+        Ok(())
+    }
+
+    /// Frees the inodes that are allocated but have no links: files that
+    /// were still open when the volume was last left without unmounting.
+    fn collect_orphans(&mut self) -> Result<(), FsError> {
+        let per_group = self.superblock.inodes_per_group;
+        let mut bitmap = vec![0u8; self.block_size];
+
+        for group in 0..self.groups.len() {
+            let from = if group == 0 { self.first_ino - 1 } else { 0 };
+            let to = per_group.min(self.superblock.inodes_count - group as u32 * per_group);
+            self.read_block(self.groups[group].inode_bitmap, &mut bitmap)?;
+
+            for bit in from..to {
+                if bitmap[(bit / 8) as usize] & (1 << (bit % 8)) == 0 {
+                    continue;
+                }
+                let ino = group as u32 * per_group + bit + 1;
+                let mut inode = self.read_inode(ino)?;
+                if inode.links_count == 0 {
+                    self.release_inode(ino, &mut inode)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn pointers_per_block(&self) -> u64 {
+        (self.block_size / 4) as u64
+    }
+
+    /// Largest size a regular file may grow to: what the block map can
+    /// address, and without the large-file feature what fits in 31 bits.
+    fn max_file_size(&self) -> u64 {
+        let p = self.pointers_per_block();
+        let blocks = DIRECT_BLOCKS as u64 + p + p * p + p * p * p;
+        let addressable = (blocks * self.block_size as u64).min(u32::MAX as u64 * 512);
+        if self.superblock.feature_ro_compat & RO_COMPAT_LARGE_FILE != 0 {
+            addressable
+        } else {
+            addressable.min(i32::MAX as u64)
+        }
+    }
+
+    /// Finds the block holding data block `index` of an inode, or 0 for a
+    /// hole. With `alloc`, missing data and indirect blocks are allocated
+    /// and counted in `inode`, which the caller writes back.
+    fn map_block(
+        &mut self,
+        inode: &mut DiskInode,
+        goal_group: usize,
+        index: u64,
+        alloc: bool,
+    ) -> Result<u32, FsError> {
+        let p = self.pointers_per_block();
+        let (slot, depth, mut rest) = if index < DIRECT_BLOCKS as u64 {
+            (index as usize, 0, 0)
+        } else if index - (DIRECT_BLOCKS as u64) < p {
+            (INDIRECT, 1, index - DIRECT_BLOCKS as u64)
+        } else if index - (DIRECT_BLOCKS as u64) - p < p * p {
+            (DOUBLE_INDIRECT, 2, index - DIRECT_BLOCKS as u64 - p)
+        } else if index - (DIRECT_BLOCKS as u64) - p - p * p < p * p * p {
+            (TRIPLE_INDIRECT, 3, index - DIRECT_BLOCKS as u64 - p - p * p)
+        } else {
+            return Err(FsError::FileTooLarge);
+        };
+
+        let mut block = inode.block[slot];
+        if block == 0 {
+            if !alloc {
+                return Ok(0);
+            }
+            block = self.alloc_block(goal_group)?;
+            inode.block[slot] = block;
+            inode.blocks += self.sectors_per_block();
+        }
+
+        let mut table = vec![0u8; self.block_size];
+        for level in (0..depth).rev() {
+            let span = p.pow(level);
+            let entry = (rest / span) as usize * 4;
+            rest %= span;
+
+            self.read_block(block, &mut table)?;
+            let mut next = read_u32(&table, entry);
+            if next == 0 {
+                if !alloc {
+                    return Ok(0);
+                }
+                next = self.alloc_block(goal_group)?;
+                write_u32(&mut table, entry, next);
+                self.write_block(block, &table)?;
+                inode.blocks += self.sectors_per_block();
+            }
+            block = next;
+        }
+        Ok(block)
+    }
+
+    /// Frees every data block from index `keep` on, along with indirect
+    /// blocks left empty.
+    fn free_blocks_from(&mut self, inode: &mut DiskInode, keep: u64) -> Result<(), FsError> {
+        for slot in (keep.min(DIRECT_BLOCKS as u64) as usize)..DIRECT_BLOCKS {
+            let block = inode.block[slot];
+            if block != 0 {
+                self.free_block(block)?;
+                inode.block[slot] = 0;
+                inode.blocks = inode.blocks.saturating_sub(self.sectors_per_block());
+            }
+        }
+
+        let p = self.pointers_per_block();
+        let mut base = DIRECT_BLOCKS as u64;
+        for (slot, depth) in [(INDIRECT, 1), (DOUBLE_INDIRECT, 2), (TRIPLE_INDIRECT, 3)] {
+            let span = p.pow(depth);
+            let block = inode.block[slot];
+            if block != 0
+                && keep < base + span
+                && self.free_tree(inode, block, depth, keep.saturating_sub(base))?
+            {
+                inode.block[slot] = 0;
+            }
+            base += span;
+        }
+        Ok(())
+    }
+
+    /// Frees the data blocks from index `from` on in the tree of `depth`
+    /// levels of indirection under `block`. Returns whether `block` itself
+    /// was freed.
+    fn free_tree(
+        &mut self,
+        inode: &mut DiskInode,
+        block: u32,
+        depth: u32,
+        from: u64,
+    ) -> Result<bool, FsError> {
+        if depth == 0 {
+            if from > 0 {
+                return Ok(false);
+            }
+            self.free_block(block)?;
+            inode.blocks = inode.blocks.saturating_sub(self.sectors_per_block());
+            return Ok(true);
+        }
+
+        let p = self.pointers_per_block();
+        let span = p.pow(depth - 1);
+        let mut table = vec![0u8; self.block_size];
+        self.read_block(block, &mut table)?;
+
+        let mut changed = false;
+        for i in (from / span) as usize..p as usize {
+            let child = read_u32(&table, i * 4);
+            if child == 0 {
+                continue;
+            }
+            let child_from = from.saturating_sub(i as u64 * span);
+            if self.free_tree(inode, child, depth - 1, child_from)? {
+                write_u32(&mut table, i * 4, 0);
+                changed = true;
+            }
+        }
+
+        if from == 0 {
+            self.free_block(block)?;
+            inode.blocks = inode.blocks.saturating_sub(self.sectors_per_block());
+            return Ok(true);
+        }
+        if changed {
+            self.write_block(block, &table)?;
+        }
+        Ok(false)
+    }
+
+    /// Whether `block` of the inode holds block numbers rather than a fast
+    /// symlink target or a device number.
+    fn has_block_map(&self, inode: &DiskInode) -> bool {
+        match inode.file_type() {
+            S_IFREG | S_IFDIR => true,
+            S_IFLNK => !inode.is_fast_symlink(self.block_size),
+            _ => false,
+        }
+    }
+
+    /// Frees everything an inode with no links left holds, then the inode.
+    fn release_inode(&mut self, ino: u32, inode: &mut DiskInode) -> Result<(), FsError> {
+        if self.has_block_map(inode) {
+            self.free_blocks_from(inode, 0)?;
+        }
+        if inode.file_acl != 0 {
+            self.release_xattr_block(inode.file_acl)?;
+            inode.file_acl = 0;
+        }
+
+        inode.links_count = 0;
+        inode.dtime = now();
+        self.write_inode(ino, inode)?;
+        self.free_inode(ino, inode.is_dir())
+    }
+
+    /// Frees an inode whose last link is gone, unless it is still open, in
+    /// which case the last `close_inode` does.
+    fn drop_inode(&mut self, ino: u32, inode: &mut DiskInode) -> Result<(), FsError> {
+        if !self.open.contains_key(&ino) {
+            return self.release_inode(ino, inode);
+        }
+        inode.links_count = 0;
+        inode.ctime = now();
+        self.write_inode(ino, inode)
+    }
+
+    /// Notes another handle on `ino`, which keeps it allocated after its
+    /// last link goes.
+    pub fn open_inode(&mut self, ino: u32) {
+        *self.open.entry(ino).or_insert(0) += 1;
+    }
+
+    /// Drops a handle taken with `open_inode`, freeing the inode with the
+    /// last one if it has no links left.
+    pub fn close_inode(&mut self, ino: u32) -> Result<(), FsError> {
+        let Some(count) = self.open.get_mut(&ino) else {
+            return Ok(());
+        };
+        *count -= 1;
+        if *count > 0 {
+            return Ok(());
+        }
+        self.open.remove(&ino);
+
+        if self.read_only {
+            return Ok(());
+        }
+        let mut inode = self.read_inode(ino)?;
+        if inode.links_count == 0 {
+            self.release_inode(ino, &mut inode)?;
+        }
+        Ok(())
+    }
+
+    /// Drops a reference to a shared extended attribute block, freeing it
+    /// with the last one.
+    fn release_xattr_block(&mut self, block: u32) -> Result<(), FsError> {
+        let mut buf = vec![0u8; self.block_size];
+        self.read_block(block, &mut buf)?;
+
+        let refcount = read_u32(&buf, 4);
+        if refcount > 1 {
+            write_u32(&mut buf, 4, refcount - 1);
+            self.write_block(block, &buf)
+        } else {
+            self.free_block(block)
+        }
+    }
+
+    /// Reads from the data of inode `ino`. Holes read as zeroes.
+    pub fn read_at(&mut self, ino: u32, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let mut inode = self.read_inode(ino)?;
+        if !self.has_block_map(&inode) {
+            return Err(FsError::NotSupported);
+        }
+
+        let size = inode.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let goal = self.group_of(ino);
+        let block_size = self.block_size as u64;
+        let mut scratch = vec![0u8; self.block_size];
+
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let within = (position % block_size) as usize;
+            let count = (self.block_size - within).min(len - done);
+            let dest = &mut buf[done..done + count];
+
+            match self.map_block(&mut inode, goal, position / block_size, false)? {
+                0 => dest.fill(0),
+                block if count == self.block_size => self.read_block(block, dest)?,
+                block => {
+                    self.read_block(block, &mut scratch)?;
+                    dest.copy_from_slice(&scratch[within..within + count]);
+                }
+            }
+            done += count;
+        }
+        Ok(len)
+    }
+
+    /// Writes to the data of inode `ino`, allocating blocks as needed and
+    /// growing the file past its end.
+    pub fn write_at(&mut self, ino: u32, offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        self.check_writable()?;
+        let mut inode = self.read_inode(ino)?;
+        if !self.has_block_map(&inode) {
+            return Err(FsError::NotSupported);
+        }
+
+        let end = offset
+            .checked_add(data.len() as u64)
+            .ok_or(FsError::FileTooLarge)?;
+        if inode.file_type() == S_IFREG && end > self.max_file_size() {
+            return Err(FsError::FileTooLarge);
+        }
+
+        let mut done = 0;
+        let result = self.write_blocks(ino, &mut inode, offset, data, &mut done);
+
+        // Blocks allocated before a failure belong to the inode either way.
+        let size = inode.size().max(offset + done as u64);
+        inode.set_size(size);
+        if done > 0 {
+            inode.mtime = now();
+            inode.ctime = inode.mtime;
+        }
+        self.write_inode(ino, &inode)?;
+
+        match result {
+            Err(e) if done == 0 => Err(e),
+            _ => Ok(done),
+        }
+    }
+
+    fn write_blocks(
+        &mut self,
+        ino: u32,
+        inode: &mut DiskInode,
+        offset: u64,
+        data: &[u8],
+        done: &mut usize,
+    ) -> Result<(), FsError> {
+        let goal = self.group_of(ino);
+        let block_size = self.block_size as u64;
+        let mut scratch = vec![0u8; self.block_size];
+
+        while *done < data.len() {
+            let position = offset + *done as u64;
+            let within = (position % block_size) as usize;
+            let count = (self.block_size - within).min(data.len() - *done);
+            let source = &data[*done..*done + count];
+
+            let block = self.map_block(inode, goal, position / block_size, true)?;
+            if count == self.block_size {
+                self.write_block(block, source)?;
+            } else {
+                self.read_block(block, &mut scratch)?;
+                scratch[within..within + count].copy_from_slice(source);
+                self.write_block(block, &scratch)?;
+            }
+            *done += count;
+        }
+        Ok(())
+    }
+
+    /// Sets the size of a regular file, freeing blocks past a smaller end.
+    /// Bytes past the end of the last block are zeroed, so a file that grows
+    /// again reads zeroes there.
+    pub fn truncate(&mut self, ino: u32, size: u64) -> Result<(), FsError> {
+        self.check_writable()?;
+        let mut inode = self.read_inode(ino)?;
+        if inode.is_dir() {
+            return Err(FsError::IsDirectory);
+        }
+        if inode.file_type() != S_IFREG {
+            return Err(FsError::InvalidArgument);
+        }
+        if size > self.max_file_size() {
+            return Err(FsError::FileTooLarge);
+        }
+
+        let block_size = self.block_size as u64;
+        if size < inode.size() {
+            self.free_blocks_from(&mut inode, size.div_ceil(block_size))?;
+
+            let within = (size % block_size) as usize;
+            if within != 0 {
+                let goal = self.group_of(ino);
+                let block = self.map_block(&mut inode, goal, size / block_size, false)?;
+                if block != 0 {
+                    let mut buf = vec![0u8; self.block_size];
+                    self.read_block(block, &mut buf)?;
+                    buf[within..].fill(0);
+                    self.write_block(block, &buf)?;
+                }
+            }
+        }
+
+        inode.set_size(size);
+        inode.mtime = now();
+        inode.ctime = inode.mtime;
+        self.write_inode(ino, &inode)
+    }
+
+    /// Reads the target of symlink `ino`.
+    pub fn read_link(&mut self, ino: u32) -> Result<Vec<u8>, FsError> {
+        let inode = self.read_inode(ino)?;
+        if inode.file_type() != S_IFLNK {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let size = inode.size() as usize;
+        if inode.is_fast_symlink(self.block_size) {
+            let data = inode.inline_data();
+            return data
+                .get(..size)
+                .map(|target| target.to_vec())
+                .ok_or(FsError::Corrupted);
+        }
+        if size > self.block_size {
+            return Err(FsError::Corrupted);
+        }
+
+        let mut target = vec![0u8; size];
+        let read = self.read_at(ino, 0, &mut target)?;
+        target.truncate(read);
+        Ok(target)
+    }
+
+    /// Replaces the permission bits of `ino`, keeping its type.
+    pub fn set_mode(&mut self, ino: u32, mode: u32) -> Result<(), FsError> {
+        self.check_writable()?;
+        let mut inode = self.read_inode(ino)?;
+        inode.mode = inode.file_type() | (mode & 0o7777) as u16;
+        inode.ctime = now();
+        self.write_inode(ino, &inode)
+    }
+
+    pub fn set_owner(&mut self, ino: u32, uid: u32, gid: u32) -> Result<(), FsError> {
+        self.check_writable()?;
+        let mut inode = self.read_inode(ino)?;
+        inode.set_owner(uid, gid);
+        inode.ctime = now();
+        self.write_inode(ino, &inode)
+    }
+
+    /// Allocates and writes a new inode of the given mode with one link, or
+    /// two for a directory, whose `.` and `..` entries are filled in for
+    /// `parent`. Symlinks get `link_target` as their contents. Everything
+    /// the inode refers to is written before the inode itself.
+    fn new_inode(
+        &mut self,
+        parent: u32,
+        mode: u16,
+        uid: u32,
+        gid: u32,
+        link_target: &[u8],
+    ) -> Result<u32, FsError> {
+        let is_dir = mode & disk::S_IFMT == S_IFDIR;
+        let ino = self.alloc_inode(self.group_of(parent), is_dir)?;
+
+        let time = now();
+        let mut inode = DiskInode {
+            mode,
+            links_count: if is_dir { 2 } else { 1 },
+            atime: time,
+            ctime: time,
+            mtime: time,
+            ..DiskInode::empty()
+        };
+        inode.set_owner(uid, gid);
+
+        let filled = if is_dir {
+            self.init_dir(ino, parent, &mut inode)
+        } else if mode & disk::S_IFMT == S_IFLNK {
+            self.fill_link(ino, &mut inode, link_target)
+        } else {
+            Ok(())
+        };
+
+        if let Err(e) = filled.and_then(|()| self.init_inode(ino, &inode)) {
+            self.free_blocks_from(&mut inode, 0)?;
+            self.free_inode(ino, is_dir)?;
+            return Err(e);
+        }
+        Ok(ino)
+    }
+
+    fn fill_link(&mut self, ino: u32, inode: &mut DiskInode, target: &[u8]) -> Result<(), FsError> {
+        if target.len() < disk::FAST_SYMLINK_MAX {
+            inode.set_inline_data(target);
+        } else {
+            self.write_blocks(ino, inode, 0, target, &mut 0)?;
+        }
+        inode.set_size(target.len() as u64);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disk::{FT_DIR, INCOMPAT_FILETYPE, ROOT_INO};
+    use super::*;
+    use crate::drivers::block::RamDisk;
+    use crate::fs::vfs::{FileSystem, InodeKind};
+    use alloc::string::String;
+
+    const BLOCKS: u32 = 4096;
+    const INODES: u32 = 128;
+    /// Superblock, descriptors, two bitmaps, the inode table and the root
+    /// directory, from block 1.
+    const USED_BLOCKS: u32 = 4 + INODES * 128 / 1024 + 1;
+
+    /// Formats a single-group volume of 1 KiB blocks on a RAM disk, as
+    /// `mke2fs -b 1024 -N 128 -O ^resize_inode,^dir_index` would.
+    fn format() -> Arc<RamDisk> {
+        let mut image = vec![0u8; BLOCKS as usize * 1024];
+        let superblock = Superblock {
+            inodes_count: INODES,
+            blocks_count: BLOCKS,
+            r_blocks_count: 0,
+            free_blocks_count: BLOCKS - 1 - USED_BLOCKS,
+            free_inodes_count: INODES - 10,
+            first_data_block: 1,
+            log_block_size: 0,
+            log_frag_size: 0,
+            blocks_per_group: 8192,
+            frags_per_group: 8192,
+            inodes_per_group: INODES,
+            mtime: 0,
+            wtime: 0,
+            mnt_count: 0,
+            max_mnt_count: 20,
+            magic: MAGIC,
+            state: STATE_VALID,
+            errors: 1,
+            minor_rev_level: 0,
+            lastcheck: 0,
+            checkinterval: 0,
+            creator_os: 0,
+            rev_level: 1,
+            def_resuid: 0,
+            def_resgid: 0,
+            first_ino: 11,
+            inode_size: 128,
+            block_group_nr: 0,
+            feature_compat: 0,
+            feature_incompat: INCOMPAT_FILETYPE,
+            feature_ro_compat: RO_COMPAT_LARGE_FILE,
+        };
+        disk::write(&mut image, SUPERBLOCK_OFFSET, &superblock);
+
+        let group = GroupDescriptor {
+            block_bitmap: 3,
+            inode_bitmap: 4,
+            inode_table: 5,
+            free_blocks_count: superblock.free_blocks_count as u16,
+            free_inodes_count: superblock.free_inodes_count as u16,
+            used_dirs_count: 1,
+            pad: 0,
+            reserved: [0; 12],
+        };
+        disk::write(&mut image, 2 * 1024, &group);
+
+        // Bits start at the first data block, so bit `n` is block `n + 1`.
+        // Those past the end of the group are set as padding.
+        let set_bits = |image: &mut [u8], block: usize, bits: &mut dyn Iterator<Item = u32>| {
+            for bit in bits {
+                image[block * 1024 + bit as usize / 8] |= 1 << (bit % 8);
+            }
+        };
+        set_bits(&mut image, 3, &mut (0..USED_BLOCKS).chain(BLOCKS - 1..8192));
+        set_bits(&mut image, 4, &mut (0..10).chain(INODES..8192));
+
+        let root_block = USED_BLOCKS;
+        let mut root = DiskInode {
+            mode: S_IFDIR | 0o755,
+            links_count: 2,
+            blocks: 2,
+            ..DiskInode::empty()
+        };
+        root.block[0] = root_block;
+        root.set_size(1024);
+        disk::write(&mut image, 5 * 1024 + (ROOT_INO as usize - 1) * 128, &root);
+
+        let dir = &mut image[root_block as usize * 1024..][..1024];
+        for (offset, rec_len, name) in [(0, 12u16, &b"."[..]), (12, 1012, b"..")] {
+            write_u32(dir, offset, ROOT_INO);
+            dir[offset + 4..offset + 6].copy_from_slice(&rec_len.to_le_bytes());
+            dir[offset + 6] = name.len() as u8;
+            dir[offset + 7] = FT_DIR;
+            dir[offset + 8..offset + 8 + name.len()].copy_from_slice(name);
+        }
+
+        Arc::new(RamDisk::from_vec(image, 512))
+    }
+
+    fn mount(disk: &Arc<RamDisk>) -> Ext2Driver {
+        Ext2Driver::new(disk.clone()).unwrap()
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    fn read_all(driver: &mut Ext2Driver, ino: u32) -> Vec<u8> {
+        let mut data = vec![0u8; driver.read_inode(ino).unwrap().size() as usize];
+        let count = driver.read_at(ino, 0, &mut data).unwrap();
+        data.truncate(count);
+        data
+    }
+
+    fn names(driver: &mut Ext2Driver, dir: u32) -> Vec<String> {
+        let mut names: Vec<_> = driver
+            .read_dir(dir)
+            .unwrap()
+            .into_iter()
+            .map(|record| record.name)
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let disk = format();
+        let mut driver = mount(&disk);
+        let free = driver.free_blocks();
+
+        // Past the twelve direct blocks, with a hole in front.
+        let data = pattern(20000);
+        let ino = driver
+            .create(ROOT_INO, "data.bin", S_IFREG | 0o644, 1000, 100)
+            .unwrap();
+        assert_eq!(driver.write_at(ino, 3000, &data).unwrap(), data.len());
+
+        let contents = read_all(&mut driver, ino);
+        assert_eq!(contents.len(), 23000);
+        assert!(contents[..3000].iter().all(|&b| b == 0));
+        assert_eq!(&contents[3000..], &data[..]);
+
+        driver.unmount().unwrap();
+        let mut driver = mount(&disk);
+        assert!(!driver.was_dirty());
+        assert_eq!(driver.lookup(ROOT_INO, "data.bin").unwrap(), ino);
+        assert_eq!(&read_all(&mut driver, ino)[3000..], &data[..]);
+        assert_eq!(driver.read_inode(ino).unwrap().uid(), 1000);
+
+        driver.truncate(ino, 5000).unwrap();
+        assert_eq!(read_all(&mut driver, ino)[3000..], data[..2000]);
+        driver.truncate(ino, 8000).unwrap();
+        assert!(read_all(&mut driver, ino)[5000..].iter().all(|&b| b == 0));
+
+        driver.unlink(ROOT_INO, "data.bin").unwrap();
+        assert_eq!(driver.free_blocks(), free);
+        assert!(matches!(
+            driver.lookup(ROOT_INO, "data.bin"),
+            Err(FsError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn directory_entries_split_and_grow() {
+        let mut driver = mount(&format());
+        let dir = driver
+            .create(ROOT_INO, "many", S_IFDIR | 0o755, 0, 0)
+            .unwrap();
+        assert_eq!(driver.read_inode(ROOT_INO).unwrap().links_count, 3);
+
+        // Forty entries of 32 bytes need a second block.
+        let name = |i: usize| alloc::format!("entry-with-a-long-name-{:02}", i);
+        for i in 0..40 {
+            driver.create(dir, &name(i), S_IFREG | 0o644, 0, 0).unwrap();
+        }
+        assert_eq!(driver.read_inode(dir).unwrap().size(), 2048);
+        assert_eq!(names(&mut driver, dir).len(), 40);
+
+        // Freed space goes to the entry in front and is used again.
+        for i in (0..40).step_by(2) {
+            driver.unlink(dir, &name(i)).unwrap();
+        }
+        for i in 0..20 {
+            driver
+                .create(
+                    dir,
+                    &alloc::format!("again-{:02}", i),
+                    S_IFREG | 0o644,
+                    0,
+                    0,
+                )
+                .unwrap();
+        }
+        assert_eq!(driver.read_inode(dir).unwrap().size(), 2048);
+        assert_eq!(names(&mut driver, dir).len(), 40);
+        assert!(matches!(
+            driver.rmdir(ROOT_INO, "many"),
+            Err(FsError::NotEmpty)
+        ));
+    }
+
+    #[test]
+    fn rename_and_link_keep_counts() {
+        let mut driver = mount(&format());
+        let free = driver.free_blocks();
+        let a = driver.create(ROOT_INO, "a", S_IFDIR | 0o755, 0, 0).unwrap();
+        let b = driver.create(ROOT_INO, "b", S_IFDIR | 0o755, 0, 0).unwrap();
+        let sub = driver.create(a, "sub", S_IFDIR | 0o755, 0, 0).unwrap();
+        let file = driver.create(a, "file", S_IFREG | 0o644, 0, 0).unwrap();
+        driver.write_at(file, 0, b"contents").unwrap();
+
+        driver.rename(a, "sub", b, "moved").unwrap();
+        assert_eq!(driver.lookup(sub, "..").unwrap(), b);
+        assert_eq!(driver.read_inode(a).unwrap().links_count, 2);
+        assert_eq!(driver.read_inode(b).unwrap().links_count, 3);
+
+        driver.link(b, "alias", file).unwrap();
+        assert_eq!(driver.read_inode(file).unwrap().links_count, 2);
+        assert!(matches!(
+            driver.link(b, "dir-alias", a),
+            Err(FsError::IsDirectory)
+        ));
+
+        // Replacing a name drops one link of what it named.
+        let other = driver.create(b, "other", S_IFREG | 0o644, 0, 0).unwrap();
+        driver.rename(b, "other", a, "file").unwrap();
+        assert_eq!(driver.lookup(a, "file").unwrap(), other);
+        assert_eq!(driver.read_inode(file).unwrap().links_count, 1);
+        assert_eq!(read_all(&mut driver, file), b"contents");
+        assert_eq!(names(&mut driver, b), ["alias", "moved"]);
+
+        driver.unlink(b, "alias").unwrap();
+        driver.unlink(a, "file").unwrap();
+        driver.rmdir(b, "moved").unwrap();
+        driver.rmdir(ROOT_INO, "a").unwrap();
+        driver.rmdir(ROOT_INO, "b").unwrap();
+        assert_eq!(driver.free_blocks(), free);
+        assert_eq!(driver.read_inode(ROOT_INO).unwrap().links_count, 2);
+        assert_eq!(driver.groups[0].used_dirs_count, 1);
+    }
+
+    #[test]
+    fn symlinks_fast_and_slow() {
+        let mut driver = mount(&format());
+        let short = driver.symlink(ROOT_INO, "short", "target").unwrap();
+        let long_target = "x/".repeat(100);
+        let long = driver.symlink(ROOT_INO, "long", &long_target).unwrap();
+
+        assert_eq!(driver.read_link(short).unwrap(), b"target");
+        assert_eq!(driver.read_link(long).unwrap(), long_target.as_bytes());
+        assert_eq!(driver.read_inode(short).unwrap().blocks, 0);
+        assert_eq!(driver.read_inode(long).unwrap().blocks, 2);
+    }
+
+    #[test]
+    fn unlinked_inode_lives_while_open() {
+        let disk = format();
+        let fs = Ext2Fs::new(mount(&disk));
+        let free = fs.driver().free_blocks();
+
+        let root = fs.root();
+        let file = root.create("log", InodeKind::File).unwrap();
+        file.write_at(0, &pattern(5000)).unwrap();
+        let ino = file.stat().unwrap().ino as u32;
+        root.unlink("log").unwrap();
+
+        // The data stays readable and its inode is not handed out again.
+        let mut buf = [0u8; 100];
+        assert_eq!(file.read_at(4900, &mut buf).unwrap(), 100);
+        assert_eq!(file.stat().unwrap().nlink, 0);
+        let next = root.create("next", InodeKind::File).unwrap();
+        assert_ne!(next.stat().unwrap().ino as u32, ino);
+        assert!(fs.driver().free_blocks() < free);
+
+        drop(file);
+        assert_eq!(fs.driver().free_blocks(), free);
+        assert_eq!(fs.driver().read_inode(ino).unwrap().links_count, 0);
+        assert_ne!(fs.driver().read_inode(ino).unwrap().dtime, 0);
+    }
+
+    #[test]
+    fn removed_directory_takes_no_entries() {
+        let fs = Ext2Fs::new(mount(&format()));
+        let root = fs.root();
+        let dir = root.create("gone", InodeKind::Directory).unwrap();
+        root.rmdir("gone").unwrap();
+
+        assert!(matches!(
+            dir.create("file", InodeKind::File),
+            Err(FsError::NotFound)
+        ));
+        drop(dir);
+        assert_eq!(fs.driver().groups[0].used_dirs_count, 1);
+    }
+
+    #[test]
+    fn orphans_are_collected_after_a_crash() {
+        let disk = format();
+        let mut driver = mount(&disk);
+        let free = (driver.free_blocks(), driver.superblock.free_inodes_count);
+
+        let ino = driver
+            .create(ROOT_INO, "orphan", S_IFREG | 0o644, 0, 0)
+            .unwrap();
+        driver.write_at(ino, 0, &pattern(3000)).unwrap();
+        driver.open_inode(ino);
+        driver.unlink(ROOT_INO, "orphan").unwrap();
+        driver.sync().unwrap();
+        // Dropped without unmounting, as if the machine went down.
+        drop(driver);
+
+        let driver = mount(&disk);
+        assert!(driver.was_dirty());
+        assert_eq!(
+            (driver.free_blocks(), driver.superblock.free_inodes_count),
+            free
+        );
+        assert_ne!(driver.read_inode(ino).unwrap().dtime, 0);
+    }
+
+    #[test]
+    fn unmount_frees_inodes_still_open() {
+        let disk = format();
+        let mut driver = mount(&disk);
+        let free = driver.free_blocks();
+        let ino = driver
+            .create(ROOT_INO, "open", S_IFREG | 0o644, 0, 0)
+            .unwrap();
+        driver.write_at(ino, 0, &pattern(3000)).unwrap();
+        driver.open_inode(ino);
+        driver.unlink(ROOT_INO, "open").unwrap();
+
+        driver.unmount().unwrap();
+        driver.close_inode(ino).unwrap();
+        let driver = mount(&disk);
+        assert!(!driver.was_dirty());
+        assert_eq!(driver.free_blocks(), free);
+    }
+}
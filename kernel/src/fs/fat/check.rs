@@ -0,0 +1,449 @@
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::{EntryLocation, Fat32Driver};
+use crate::fs::FsError;
+use crate::fs::vfs::path;
+
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+
+/// Bytes that may not appear in a short name.
+const INVALID_SHORT_NAME_BYTES: &[u8] = b"\"*+,./:;<=>?[\\]|";
+
+/// A problem found by `Fat32Driver::check`.
+#[derive(Debug, Clone)]
+pub enum Issue {
+    /// The entry at `path` cannot be used as it stands.
+    InvalidEntry { path: String, reason: &'static str },
+    /// The chain of `path` links from `cluster` to a free, reserved or
+    /// out-of-range cluster.
+    BrokenChain { path: String, cluster: u32 },
+    /// The chain of `path` runs into `cluster`, which an earlier chain
+    /// already holds.
+    CrossLinked { path: String, cluster: u32 },
+    /// The size of `path` does not match the length of its chain.
+    SizeMismatch {
+        path: String,
+        size: u32,
+        clusters: u32,
+    },
+    /// A `.` or `..` entry in the directory `path` names the wrong cluster.
+    BadDotEntry { path: String, name: &'static str },
+    /// Allocated clusters that no entry reaches.
+    LostClusters { clusters: u32, chains: u32 },
+    /// A FAT copy that disagrees with the active FAT.
+    FatMismatch { copy: u32, sectors: u32 },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::InvalidEntry { path, reason } => write!(f, "{}: {}", path, reason),
+            Issue::BrokenChain { path, cluster } => {
+                write!(f, "{}: chain breaks after cluster {}", path, cluster)
+            }
+            Issue::CrossLinked { path, cluster } => {
+                write!(f, "{}: cluster {} is cross-linked", path, cluster)
+            }
+            Issue::SizeMismatch {
+                path,
+                size,
+                clusters,
+            } => write!(
+                f,
+                "{}: size {} does not match its chain of {} clusters",
+                path, size, clusters
+            ),
+            Issue::BadDotEntry { path, name } => {
+                write!(f, "{}: '{}' points at the wrong directory", path, name)
+            }
+            Issue::LostClusters { clusters, chains } => {
+                write!(f, "{} lost clusters in {} chains", clusters, chains)
+            }
+            Issue::FatMismatch { copy, sectors } => write!(
+                f,
+                "FAT copy {} differs from the active FAT in {} sectors",
+                copy, sectors
+            ),
+        }
+    }
+}
+
+/// One bit per cluster, set once some entry's chain has reached it.
+struct ClaimMap(Vec<u64>);
+
+impl ClaimMap {
+    /// Marks `cluster` as claimed. Returns `false` if it already was.
+    fn claim(&mut self, cluster: u32) -> bool {
+        let (word, bit) = (cluster as usize / 64, 1 << (cluster % 64));
+        let fresh = self.0[word] & bit == 0;
+        self.0[word] |= bit;
+        fresh
+    }
+
+    fn is_claimed(&self, cluster: u32) -> bool {
+        self.0[cluster as usize / 64] & (1 << (cluster % 64)) != 0
+    }
+}
+
+struct Checker<'a> {
+    driver: &'a mut Fat32Driver,
+    repair: bool,
+    claimed: ClaimMap,
+    issues: Vec<Issue>,
+    /// Set when a damaged directory was left unscanned, after which
+    /// unreached clusters cannot be called lost.
+    incomplete: bool,
+}
+
+impl Fat32Driver {
+    /// Walks every directory and cluster chain on the volume and reports
+    /// what is inconsistent. With `repair`, each problem is fixed as it is
+    /// found: broken chains are cut short, sizes follow the surviving chain,
+    /// unusable entries are dropped, lost clusters are freed and FAT copies
+    /// are rewritten from the active one.
+    pub fn check(&mut self, repair: bool) -> Result<Vec<Issue>, FsError> {
+        let fat_mismatches = self.compare_fats(false)?;
+
+        let mut checker = Checker {
+            repair,
+            claimed: ClaimMap(vec![0; (self.cluster_end as usize).div_ceil(64)]),
+            issues: Vec::new(),
+            incomplete: false,
+            driver: self,
+        };
+        checker.check_tree()?;
+        for first in checker.driver.open_orphans() {
+            checker.walk_chain("(removed while open)", first)?;
+        }
+        if !checker.incomplete {
+            checker.check_lost()?;
+        }
+        let mut issues = checker.issues;
+
+        for (copy, sectors) in fat_mismatches {
+            issues.push(Issue::FatMismatch { copy, sectors });
+        }
+        if repair && !issues.is_empty() {
+            self.compare_fats(true)?;
+            self.sync()?;
+        }
+        Ok(issues)
+    }
+
+    /// Compares each mirrored FAT copy with the active FAT, sector by
+    /// sector, and returns the copies that differ with their count of
+    /// differing sectors. With `rewrite`, those sectors are overwritten.
+    fn compare_fats(&mut self, rewrite: bool) -> Result<Vec<(u32, u32)>, FsError> {
+        // Copies are expected to drift apart when mirroring is off.
+        if self.active_fat.is_some() {
+            return Ok(Vec::new());
+        }
+
+        let mut mismatches = Vec::new();
+        for copy in 1..self.fat_count {
+            let mut sectors = 0;
+            for sector in 0..self.fat_size {
+                let mut active = [0u8; 512];
+                let mut mirror = [0u8; 512];
+                self.read_sector_into_u8(self.fat_start_sector + sector, &mut active)?;
+                let lba = self.fat_start_sector + copy * self.fat_size + sector;
+                self.read_sector_into_u8(lba, &mut mirror)?;
+
+                if active != mirror {
+                    sectors += 1;
+                    if rewrite {
+                        self.write_sector_from_u8(lba, &active)?;
+                    }
+                }
+            }
+            if sectors > 0 {
+                mismatches.push((copy, sectors));
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+impl Checker<'_> {
+    fn check_tree(&mut self) -> Result<(), FsError> {
+        let root = self.driver.root_cluster;
+        if root != 0 && !self.walk_dir_chain("/", root)? {
+            return Ok(());
+        }
+
+        // Directories still to scan, with the cluster their `..` should name.
+        let mut pending = vec![(String::from("/"), root, None)];
+        while let Some((dir_path, cluster, parent)) = pending.pop() {
+            for location in self.entries(cluster)? {
+                let entry = location.entry;
+                if entry.is_volume_label() {
+                    continue;
+                }
+
+                if entry.is_dot() {
+                    self.check_dot(&dir_path, cluster, parent, location)?;
+                    continue;
+                }
+
+                let entry_path = path::join(&dir_path, &location.name());
+                if let Some(subdir) = self.check_entry(&entry_path, location)? {
+                    pending.push((entry_path, subdir, Some(cluster)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn entries(&mut self, cluster: u32) -> Result<Vec<EntryLocation>, FsError> {
+        let mut entries = Vec::new();
+        self.driver.scan_dir(cluster, |location| {
+            entries.push(location);
+            true
+        })?;
+        Ok(entries)
+    }
+
+    /// Walks a directory's chain. Returns whether the directory can be
+    /// scanned, which a damaged chain only allows once it is repaired: left
+    /// alone, it could lead into another chain or around a loop.
+    fn walk_dir_chain(&mut self, dir_path: &str, first: u32) -> Result<bool, FsError> {
+        let issues = self.issues.len();
+        let clusters = self.walk_chain(dir_path, first)?;
+
+        let damaged = self.issues.len() > issues;
+        if damaged && !self.repair {
+            self.incomplete = true;
+        }
+        Ok(clusters > 0 && (self.repair || !damaged))
+    }
+
+    /// Checks one entry and its chain. Returns the first cluster of a
+    /// directory that should be scanned in turn.
+    fn check_entry(
+        &mut self,
+        entry_path: &str,
+        location: EntryLocation,
+    ) -> Result<Option<u32>, FsError> {
+        let mut entry = location.entry;
+        let first = entry.get_cluster();
+        let is_dir = entry.is_directory();
+
+        let invalid = if !is_valid_short_name(&entry.name, &entry.ext) {
+            Some("invalid short name")
+        } else if first != 0 && !(2..self.driver.cluster_end).contains(&first) {
+            Some("start cluster out of range")
+        } else if is_dir && first == 0 {
+            Some("directory without a cluster")
+        } else {
+            None
+        };
+        if let Some(reason) = invalid {
+            self.issues.push(Issue::InvalidEntry {
+                path: String::from(entry_path),
+                reason,
+            });
+            // Whatever chain the entry had is picked up as lost.
+            if self.repair {
+                self.driver.delete_entry(&location)?;
+            }
+            return Ok(None);
+        }
+
+        if is_dir {
+            if !self.walk_dir_chain(entry_path, first)? {
+                // Only a directory cross-linked from its first cluster is
+                // beyond repair.
+                if self.repair {
+                    self.driver.delete_entry(&location)?;
+                }
+                return Ok(None);
+            }
+            if entry.size != 0 {
+                self.issues.push(Issue::InvalidEntry {
+                    path: String::from(entry_path),
+                    reason: "directory has a size",
+                });
+                if self.repair {
+                    entry.size = 0;
+                    self.driver.write_entry(&location, &entry)?;
+                }
+            }
+            return Ok(Some(first));
+        }
+
+        let clusters = if first == 0 {
+            0
+        } else {
+            self.walk_chain(entry_path, first)?
+        };
+
+        let cluster_size = self.driver.sectors_per_cluster * 512;
+        let needed = entry.size.div_ceil(cluster_size);
+        if clusters == needed {
+            return Ok(None);
+        }
+
+        self.issues.push(Issue::SizeMismatch {
+            path: String::from(entry_path),
+            size: entry.size,
+            clusters,
+        });
+        if self.repair {
+            if clusters < needed {
+                entry.size = clusters * cluster_size;
+            } else {
+                self.cut_chain(first, needed)?;
+            }
+            if clusters == 0 || needed == 0 {
+                entry.set_cluster(0);
+            }
+            self.driver.write_entry(&location, &entry)?;
+        }
+        Ok(None)
+    }
+
+    /// Checks a subdirectory's `.` and `..` entries, which name the directory
+    /// itself and its parent, with the root written as cluster 0.
+    fn check_dot(
+        &mut self,
+        dir_path: &str,
+        cluster: u32,
+        parent: Option<u32>,
+        location: EntryLocation,
+    ) -> Result<(), FsError> {
+        let Some(parent) = parent else {
+            return Ok(());
+        };
+
+        let (name, expected) = if location.entry.name[1] == b'.' {
+            let parent = if parent == self.driver.root_cluster {
+                0
+            } else {
+                parent
+            };
+            ("..", parent)
+        } else {
+            (".", cluster)
+        };
+        if location.entry.get_cluster() == expected {
+            return Ok(());
+        }
+
+        self.issues.push(Issue::BadDotEntry {
+            path: String::from(dir_path),
+            name,
+        });
+        if self.repair {
+            let mut entry = location.entry;
+            entry.set_cluster(expected);
+            self.driver.write_entry(&location, &entry)?;
+        }
+        Ok(())
+    }
+
+    /// Follows the chain from `first`, claiming each cluster, and returns its
+    /// length up to the first problem. With repair, the chain is ended there.
+    fn walk_chain(&mut self, owner: &str, first: u32) -> Result<u32, FsError> {
+        let mut previous = None;
+        let mut cluster = first;
+        let mut length = 0;
+
+        loop {
+            if !self.claimed.claim(cluster) {
+                self.issues.push(Issue::CrossLinked {
+                    path: String::from(owner),
+                    cluster,
+                });
+                if let Some(previous) = previous.filter(|_| self.repair) {
+                    self.driver.set_fat_entry(previous, END_OF_CHAIN)?;
+                }
+                return Ok(length);
+            }
+            length += 1;
+
+            let next = self.driver.get_fat_entry(cluster)?;
+            if next >= self.driver.fat_type.end_of_chain() {
+                return Ok(length);
+            }
+            if !(2..self.driver.cluster_end).contains(&next) {
+                self.issues.push(Issue::BrokenChain {
+                    path: String::from(owner),
+                    cluster,
+                });
+                if self.repair {
+                    self.driver.set_fat_entry(cluster, END_OF_CHAIN)?;
+                }
+                return Ok(length);
+            }
+
+            previous = Some(cluster);
+            cluster = next;
+        }
+    }
+
+    /// Shortens the chain from `first` to `keep` clusters, freeing the rest.
+    fn cut_chain(&mut self, first: u32, keep: u32) -> Result<(), FsError> {
+        if keep == 0 {
+            return self.driver.free_chain(first);
+        }
+
+        let mut last = first;
+        for _ in 1..keep {
+            last = self.driver.next_cluster(last)?.ok_or(FsError::Corrupted)?;
+        }
+        if let Some(tail) = self.driver.next_cluster(last)? {
+            self.driver.set_fat_entry(last, END_OF_CHAIN)?;
+            self.driver.free_chain(tail)?;
+        }
+        Ok(())
+    }
+
+    /// Finds allocated clusters that no chain reached. Clusters marked bad
+    /// are allocated on purpose and left alone.
+    fn check_lost(&mut self) -> Result<(), FsError> {
+        let bad = self.driver.fat_type.bad_cluster();
+        let mut lost = BTreeSet::new();
+        let mut links = Vec::new();
+
+        for cluster in 2..self.driver.cluster_end {
+            if !self.driver.free_map.is_used(cluster) || self.claimed.is_claimed(cluster) {
+                continue;
+            }
+            let next = self.driver.get_fat_entry(cluster)?;
+            if next != bad {
+                lost.insert(cluster);
+                links.push(next);
+            }
+        }
+        if lost.is_empty() {
+            return Ok(());
+        }
+
+        // A chain starts at each lost cluster no other lost cluster links to.
+        let linked: BTreeSet<u32> = links.into_iter().filter(|c| lost.contains(c)).collect();
+        let chains = (lost.len() - linked.len()).max(1);
+        self.issues.push(Issue::LostClusters {
+            clusters: lost.len() as u32,
+            chains: chains as u32,
+        });
+
+        if self.repair {
+            for &cluster in &lost {
+                self.driver.set_fat_entry(cluster, 0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_valid_short_name(name: &[u8; 8], ext: &[u8; 3]) -> bool {
+    // 0x05 in the first byte stands for a leading 0xE5.
+    name[0] != b' '
+        && name.iter().chain(ext.iter()).enumerate().all(|(i, &byte)| {
+            (byte >= 0x20 || (i == 0 && byte == 0x05)) && !INVALID_SHORT_NAME_BYTES.contains(&byte)
+        })
+}
@@ -0,0 +1,146 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// One bit per cluster, set while the cluster is in use. Built from the FAT at
+/// mount and kept in step by every FAT update, so allocation never has to scan
+/// the table on disk.
+pub struct FreeMap {
+    words: Vec<u64>,
+    end: u32,
+    free: u32,
+    next: u32,
+}
+
+impl FreeMap {
+    /// Creates a map for clusters `2..end`, all initially in use.
+    pub fn new(end: u32) -> Self {
+        Self {
+            words: vec![u64::MAX; (end as usize).div_ceil(64)],
+            end,
+            free: 0,
+            next: 2,
+        }
+    }
+
+    pub fn free_count(&self) -> u32 {
+        self.free
+    }
+
+    pub fn next_hint(&self) -> u32 {
+        self.next
+    }
+
+    pub fn set_next_hint(&mut self, cluster: u32) {
+        if (2..self.end).contains(&cluster) {
+            self.next = cluster;
+        }
+    }
+
+    pub fn is_used(&self, cluster: u32) -> bool {
+        let index = cluster as usize;
+        self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// Records the state of `cluster`. Returns whether it changed.
+    pub fn set_used(&mut self, cluster: u32, used: bool) -> bool {
+        if !(2..self.end).contains(&cluster) || self.is_used(cluster) == used {
+            return false;
+        }
+
+        let index = cluster as usize;
+        if used {
+            self.words[index / 64] |= 1 << (index % 64);
+            self.free -= 1;
+        } else {
+            self.words[index / 64] &= !(1 << (index % 64));
+            self.free += 1;
+        }
+        true
+    }
+
+    /// Finds a free cluster, searching from the next-free hint and wrapping
+    /// around once.
+    pub fn find_free(&mut self) -> Option<u32> {
+        if self.free == 0 {
+            return None;
+        }
+
+        let found = self
+            .search(self.next, self.end)
+            .or_else(|| self.search(2, self.next))?;
+        self.next = if found + 1 < self.end { found + 1 } else { 2 };
+        Some(found)
+    }
+
+    fn search(&self, start: u32, end: u32) -> Option<u32> {
+        let mut cluster = start;
+
+        while cluster < end {
+            let index = cluster as usize;
+            let word = self.words[index / 64] | ((1u64 << (index % 64)) - 1);
+            if word == u64::MAX {
+                cluster = ((index / 64 + 1) * 64) as u32;
+                continue;
+            }
+
+            let found = ((index / 64) * 64) as u32 + (!word).trailing_zeros();
+            return (found < end).then_some(found);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_with_free(end: u32, free: &[u32]) -> FreeMap {
+        let mut map = FreeMap::new(end);
+        for &cluster in free {
+            assert!(map.set_used(cluster, false));
+        }
+        map
+    }
+
+    #[test]
+    fn tracks_free_count() {
+        let mut map = map_with_free(200, &[5, 70, 199]);
+        assert_eq!(map.free_count(), 3);
+        assert!(!map.is_used(70));
+
+        assert!(!map.set_used(70, false));
+        assert!(map.set_used(70, true));
+        assert_eq!(map.free_count(), 2);
+
+        // Reserved clusters and those past the end are never tracked.
+        assert!(!map.set_used(1, false));
+        assert!(!map.set_used(200, false));
+        assert_eq!(map.free_count(), 2);
+    }
+
+    #[test]
+    fn finds_free_from_hint_and_wraps() {
+        let mut map = map_with_free(200, &[5, 70, 199]);
+        map.set_next_hint(60);
+        assert_eq!(map.find_free(), Some(70));
+        assert_eq!(map.next_hint(), 71);
+
+        map.set_used(70, true);
+        assert_eq!(map.find_free(), Some(199));
+        assert_eq!(map.next_hint(), 2);
+
+        map.set_used(199, true);
+        assert_eq!(map.find_free(), Some(5));
+        map.set_used(5, true);
+        assert_eq!(map.find_free(), None);
+    }
+
+    #[test]
+    fn ignores_hint_outside_the_volume() {
+        let mut map = map_with_free(100, &[3]);
+        map.set_next_hint(500);
+        assert_eq!(map.next_hint(), 2);
+        map.set_next_hint(0);
+        assert_eq!(map.find_free(), Some(3));
+    }
+}
@@ -0,0 +1,311 @@
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::{Mutex, MutexGuard};
+
+use super::{ChainCursor, Fat32Driver};
+use crate::drivers::rtc::DateTime;
+use crate::fs::FsError;
+use crate::fs::file::{FileStat, S_IFDIR, S_IFREG};
+use crate::fs::vfs::{DirEntry, File, FileSystem, Inode, InodeKind, path};
+
+pub struct FatFs {
+    driver: Mutex<Fat32Driver>,
+    this: Weak<FatFs>,
+}
+
+impl FatFs {
+    pub fn new(driver: Fat32Driver) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            driver: Mutex::new(driver),
+            this: this.clone(),
+        })
+    }
+
+    pub fn driver(&self) -> MutexGuard<'_, Fat32Driver> {
+        self.driver.lock()
+    }
+
+    fn inode(&self, path: String, kind: InodeKind) -> Arc<dyn Inode> {
+        Arc::new(FatInode {
+            fs: self.this.upgrade().expect("FatFs dropped while in use"),
+            path,
+            kind,
+            cursor: Mutex::new(ChainCursor::default()),
+        })
+    }
+}
+
+impl FileSystem for FatFs {
+    fn name(&self) -> &'static str {
+        self.driver().fat_type().name()
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.inode(String::from("/"), InodeKind::Directory)
+    }
+
+    /// Names match case-insensitively, so one file has many paths.
+    fn caches_lookups(&self) -> bool {
+        false
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        self.driver().sync()
+    }
+
+    fn unmount(&self) -> Result<(), FsError> {
+        self.driver().unmount()
+    }
+
+    fn check(&self, repair: bool) -> Result<Vec<String>, FsError> {
+        let issues = self.driver().check(repair)?;
+        Ok(issues.iter().map(|issue| issue.to_string()).collect())
+    }
+
+    fn rename(&self, old_path: &str, new_path: &str) -> Result<(), FsError> {
+        let mut driver = self.driver();
+        let entry = driver.lookup(old_path)?;
+
+        let existing = match driver.lookup(new_path) {
+            Ok(existing) => Some(existing),
+            Err(FsError::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+        if let Some(existing) = existing {
+            match (entry.is_directory(), existing.is_directory()) {
+                (true, false) => return Err(FsError::NotDirectory),
+                (false, true) => return Err(FsError::IsDirectory),
+                (true, true) if !old_path.eq_ignore_ascii_case(new_path) => {
+                    return Err(FsError::AlreadyExists);
+                }
+                _ => {}
+            }
+        }
+
+        driver.rename(old_path, new_path)
+    }
+}
+
+/// A file or directory named by its path within the volume. The FAT driver
+/// works on paths, so every operation goes back to the directory entry;
+/// only open files hold on to the entry itself.
+struct FatInode {
+    fs: Arc<FatFs>,
+    path: String,
+    kind: InodeKind,
+    cursor: Mutex<ChainCursor>,
+}
+
+impl Inode for FatInode {
+    fn kind(&self) -> InodeKind {
+        self.kind
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        let mut driver = self.fs.driver();
+        let blksize = (driver.sectors_per_cluster * 512) as u64;
+
+        // The root directory has no entry of its own to hold times.
+        if self.path == "/" {
+            return Ok(FileStat {
+                mode: S_IFDIR | 0o755,
+                nlink: 1,
+                blksize,
+                ..FileStat::default()
+            });
+        }
+
+        let entry = driver.lookup(&self.path)?;
+        let unix = |time: Option<DateTime>| time.map_or(0, |time| time.to_unix());
+        let (atime, mtime, btime) = (
+            unix(entry.accessed()),
+            unix(entry.modified()),
+            unix(entry.created()),
+        );
+
+        if self.kind == InodeKind::Directory {
+            return Ok(FileStat {
+                mode: S_IFDIR | 0o755,
+                nlink: 1,
+                blksize,
+                atime,
+                mtime,
+                btime,
+                ..FileStat::default()
+            });
+        }
+
+        let size = entry.size as u64;
+        Ok(FileStat {
+            mode: S_IFREG | 0o644,
+            nlink: 1,
+            size,
+            blksize,
+            blocks: size.div_ceil(512),
+            atime,
+            mtime,
+            btime,
+            ..FileStat::default()
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        if self.kind != InodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+
+        let path = path::join(&self.path, name);
+        let entry = self.fs.driver().lookup(&path)?;
+
+        let kind = if entry.is_directory() {
+            InodeKind::Directory
+        } else {
+            InodeKind::File
+        };
+        Ok(self.fs.inode(path, kind))
+    }
+
+    fn create(&self, name: &str, kind: InodeKind) -> Result<Arc<dyn Inode>, FsError> {
+        if self.kind != InodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+
+        let path = path::join(&self.path, name);
+        let mut driver = self.fs.driver();
+        match kind {
+            InodeKind::Directory => driver.mkdir(&path)?,
+            InodeKind::File => driver.create_file(&path, &[])?,
+            _ => return Err(FsError::NotSupported),
+        }
+        drop(driver);
+
+        Ok(self.fs.inode(path, kind))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        if self.kind != InodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+
+        let path = path::join(&self.path, name);
+        self.fs.driver().remove(&path)
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), FsError> {
+        if self.kind != InodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+
+        let path = path::join(&self.path, name);
+        self.fs.driver().rmdir(&path)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        if self.kind != InodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+
+        let entries = self.fs.driver().read_dir(&self.path)?;
+
+        Ok(entries
+            .into_iter()
+            .map(|entry| DirEntry {
+                kind: if entry.is_dir {
+                    InodeKind::Directory
+                } else {
+                    InodeKind::File
+                },
+                name: entry.name,
+                size: entry.size as u64,
+                id: entry.id,
+            })
+            .collect())
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if self.kind == InodeKind::Directory {
+            return Err(FsError::IsDirectory);
+        }
+
+        let mut cursor = self.cursor.lock();
+        self.fs
+            .driver()
+            .read_at(&self.path, offset, buf, &mut cursor)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        if self.kind == InodeKind::Directory {
+            return Err(FsError::IsDirectory);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut cursor = self.cursor.lock();
+        self.fs
+            .driver()
+            .write_at(&self.path, offset, buf, &mut cursor)
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        if self.kind == InodeKind::Directory {
+            return Err(FsError::IsDirectory);
+        }
+
+        self.fs.driver().truncate(&self.path, size)
+    }
+
+    fn open(&self) -> Result<Option<Arc<dyn File>>, FsError> {
+        if self.kind != InodeKind::File {
+            return Ok(None);
+        }
+
+        let handle = self.fs.driver().open(&self.path)?;
+        Ok(Some(Arc::new(FatFile {
+            fs: self.fs.clone(),
+            handle,
+            cursor: Mutex::new(ChainCursor::default()),
+        })))
+    }
+}
+
+/// An open regular file. It holds its directory entry rather than its
+/// path, so it follows the file through renames and outlives its removal,
+/// and it remembers where in the cluster chain the last access ended, so
+/// reading or writing on from there never walks the chain again.
+struct FatFile {
+    fs: Arc<FatFs>,
+    handle: u32,
+    cursor: Mutex<ChainCursor>,
+}
+
+impl File for FatFile {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let mut cursor = self.cursor.lock();
+        self.fs
+            .driver()
+            .read_open(self.handle, offset, buf, &mut cursor)
+    }
+
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut cursor = self.cursor.lock();
+        self.fs
+            .driver()
+            .write_open(self.handle, offset, buf, &mut cursor)
+    }
+
+    fn size(&self) -> Result<u64, FsError> {
+        self.fs.driver().open_size(self.handle)
+    }
+}
+
+impl Drop for FatFile {
+    fn drop(&mut self) {
+        let _ = self.fs.driver().close(self.handle);
+    }
+}
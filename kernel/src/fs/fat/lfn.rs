@@ -0,0 +1,262 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const MAX_NAME_UNITS: usize = 255;
+
+const UNITS_PER_ENTRY: usize = 13;
+const UNIT_OFFSETS: [usize; UNITS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const LAST_ENTRY: u8 = 0x40;
+
+/// Checksum of an 11-byte short name, stored in each of its LFN entries.
+pub fn checksum(name: &[u8; 8], ext: &[u8; 3]) -> u8 {
+    name.iter().chain(ext.iter()).fold(0u8, |sum, &b| {
+        (sum >> 1).wrapping_add(sum << 7).wrapping_add(b)
+    })
+}
+
+fn is_short_char(b: u8) -> bool {
+    b.is_ascii_uppercase() || b.is_ascii_digit() || b"$%'-_@~`!(){}^#&".contains(&b)
+}
+
+pub fn is_valid_long_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= MAX_NAME_UNITS
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+}
+
+fn pack(base: &[u8], ext: &[u8]) -> ([u8; 8], [u8; 3]) {
+    let mut name = [0x20u8; 8];
+    let mut extension = [0x20u8; 3];
+    name[..base.len()].copy_from_slice(base);
+    extension[..ext.len()].copy_from_slice(ext);
+    (name, extension)
+}
+
+/// Returns the 8.3 form of `name` if it already is a valid upper-case short
+/// name and needs no long name entries.
+pub fn exact_short_name(name: &str) -> Option<([u8; 8], [u8; 3])> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    let valid = |part: &str, max: usize| part.len() <= max && part.bytes().all(is_short_char);
+
+    if base.is_empty() || !valid(base, 8) || !valid(ext, 3) {
+        return None;
+    }
+    Some(pack(base.as_bytes(), ext.as_bytes()))
+}
+
+fn basis_part(part: &str, lossy: &mut bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    for c in part.chars() {
+        if c == ' ' || c == '.' {
+            *lossy = true;
+            continue;
+        }
+
+        let upper = c.to_ascii_uppercase();
+        if upper.is_ascii() && is_short_char(upper as u8) {
+            out.push(upper as u8);
+        } else {
+            out.push(b'_');
+            *lossy = true;
+        }
+    }
+    out
+}
+
+/// Candidate short aliases for a long name: the plain upper-case conversion
+/// when it loses nothing but case, then `BASE~N.EXT` for increasing `N`.
+pub fn short_aliases(name: &str) -> impl Iterator<Item = ([u8; 8], [u8; 3])> {
+    let (base, ext) = match name.rfind('.') {
+        Some(index) if index > 0 => (&name[..index], &name[index + 1..]),
+        _ => (name, ""),
+    };
+
+    let mut lossy = false;
+    let base = basis_part(base, &mut lossy);
+    let mut ext = basis_part(ext, &mut lossy);
+    if base.len() > 8 || ext.len() > 3 || base.is_empty() {
+        lossy = true;
+    }
+    ext.truncate(3);
+
+    let exact = (!lossy).then(|| pack(&base, &ext));
+    let numbered = (1u32..1_000_000).map(move |n| {
+        let tail = alloc::format!("~{}", n);
+        let keep = (8 - tail.len()).min(base.len());
+        let mut alias = Vec::from(&base[..keep]);
+        alias.extend_from_slice(tail.as_bytes());
+        pack(&alias, &ext)
+    });
+
+    exact.into_iter().chain(numbered)
+}
+
+/// Builds the LFN entries for `name` in on-disk order, highest sequence
+/// number first.
+pub fn encode(name: &str, checksum: u8) -> Vec<[u8; 32]> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(UNITS_PER_ENTRY);
+
+    (1..=count)
+        .rev()
+        .map(|seq| {
+            let mut raw = [0u8; 32];
+            raw[0] = seq as u8 | if seq == count { LAST_ENTRY } else { 0 };
+            raw[11] = 0x0F;
+            raw[13] = checksum;
+
+            for (i, &offset) in UNIT_OFFSETS.iter().enumerate() {
+                let index = (seq - 1) * UNITS_PER_ENTRY + i;
+                let unit = match index.cmp(&units.len()) {
+                    core::cmp::Ordering::Less => units[index],
+                    core::cmp::Ordering::Equal => 0x0000,
+                    core::cmp::Ordering::Greater => 0xFFFF,
+                };
+                raw[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            raw
+        })
+        .collect()
+}
+
+/// Long name fragments collected ahead of the short entry they belong to.
+#[derive(Default)]
+pub struct LfnBuilder {
+    parts: Vec<[u16; UNITS_PER_ENTRY]>,
+    slots: Vec<(u32, usize)>,
+    checksum: u8,
+    next_seq: u8,
+}
+
+impl LfnBuilder {
+    pub fn reset(&mut self) {
+        self.parts.clear();
+        self.slots.clear();
+        self.next_seq = 0;
+    }
+
+    /// Adds the LFN entry `raw` found at `slot`. Out-of-sequence entries
+    /// discard whatever was collected so far.
+    pub fn push(&mut self, raw: &[u8], slot: (u32, usize)) {
+        let seq = raw[0] & 0x1F;
+
+        if raw[0] & LAST_ENTRY != 0 {
+            self.reset();
+            self.checksum = raw[13];
+        } else if self.parts.is_empty() || seq != self.next_seq || raw[13] != self.checksum {
+            self.reset();
+            return;
+        }
+        if seq == 0 {
+            self.reset();
+            return;
+        }
+
+        let mut units = [0u16; UNITS_PER_ENTRY];
+        for (unit, &offset) in units.iter_mut().zip(UNIT_OFFSETS.iter()) {
+            *unit = u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        }
+
+        self.parts.push(units);
+        self.slots.push(slot);
+        self.next_seq = seq - 1;
+    }
+
+    /// Completes the sequence with its short entry. Returns the decoded long
+    /// name and the slots it occupied, or nothing if the sequence is partial
+    /// or belongs to a different short name.
+    pub fn finish(&mut self, checksum: u8) -> Option<(String, Vec<(u32, usize)>)> {
+        let complete = !self.parts.is_empty() && self.next_seq == 0 && self.checksum == checksum;
+        if !complete {
+            self.reset();
+            return None;
+        }
+
+        let units: Vec<u16> = self
+            .parts
+            .iter()
+            .rev()
+            .flatten()
+            .copied()
+            .take_while(|&unit| unit != 0x0000)
+            .filter(|&unit| unit != 0xFFFF)
+            .collect();
+        let name = char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+
+        let slots = core::mem::take(&mut self.slots);
+        self.reset();
+        Some((name, slots))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_of_short_name() {
+        assert_eq!(checksum(b"README  ", b"TXT"), 0x73);
+        assert_eq!(checksum(b"LONGFI~1", b"TXT"), 0xD4);
+    }
+
+    #[test]
+    fn long_name_round_trip() {
+        let name = "A rather long file name, über 13 units.txt";
+        let entries = encode(name, 0xD4);
+        assert_eq!(entries.len(), name.encode_utf16().count().div_ceil(13));
+        assert_eq!(entries[0][0], entries.len() as u8 | LAST_ENTRY);
+
+        let mut builder = LfnBuilder::default();
+        for (i, raw) in entries.iter().enumerate() {
+            builder.push(raw, (7, i * 32));
+        }
+        let (decoded, slots) = builder.finish(0xD4).unwrap();
+        assert_eq!(decoded, name);
+        assert_eq!(slots.len(), entries.len());
+        assert_eq!(slots[0], (7, 0));
+    }
+
+    #[test]
+    fn mismatched_or_partial_sequences_are_dropped() {
+        let entries = encode("a long enough name.txt", 0x11);
+        assert_eq!(entries.len(), 2);
+
+        let mut builder = LfnBuilder::default();
+        for raw in &entries {
+            builder.push(raw, (0, 0));
+        }
+        assert!(builder.finish(0x12).is_none());
+
+        builder.push(&entries[0], (0, 0));
+        assert!(builder.finish(0x11).is_none());
+
+        builder.push(&entries[1], (0, 0));
+        assert!(builder.finish(0x11).is_none());
+    }
+
+    #[test]
+    fn short_names_and_aliases() {
+        assert_eq!(
+            exact_short_name("README.TXT"),
+            Some((*b"README  ", *b"TXT"))
+        );
+        assert_eq!(exact_short_name("readme.txt"), None);
+        assert_eq!(exact_short_name("TOOLONGNAME"), None);
+
+        let mut aliases = short_aliases("readme.txt");
+        assert_eq!(aliases.next(), Some((*b"README  ", *b"TXT")));
+        assert_eq!(aliases.next(), Some((*b"README~1", *b"TXT")));
+
+        let mut aliases = short_aliases("long file name.text");
+        assert_eq!(aliases.next(), Some((*b"LONGFI~1", *b"TEX")));
+        assert_eq!(aliases.next(), Some((*b"LONGFI~2", *b"TEX")));
+
+        assert!(is_valid_long_name("with spaces.tar.gz"));
+        assert!(!is_valid_long_name(".."));
+        assert!(!is_valid_long_name("a*b"));
+    }
+}
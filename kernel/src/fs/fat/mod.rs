@@ -0,0 +1,2044 @@
+mod check;
+mod free_map;
+mod inode;
+mod lfn;
+mod table;
+mod time;
+
+pub use check::Issue;
+pub use inode::FatFs;
+pub use table::FatType;
+
+use self::free_map::FreeMap;
+use self::lfn::LfnBuilder;
+use crate::drivers::block::BlockDevice;
+use crate::drivers::rtc::{self, DateTime};
+use crate::fs::FsError;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+struct Bpb {
+    jmp: [u8; 3],
+    oem: [u8; 8],
+    bytes_per_sector: u16,
+    sectors_per_cluster: u8,
+    reserved_sectors: u16,
+    fats: u8,
+    root_entries: u16,
+    total_sectors_16: u16,
+    media: u8,
+    sectors_per_fat_16: u16,
+    sectors_per_track: u16,
+    heads: u16,
+    hidden_sectors: u32,
+    total_sectors_32: u32,
+    sectors_per_fat_32: u32,
+    ext_flags: u16,
+    fs_version: u16,
+    root_cluster: u32,
+    fs_info: u16,
+    backup_boot_sector: u16,
+    reserved: [u8; 12],
+    drive_number: u8,
+    reserved1: u8,
+    boot_signature: u8,
+    vol_id: u32,
+    vol_label: [u8; 11],
+    fs_type: [u8; 8],
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct DirectoryEntry {
+    pub name: [u8; 8],
+    pub ext: [u8; 3],
+    pub attributes: u8,
+    pub reserved: u8,
+    pub ctime_tenth: u8,
+    pub ctime: u16,
+    pub cdate: u16,
+    pub adate: u16,
+    pub cluster_high: u16,
+    pub time: u16,
+    pub date: u16,
+    pub cluster_low: u16,
+    pub size: u32,
+}
+
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+
+impl DirectoryEntry {
+    /// An entry with a blank name, created now.
+    fn new(attributes: u8, cluster: u32, size: u32) -> Self {
+        let (date, clock, hundredths) = time::encode(&rtc::now());
+        Self {
+            name: [b' '; 8],
+            ext: [b' '; 3],
+            attributes,
+            reserved: 0,
+            ctime_tenth: hundredths,
+            ctime: clock,
+            cdate: date,
+            adate: date,
+            cluster_high: ((cluster >> 16) & 0xFFFF) as u16,
+            time: clock,
+            date,
+            cluster_low: (cluster & 0xFFFF) as u16,
+            size,
+        }
+    }
+
+    pub fn is_free(&self) -> bool {
+        self.name[0] == 0xE5
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.name[0] == 0x00
+    }
+
+    pub fn is_long_name(&self) -> bool {
+        self.attributes == 0x0F
+    }
+
+    pub fn is_volume_label(&self) -> bool {
+        self.attributes & 0x08 != 0
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// The `.` and `..` entries at the start of every subdirectory.
+    pub fn is_dot(&self) -> bool {
+        self.name[0] == b'.'
+    }
+
+    pub fn checksum(&self) -> u8 {
+        lfn::checksum(&self.name, &self.ext)
+    }
+
+    pub fn get_cluster(&self) -> u32 {
+        ((self.cluster_high as u32) << 16) | (self.cluster_low as u32)
+    }
+
+    fn set_cluster(&mut self, cluster: u32) {
+        self.cluster_high = ((cluster >> 16) & 0xFFFF) as u16;
+        self.cluster_low = (cluster & 0xFFFF) as u16;
+    }
+
+    pub fn created(&self) -> Option<DateTime> {
+        time::decode(self.cdate, self.ctime, self.ctime_tenth)
+    }
+
+    pub fn modified(&self) -> Option<DateTime> {
+        time::decode(self.date, self.time, 0)
+    }
+
+    /// FAT keeps only the date of the last access.
+    pub fn accessed(&self) -> Option<DateTime> {
+        time::decode(self.adate, 0, 0)
+    }
+
+    /// Records a change to the contents, which is also an access.
+    fn touch_modified(&mut self) {
+        let (date, clock, _) = time::encode(&rtc::now());
+        self.date = date;
+        self.time = clock;
+        self.adate = date;
+    }
+
+    /// Records a read. Returns whether the access date moved, so that the
+    /// entry only needs writing back once a day.
+    fn touch_accessed(&mut self) -> bool {
+        let (date, _, _) = time::encode(&rtc::now());
+        let changed = self.adate != date;
+        self.adate = date;
+        changed
+    }
+
+    pub fn get_filename(&self) -> String {
+        let mut name = String::new();
+        for &c in &self.name {
+            if c != 0x20 {
+                name.push(c as char);
+            }
+        }
+
+        let mut ext = String::new();
+        for &c in &self.ext {
+            if c != 0x20 {
+                ext.push(c as char);
+            }
+        }
+
+        if !ext.is_empty() {
+            name.push('.');
+            name.push_str(&ext);
+        }
+        name
+    }
+}
+
+struct EntryLocation {
+    lba: u32,
+    offset: usize,
+    entry: DirectoryEntry,
+    long_name: Option<String>,
+    /// Slots of the LFN entries in front of the short entry.
+    lfn_slots: Vec<(u32, usize)>,
+}
+
+impl EntryLocation {
+    /// Position of the entry on disk, stable for as long as the entry exists.
+    fn id(&self) -> u64 {
+        (self.lba as u64) * 16 + (self.offset as u64) / 32
+    }
+
+    fn name(&self) -> String {
+        self.long_name
+            .clone()
+            .unwrap_or_else(|| self.entry.get_filename())
+    }
+
+    /// Names match case-insensitively against either the long name or the
+    /// short alias.
+    fn matches(&self, name: &str) -> bool {
+        self.long_name
+            .as_deref()
+            .is_some_and(|long| long.eq_ignore_ascii_case(name))
+            || self.entry.get_filename().eq_ignore_ascii_case(name)
+    }
+}
+
+/// A position in a file's cluster chain, kept between accesses so that
+/// sequential reads and writes do not walk the chain from its start each
+/// time. A cursor is only a hint: it is ignored once it no longer matches the
+/// file or any chain has been freed since it was taken.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChainCursor {
+    epoch: u64,
+    first_cluster: u32,
+    index: u32,
+    cluster: u32,
+}
+
+/// A file opened with `Fat32Driver::open`, shared by every handle on it.
+struct OpenEntry {
+    /// Sector and byte offset of the short entry, or `None` once the file
+    /// has been removed.
+    slot: Option<(u32, usize)>,
+    /// The entry as last seen, which is all that is left of a removed file.
+    entry: DirectoryEntry,
+    refs: usize,
+}
+
+pub struct DirEntryInfo {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u32,
+    pub id: u64,
+}
+
+/// Driver for FAT12, FAT16 and FAT32 volumes. The fixed root directory of
+/// FAT12 and FAT16 is addressed as cluster 0, which is also how `..` entries
+/// refer to the root on every variant.
+pub struct Fat32Driver {
+    device: Arc<dyn BlockDevice>,
+    pub fat_start_sector: u32,
+    pub data_start_sector: u32,
+    pub sectors_per_cluster: u32,
+    pub root_cluster: u32,
+    fat_type: FatType,
+    root_dir_sector: u32,
+    root_dir_sectors: u32,
+    fat_size: u32,
+    fat_count: u32,
+    /// One past the last cluster the FAT can describe.
+    cluster_end: u32,
+    /// The only FAT in use when mirroring is disabled in `ext_flags`.
+    active_fat: Option<u32>,
+    fs_info_sector: Option<u32>,
+    fs_info_dirty: bool,
+    /// Whether the clean bit in FAT[1] was already clear at mount time.
+    dirty_at_mount: bool,
+    free_map: FreeMap,
+    /// Bumped whenever clusters are freed, which invalidates every
+    /// `ChainCursor`.
+    chain_epoch: u64,
+    open_files: BTreeMap<u32, OpenEntry>,
+    next_handle: u32,
+}
+
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_FREE_COUNT: usize = 488;
+const FSINFO_NEXT_FREE: usize = 492;
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+impl Fat32Driver {
+    fn read_sector_into_u8(&mut self, lba: u32, buffer: &mut [u8; 512]) -> Result<(), FsError> {
+        self.device.read_blocks(lba as u64, buffer)
+    }
+
+    /// Reads the boot sector of `device` and checks that it describes a sane
+    /// FAT volume before touching anything else on it.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        if device.block_size() != 512 {
+            return Err(FsError::NotSupported);
+        }
+
+        let mut buf = [0u8; 512];
+        device.read_blocks(0, &mut buf)?;
+
+        if buf[510..512] != [0x55, 0xAA] {
+            return Err(FsError::Corrupted);
+        }
+
+        let bpb = unsafe { *(buf.as_ptr() as *const Bpb) };
+
+        if bpb.bytes_per_sector != 512 {
+            return Err(FsError::NotSupported);
+        }
+        if !bpb.sectors_per_cluster.is_power_of_two() || bpb.reserved_sectors == 0 || bpb.fats == 0
+        {
+            return Err(FsError::Corrupted);
+        }
+
+        let fat_size = match bpb.sectors_per_fat_16 {
+            0 => bpb.sectors_per_fat_32,
+            sectors => sectors as u32,
+        };
+        if fat_size == 0 {
+            return Err(FsError::Corrupted);
+        }
+
+        let fat_count = bpb.fats as u32;
+        let fat_start_sector = bpb.reserved_sectors as u32;
+        let root_dir_sectors = (bpb.root_entries as u32 * 32).div_ceil(512);
+        let root_dir_sector = fat_count
+            .checked_mul(fat_size)
+            .and_then(|size| size.checked_add(fat_start_sector))
+            .ok_or(FsError::Corrupted)?;
+        let data_start_sector = root_dir_sector + root_dir_sectors;
+        let sectors_per_cluster = bpb.sectors_per_cluster as u32;
+
+        let total_sectors = if bpb.total_sectors_16 != 0 {
+            bpb.total_sectors_16 as u32
+        } else {
+            bpb.total_sectors_32
+        };
+        if total_sectors <= data_start_sector || total_sectors as u64 > device.block_count() {
+            return Err(FsError::Corrupted);
+        }
+
+        let cluster_count = (total_sectors - data_start_sector) / sectors_per_cluster;
+        let fat_type = FatType::from_cluster_count(cluster_count);
+        let cluster_end = (cluster_count + 2).min(fat_type.entries_in(fat_size));
+
+        // Only FAT32 has a root directory chain, FSInfo and FAT mirroring
+        // flags; FAT12 and FAT16 need a fixed root directory instead.
+        let (root_cluster, active_fat, fs_info_sector) = if fat_type == FatType::Fat32 {
+            let root_cluster = bpb.root_cluster;
+            if bpb.root_entries != 0 || !(2..cluster_end).contains(&root_cluster) {
+                return Err(FsError::Corrupted);
+            }
+
+            let ext_flags = bpb.ext_flags;
+            let active_fat = (ext_flags & 0x80 != 0).then_some((ext_flags & 0x0F) as u32);
+            if active_fat.is_some_and(|active| active >= fat_count) {
+                return Err(FsError::Corrupted);
+            }
+            let fs_info_sector = match bpb.fs_info as u32 {
+                0 | 0xFFFF => None,
+                sector if sector >= fat_start_sector => None,
+                sector => Some(sector),
+            };
+            (root_cluster, active_fat, fs_info_sector)
+        } else {
+            if bpb.root_entries == 0 {
+                return Err(FsError::Corrupted);
+            }
+            (0, None, None)
+        };
+
+        let mut driver = Self {
+            device,
+            fat_start_sector,
+            data_start_sector,
+            sectors_per_cluster,
+            root_cluster,
+            fat_type,
+            root_dir_sector,
+            root_dir_sectors,
+            fat_size,
+            fat_count,
+            cluster_end,
+            active_fat,
+            fs_info_sector,
+            fs_info_dirty: false,
+            dirty_at_mount: false,
+            free_map: FreeMap::new(cluster_end),
+            chain_epoch: 0,
+            open_files: BTreeMap::new(),
+            next_handle: 0,
+        };
+        driver.load_free_map()?;
+        driver.load_fs_info()?;
+        driver.dirty_at_mount = !driver.is_clean()?;
+        driver.set_clean(false)?;
+        Ok(driver)
+    }
+
+    /// Whether the volume was in use when this driver found it, because it
+    /// was never unmounted or its last unmount did not finish.
+    pub fn was_dirty(&self) -> bool {
+        self.dirty_at_mount
+    }
+
+    fn is_clean(&mut self) -> Result<bool, FsError> {
+        match self.fat_type.clean_bit() {
+            Some(bit) => Ok(self.read_fat_slot(1)? & bit != 0),
+            None => Ok(true),
+        }
+    }
+
+    /// Sets or clears the clean-shutdown bit in FAT[1], durably, so that the
+    /// flag is never clear on disk while other metadata is still pending.
+    fn set_clean(&mut self, clean: bool) -> Result<(), FsError> {
+        let Some(bit) = self.fat_type.clean_bit() else {
+            return Ok(());
+        };
+        let value = self.read_fat_slot(1)?;
+        let value = if clean { value | bit } else { value & !bit };
+        self.write_fat_slot(1, value)?;
+        self.device.flush()
+    }
+
+    /// Writes back everything and marks the volume clean. The driver must
+    /// not be used afterwards.
+    pub fn unmount(&mut self) -> Result<(), FsError> {
+        self.sync()?;
+        self.set_clean(true)?;
+        self.device.release(0, self.device.block_count())
+    }
+
+    fn load_free_map(&mut self) -> Result<(), FsError> {
+        let fat_start = self.active_fat_start();
+        let fat_type = self.fat_type;
+        let mut buf = [[0u8; 512]; 2];
+        let mut loaded = None;
+
+        for cluster in 2..self.cluster_end {
+            let offset = fat_type.entry_offset(cluster);
+            if loaded != Some(offset / 512) {
+                let both = fat_type == FatType::Fat12;
+                self.read_fat_sectors(fat_start + offset / 512, both, &mut buf)?;
+                loaded = Some(offset / 512);
+            }
+
+            let entry = fat_type.decode(buf.as_flattened(), (offset % 512) as usize, cluster);
+            if entry == 0 {
+                self.free_map.set_used(cluster, false);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the next-free hint from FSInfo and corrects its free count if
+    /// it disagrees with the FAT.
+    fn load_fs_info(&mut self) -> Result<(), FsError> {
+        let Some(sector) = self.fs_info_sector else {
+            return Ok(());
+        };
+
+        let mut buf = [0u8; 512];
+        self.read_sector_into_u8(sector, &mut buf)?;
+        if read_u32(&buf, 0) != FSINFO_LEAD_SIGNATURE
+            || read_u32(&buf, 484) != FSINFO_STRUCT_SIGNATURE
+        {
+            self.fs_info_sector = None;
+            return Ok(());
+        }
+
+        self.free_map
+            .set_next_hint(read_u32(&buf, FSINFO_NEXT_FREE));
+        if read_u32(&buf, FSINFO_FREE_COUNT) != self.free_map.free_count() {
+            self.fs_info_dirty = true;
+            self.store_fs_info()?;
+        }
+        Ok(())
+    }
+
+    fn store_fs_info(&mut self) -> Result<(), FsError> {
+        let Some(sector) = self.fs_info_sector.filter(|_| self.fs_info_dirty) else {
+            return Ok(());
+        };
+
+        let mut buf = [0u8; 512];
+        self.read_sector_into_u8(sector, &mut buf)?;
+        buf[FSINFO_FREE_COUNT..FSINFO_FREE_COUNT + 4]
+            .copy_from_slice(&self.free_map.free_count().to_le_bytes());
+        buf[FSINFO_NEXT_FREE..FSINFO_NEXT_FREE + 4]
+            .copy_from_slice(&self.free_map.next_hint().to_le_bytes());
+        self.write_sector_from_u8(sector, &buf)?;
+        self.fs_info_dirty = false;
+        Ok(())
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    pub fn free_clusters(&self) -> u32 {
+        self.free_map.free_count()
+    }
+
+    /// Writes back allocation state that is only kept in memory. FSInfo is
+    /// only a hint, so it goes last, once everything it summarises is on
+    /// disk.
+    pub fn sync(&mut self) -> Result<(), FsError> {
+        self.barrier()?;
+        self.store_fs_info()?;
+        self.device.flush()
+    }
+
+    /// Makes every earlier write durable before any later one is issued.
+    ///
+    /// Updates are ordered around barriers so that nothing on disk ever
+    /// refers to what is not there yet: file data, then the FAT chain, then
+    /// the directory entry pointing at it, and the reverse when freeing. A
+    /// crash at any point leaves at worst lost clusters for `check` to
+    /// reclaim.
+    fn barrier(&mut self) -> Result<(), FsError> {
+        self.device.flush()
+    }
+
+    fn cluster_to_lba(&self, cluster: u32) -> u32 {
+        self.data_start_sector + ((cluster - 2) * self.sectors_per_cluster)
+    }
+
+    /// The cluster after `current_cluster`, or `None` at the end of the
+    /// chain. Links to free, reserved or out-of-range clusters are reported
+    /// as corruption rather than followed.
+    fn next_cluster(&mut self, current_cluster: u32) -> Result<Option<u32>, FsError> {
+        let val = self.get_fat_entry(current_cluster)?;
+        if val >= self.fat_type.end_of_chain() {
+            Ok(None)
+        } else if (2..self.cluster_end).contains(&val) {
+            Ok(Some(val))
+        } else {
+            Err(FsError::Corrupted)
+        }
+    }
+
+    pub fn list_root(&mut self) -> Result<Vec<String>, FsError> {
+        self.list_dir("/")
+    }
+
+    /// Names in the directory at `path`.
+    pub fn list_dir(&mut self, path: &str) -> Result<Vec<String>, FsError> {
+        let entries = self.read_dir(path)?;
+        Ok(entries.into_iter().map(|entry| entry.name).collect())
+    }
+
+    /// Reads the whole file at `path` into memory. Large files should be
+    /// read piecewise with `read_at` instead.
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, FsError> {
+        let mut data = vec![0u8; self.lookup(path)?.size as usize];
+        let count = self.read_at(path, 0, &mut data, &mut ChainCursor::default())?;
+        data.truncate(count);
+        Ok(data)
+    }
+
+    /// Reads from byte `offset` of the file at `path` into `buf`, returning
+    /// how many bytes were read, which is 0 at or past the end of the file.
+    /// Reads move the file's access date forward.
+    pub fn read_at(
+        &mut self,
+        path: &str,
+        offset: u64,
+        buf: &mut [u8],
+        cursor: &mut ChainCursor,
+    ) -> Result<usize, FsError> {
+        let location = self.find_entry(path)?;
+        let mut entry = location.entry;
+        let len = self.read_data(&entry, offset, buf, cursor)?;
+
+        if len > 0 && entry.touch_accessed() {
+            self.write_entry(&location, &entry)?;
+        }
+        Ok(len)
+    }
+
+    /// Reads from byte `offset` of the file `entry` describes.
+    fn read_data(
+        &mut self,
+        entry: &DirectoryEntry,
+        offset: u64,
+        buf: &mut [u8],
+        cursor: &mut ChainCursor,
+    ) -> Result<usize, FsError> {
+        if entry.is_directory() {
+            return Err(FsError::IsDirectory);
+        }
+
+        let size = entry.size as u64;
+        if offset >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let first_cluster = entry.get_cluster();
+        if first_cluster < 2 {
+            return Err(FsError::Corrupted);
+        }
+
+        let cluster_size = (self.sectors_per_cluster * 512) as u64;
+        let len = buf.len().min((size - offset) as usize);
+        let mut done = 0;
+
+        while done < len {
+            let position = offset + done as u64;
+            let index = (position / cluster_size) as u32;
+            let cluster = self.seek_cluster(cursor, first_cluster, index, false)?;
+
+            let in_cluster = position % cluster_size;
+            let lba = self.cluster_to_lba(cluster) + (in_cluster / 512) as u32;
+            let count = ((cluster_size - in_cluster) as usize).min(len - done);
+            self.read_bytes(
+                lba,
+                (in_cluster % 512) as usize,
+                &mut buf[done..done + count],
+            )?;
+            done += count;
+        }
+        Ok(len)
+    }
+
+    /// Fills `buf` from byte `offset` of sector `lba` onwards. Whole sectors
+    /// are read straight into `buf`.
+    fn read_bytes(
+        &mut self,
+        mut lba: u32,
+        mut offset: usize,
+        buf: &mut [u8],
+    ) -> Result<(), FsError> {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let rest = buf.len() - done;
+            if offset == 0 && rest >= 512 {
+                let whole = rest / 512 * 512;
+                self.device
+                    .read_blocks(lba as u64, &mut buf[done..done + whole])?;
+                lba += (whole / 512) as u32;
+                done += whole;
+            } else {
+                let count = (512 - offset).min(rest);
+                let mut sector = [0u8; 512];
+                self.read_sector_into_u8(lba, &mut sector)?;
+                buf[done..done + count].copy_from_slice(&sector[offset..offset + count]);
+                lba += 1;
+                offset = 0;
+                done += count;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns cluster `index` of the chain starting at `first_cluster`,
+    /// walking on from `cursor` when it is still valid and not past `index`.
+    /// With `extend`, zeroed clusters are appended to a chain that is too
+    /// short; otherwise that is corruption.
+    fn seek_cluster(
+        &mut self,
+        cursor: &mut ChainCursor,
+        first_cluster: u32,
+        index: u32,
+        extend: bool,
+    ) -> Result<u32, FsError> {
+        let (mut at, mut cluster) = if cursor.epoch == self.chain_epoch
+            && cursor.first_cluster == first_cluster
+            && cursor.index <= index
+        {
+            (cursor.index, cursor.cluster)
+        } else {
+            (0, first_cluster)
+        };
+
+        while at < index {
+            cluster = if extend {
+                self.next_or_extend(cluster)?
+            } else {
+                self.next_cluster(cluster)?.ok_or(FsError::Corrupted)?
+            };
+            at += 1;
+        }
+
+        *cursor = ChainCursor {
+            epoch: self.chain_epoch,
+            first_cluster,
+            index,
+            cluster,
+        };
+        Ok(cluster)
+    }
+
+    fn write_sector_from_u8(&mut self, lba: u32, buffer: &[u8; 512]) -> Result<(), FsError> {
+        self.device.write_blocks(lba as u64, buffer)
+    }
+
+    fn find_free_cluster(&mut self) -> Option<u32> {
+        self.free_map.find_free()
+    }
+
+    fn active_fat_start(&self) -> u32 {
+        self.fat_start_sector + self.active_fat.unwrap_or(0) * self.fat_size
+    }
+
+    /// Reads the FAT sector at `lba`, and the one after it when `both` is
+    /// set for an entry that crosses the boundary.
+    fn read_fat_sectors(
+        &mut self,
+        lba: u32,
+        both: bool,
+        buf: &mut [[u8; 512]; 2],
+    ) -> Result<(), FsError> {
+        self.read_sector_into_u8(lba, &mut buf[0])?;
+        if both {
+            self.read_sector_into_u8(lba + 1, &mut buf[1])?;
+        }
+        Ok(())
+    }
+
+    fn write_fat_sectors(
+        &mut self,
+        lba: u32,
+        both: bool,
+        buf: &[[u8; 512]; 2],
+    ) -> Result<(), FsError> {
+        self.write_sector_from_u8(lba, &buf[0])?;
+        if both {
+            self.write_sector_from_u8(lba + 1, &buf[1])?;
+        }
+        Ok(())
+    }
+
+    fn get_fat_entry(&mut self, cluster: u32) -> Result<u32, FsError> {
+        if !(2..self.cluster_end).contains(&cluster) {
+            return Err(FsError::Corrupted);
+        }
+        self.read_fat_slot(cluster)
+    }
+
+    /// Updates the entry in every FAT copy, or only in the active one when
+    /// mirroring is disabled.
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), FsError> {
+        if !(2..self.cluster_end).contains(&cluster) {
+            return Err(FsError::Corrupted);
+        }
+        self.write_fat_slot(cluster, value)?;
+
+        if self.free_map.set_used(cluster, value & 0x0FFF_FFFF != 0) {
+            self.fs_info_dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Reads the FAT slot for `cluster` without checking that it names a
+    /// data cluster, so the reserved slots 0 and 1 can be reached.
+    fn read_fat_slot(&mut self, cluster: u32) -> Result<u32, FsError> {
+        let fat_offset = self.fat_type.entry_offset(cluster);
+        let fat_sector = self.active_fat_start() + (fat_offset / 512);
+        let ent_offset = (fat_offset % 512) as usize;
+
+        let mut buf = [[0u8; 512]; 2];
+        let both = self.fat_type.straddles(ent_offset);
+        self.read_fat_sectors(fat_sector, both, &mut buf)?;
+
+        Ok(self
+            .fat_type
+            .decode(buf.as_flattened(), ent_offset, cluster))
+    }
+
+    fn write_fat_slot(&mut self, cluster: u32, value: u32) -> Result<(), FsError> {
+        let fat_offset = self.fat_type.entry_offset(cluster);
+        let ent_offset = (fat_offset % 512) as usize;
+        let both = self.fat_type.straddles(ent_offset);
+        let copies = match self.active_fat {
+            Some(active) => active..active + 1,
+            None => 0..self.fat_count,
+        };
+
+        for copy in copies {
+            let fat_sector = self.fat_start_sector + copy * self.fat_size + (fat_offset / 512);
+
+            let mut buf = [[0u8; 512]; 2];
+            self.read_fat_sectors(fat_sector, both, &mut buf)?;
+            self.fat_type
+                .encode(buf.as_flattened_mut(), ent_offset, cluster, value);
+            self.write_fat_sectors(fat_sector, both, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `data` to a fresh cluster chain and returns its first cluster,
+    /// or 0 for empty data. The clusters are filled before the FAT links
+    /// them, and both are durable on return.
+    fn write_chain(&mut self, data: &[u8]) -> Result<u32, FsError> {
+        let cluster_size = (self.sectors_per_cluster * 512) as usize;
+        let clusters = self.reserve_clusters(data.len().div_ceil(cluster_size))?;
+        if clusters.is_empty() {
+            return Ok(0);
+        }
+
+        let filled = clusters
+            .iter()
+            .zip(data.chunks(cluster_size))
+            .try_for_each(|(&cluster, chunk)| self.fill_cluster(cluster, chunk));
+        if let Err(e) = filled.and_then(|()| self.barrier()) {
+            self.release_clusters(&clusters);
+            return Err(e);
+        }
+
+        for pair in clusters.windows(2) {
+            self.set_fat_entry(pair[0], pair[1])?;
+        }
+        self.set_fat_entry(clusters[clusters.len() - 1], END_OF_CHAIN)?;
+        self.barrier()?;
+        Ok(clusters[0])
+    }
+
+    /// Writes `data` to the start of `cluster`, zeroing the rest of it.
+    fn fill_cluster(&mut self, cluster: u32, data: &[u8]) -> Result<(), FsError> {
+        let start_lba = self.cluster_to_lba(cluster);
+
+        for i in 0..self.sectors_per_cluster {
+            let start = (i * 512) as usize;
+            let mut sector = [0u8; 512];
+            if start < data.len() {
+                let end = (start + 512).min(data.len());
+                sector[..end - start].copy_from_slice(&data[start..end]);
+            }
+            self.write_sector_from_u8(start_lba + i, &sector)?;
+        }
+        Ok(())
+    }
+
+    /// Takes `count` free clusters out of the free map without touching the
+    /// FAT, so they can be filled before any chain refers to them.
+    fn reserve_clusters(&mut self, count: usize) -> Result<Vec<u32>, FsError> {
+        let mut clusters = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let Some(cluster) = self.find_free_cluster() else {
+                self.release_clusters(&clusters);
+                return Err(FsError::NoSpace);
+            };
+            self.free_map.set_used(cluster, true);
+            clusters.push(cluster);
+        }
+        self.fs_info_dirty |= count > 0;
+        Ok(clusters)
+    }
+
+    /// Returns reserved clusters that never made it into the FAT.
+    fn release_clusters(&mut self, clusters: &[u32]) {
+        for &cluster in clusters {
+            self.free_map.set_used(cluster, false);
+        }
+    }
+
+    fn free_chain(&mut self, start_cluster: u32) -> Result<(), FsError> {
+        self.chain_epoch += 1;
+        let mut current_cluster = Some(start_cluster);
+
+        while let Some(cluster) = current_cluster {
+            if !(2..self.cluster_end).contains(&cluster) {
+                break;
+            }
+            current_cluster = self.next_cluster(cluster)?;
+            self.set_fat_entry(cluster, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Creates a file holding `data`. Empty files get no cluster chain until
+    /// their first write.
+    pub fn create_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        if self.file_exists(path)? {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let (parent, name) = split_path(path);
+        let dir_cluster = self.resolve_dir(parent)?;
+
+        let start_cluster = self.write_chain(data)?;
+        let entry = DirectoryEntry::new(ATTR_ARCHIVE, start_cluster, data.len() as u32);
+        if let Err(e) = self.add_directory_entry(dir_cluster, name, entry) {
+            if start_cluster >= 2 {
+                self.free_chain(start_cluster)?;
+            }
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Creates an empty directory holding only its `.` and `..` entries.
+    pub fn mkdir(&mut self, path: &str) -> Result<(), FsError> {
+        if self.file_exists(path)? {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let (parent, name) = split_path(path);
+        let parent_cluster = self.resolve_dir(parent)?;
+
+        let [cluster] = self.reserve_clusters(1)?[..] else {
+            unreachable!()
+        };
+        if let Err(e) = self.init_dir_cluster(cluster, parent_cluster) {
+            self.release_clusters(&[cluster]);
+            return Err(e);
+        }
+        self.set_fat_entry(cluster, END_OF_CHAIN)?;
+        self.barrier()?;
+
+        let entry = DirectoryEntry::new(ATTR_DIRECTORY, cluster, 0);
+        if let Err(e) = self.add_directory_entry(parent_cluster, name, entry) {
+            self.free_chain(cluster)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Writes an empty directory holding only `.` and `..` to `cluster`, and
+    /// makes it durable before anything links to it.
+    fn init_dir_cluster(&mut self, cluster: u32, parent_cluster: u32) -> Result<(), FsError> {
+        self.zero_cluster(cluster)?;
+
+        // `..` refers to the root directory as cluster 0.
+        let dotdot_cluster = if parent_cluster == self.root_cluster {
+            0
+        } else {
+            parent_cluster
+        };
+        let lba = self.cluster_to_lba(cluster);
+        self.write_entry_at(
+            lba,
+            0,
+            &DirectoryEntry {
+                name: *b".       ",
+                ..DirectoryEntry::new(ATTR_DIRECTORY, cluster, 0)
+            },
+        )?;
+        self.write_entry_at(
+            lba,
+            32,
+            &DirectoryEntry {
+                name: *b"..      ",
+                ..DirectoryEntry::new(ATTR_DIRECTORY, dotdot_cluster, 0)
+            },
+        )?;
+        self.barrier()
+    }
+
+    /// Removes the directory at `path`, which must be empty.
+    pub fn rmdir(&mut self, path: &str) -> Result<(), FsError> {
+        let (_, name) = split_path(path);
+        if name == "." || name == ".." {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let location = self.find_entry(path)?;
+        if !location.entry.is_directory() {
+            return Err(FsError::NotDirectory);
+        }
+
+        let cluster = location.entry.get_cluster();
+        if !self.is_dir_empty(cluster)? {
+            return Err(FsError::NotEmpty);
+        }
+
+        self.delete_entry(&location)?;
+        self.barrier()?;
+        self.free_chain(cluster)
+    }
+
+    /// Replaces the contents of `filename`, creating it if it does not exist.
+    /// The new chain is written before the old one is released.
+    pub fn write_file(&mut self, filename: &str, data: &[u8]) -> Result<(), FsError> {
+        let location = match self.find_entry(filename) {
+            Err(FsError::NotFound) => return self.create_file(filename, data),
+            location => location?,
+        };
+
+        if location.entry.is_directory() {
+            return Err(FsError::IsDirectory);
+        }
+
+        let old_cluster = location.entry.get_cluster();
+        let new_cluster = self.write_chain(data)?;
+
+        let mut entry = location.entry;
+        entry.set_cluster(new_cluster);
+        entry.size = data.len() as u32;
+        entry.touch_modified();
+        self.write_entry(&location, &entry)?;
+
+        if old_cluster >= 2 {
+            self.barrier()?;
+            self.free_chain(old_cluster)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `data` at byte `offset` of the file at `path`, overwriting the
+    /// existing clusters in place and growing the chain past its end. A gap
+    /// between the old end and `offset` reads back as zeroes.
+    pub fn write_at(
+        &mut self,
+        path: &str,
+        offset: u64,
+        data: &[u8],
+        cursor: &mut ChainCursor,
+    ) -> Result<usize, FsError> {
+        let location = self.find_entry(path)?;
+        let mut entry = location.entry;
+        let written = self.write_data(&mut entry, offset, data, cursor)?;
+
+        if written > 0 {
+            self.write_entry(&location, &entry)?;
+        }
+        Ok(written)
+    }
+
+    /// Writes `data` at byte `offset` of the file `entry` describes and
+    /// updates its size, first cluster and times. Storing the entry is left
+    /// to the caller; whatever it newly refers to is durable by then.
+    fn write_data(
+        &mut self,
+        entry: &mut DirectoryEntry,
+        offset: u64,
+        data: &[u8],
+        cursor: &mut ChainCursor,
+    ) -> Result<usize, FsError> {
+        if entry.is_directory() {
+            return Err(FsError::IsDirectory);
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let end = offset + data.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err(FsError::FileTooLarge);
+        }
+
+        let old_cluster = entry.get_cluster();
+        let first_cluster = self.first_cluster(entry)?;
+        self.zero_range(first_cluster, entry.size as u64, offset, cursor)?;
+        self.write_span(first_cluster, offset, data, cursor)?;
+
+        // A larger size or a new first cluster must not reach the entry
+        // before the clusters behind it do.
+        if end > entry.size as u64 || first_cluster != old_cluster {
+            self.barrier()?;
+        }
+        entry.size = entry.size.max(end as u32);
+        entry.touch_modified();
+        Ok(data.len())
+    }
+
+    /// Opens the file at `path` and returns a handle for `read_open` and
+    /// `write_open`. The handle follows the directory entry rather than the
+    /// path, so it survives renames, and a file removed while open keeps its
+    /// clusters until its last handle is closed.
+    pub fn open(&mut self, path: &str) -> Result<u32, FsError> {
+        let location = self.find_entry(path)?;
+        if location.entry.is_directory() {
+            return Err(FsError::IsDirectory);
+        }
+
+        let slot = Some((location.lba, location.offset));
+        if let Some((&handle, open)) = self
+            .open_files
+            .iter_mut()
+            .find(|(_, open)| open.slot == slot)
+        {
+            open.refs += 1;
+            return Ok(handle);
+        }
+
+        let handle = self.next_handle;
+        self.next_handle = self.next_handle.wrapping_add(1);
+        self.open_files.insert(
+            handle,
+            OpenEntry {
+                slot,
+                entry: location.entry,
+                refs: 1,
+            },
+        );
+        Ok(handle)
+    }
+
+    /// Drops a handle from `open`, freeing the clusters of a removed file
+    /// once nothing has it open.
+    pub fn close(&mut self, handle: u32) -> Result<(), FsError> {
+        let Some(open) = self.open_files.get_mut(&handle) else {
+            return Ok(());
+        };
+        open.refs -= 1;
+        if open.refs > 0 {
+            return Ok(());
+        }
+
+        let open = self.open_files.remove(&handle).unwrap();
+        let cluster = open.entry.get_cluster();
+        if open.slot.is_none() && cluster >= 2 {
+            self.free_chain(cluster)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_open(
+        &mut self,
+        handle: u32,
+        offset: u64,
+        buf: &mut [u8],
+        cursor: &mut ChainCursor,
+    ) -> Result<usize, FsError> {
+        let mut entry = self.open_entry(handle)?;
+        let len = self.read_data(&entry, offset, buf, cursor)?;
+
+        if len > 0 && entry.touch_accessed() {
+            self.store_open_entry(handle, &entry)?;
+        }
+        Ok(len)
+    }
+
+    pub fn write_open(
+        &mut self,
+        handle: u32,
+        offset: u64,
+        data: &[u8],
+        cursor: &mut ChainCursor,
+    ) -> Result<usize, FsError> {
+        let mut entry = self.open_entry(handle)?;
+        let written = self.write_data(&mut entry, offset, data, cursor)?;
+
+        if written > 0 {
+            self.store_open_entry(handle, &entry)?;
+        }
+        Ok(written)
+    }
+
+    pub fn open_size(&mut self, handle: u32) -> Result<u64, FsError> {
+        Ok(self.open_entry(handle)?.size as u64)
+    }
+
+    /// The current directory entry of an open file.
+    fn open_entry(&mut self, handle: u32) -> Result<DirectoryEntry, FsError> {
+        let open = self
+            .open_files
+            .get(&handle)
+            .ok_or(FsError::InvalidArgument)?;
+        match open.slot {
+            Some((lba, offset)) => self.read_entry_at(lba, offset),
+            None => Ok(open.entry),
+        }
+    }
+
+    fn store_open_entry(&mut self, handle: u32, entry: &DirectoryEntry) -> Result<(), FsError> {
+        let open = self
+            .open_files
+            .get_mut(&handle)
+            .ok_or(FsError::InvalidArgument)?;
+        open.entry = *entry;
+        match open.slot {
+            Some((lba, offset)) => self.write_entry_at(lba, offset, entry),
+            None => Ok(()),
+        }
+    }
+
+    /// Points the open file whose entry was at `from` to `to`, or marks it
+    /// removed when `to` is `None`. Returns whether the file was open.
+    fn move_open(&mut self, from: &EntryLocation, to: Option<(u32, usize)>) -> bool {
+        let slot = Some((from.lba, from.offset));
+        let Some(open) = self.open_files.values_mut().find(|open| open.slot == slot) else {
+            return false;
+        };
+        open.slot = to;
+        open.entry = from.entry;
+        true
+    }
+
+    /// Chains of files removed while still open, which no entry reaches.
+    fn open_orphans(&self) -> Vec<u32> {
+        self.open_files
+            .values()
+            .filter(|open| open.slot.is_none())
+            .map(|open| open.entry.get_cluster())
+            .filter(|&cluster| cluster >= 2)
+            .collect()
+    }
+
+    /// Sets the size of the file at `path`. Shrinking frees the clusters past
+    /// the new end; growing fills the new bytes with zeroes.
+    pub fn truncate(&mut self, path: &str, size: u64) -> Result<(), FsError> {
+        let location = self.find_entry(path)?;
+        if location.entry.is_directory() {
+            return Err(FsError::IsDirectory);
+        }
+        if size > u32::MAX as u64 {
+            return Err(FsError::FileTooLarge);
+        }
+
+        let mut entry = location.entry;
+        let old_size = entry.size as u64;
+        entry.size = size as u32;
+        entry.touch_modified();
+
+        if size > old_size {
+            let first_cluster = self.first_cluster(&mut entry)?;
+            self.zero_range(first_cluster, old_size, size, &mut ChainCursor::default())?;
+            self.barrier()?;
+            return self.write_entry(&location, &entry);
+        }
+        if size == old_size {
+            return self.write_entry(&location, &entry);
+        }
+
+        // Shrinking: the entry stops referring to the tail before the tail
+        // is freed.
+        let cluster_size = (self.sectors_per_cluster * 512) as u64;
+        let keep = size.div_ceil(cluster_size);
+        let first_cluster = entry.get_cluster();
+        if keep == 0 {
+            entry.set_cluster(0);
+        }
+        self.write_entry(&location, &entry)?;
+        self.barrier()?;
+
+        if keep == 0 {
+            if first_cluster >= 2 {
+                self.free_chain(first_cluster)?;
+            }
+            return Ok(());
+        }
+
+        let mut last = first_cluster;
+        for _ in 1..keep {
+            last = self.next_cluster(last)?.ok_or(FsError::Corrupted)?;
+        }
+        if let Some(tail) = self.next_cluster(last)? {
+            self.set_fat_entry(last, END_OF_CHAIN)?;
+            self.barrier()?;
+            self.free_chain(tail)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the file at `path` and releases its clusters, or leaves them
+    /// to `close` while the file is open.
+    pub fn remove(&mut self, path: &str) -> Result<(), FsError> {
+        let location = self.find_entry(path)?;
+        if location.entry.is_directory() {
+            return Err(FsError::IsDirectory);
+        }
+
+        self.delete_entry(&location)?;
+        let cluster = location.entry.get_cluster();
+        if !self.move_open(&location, None) && cluster >= 2 {
+            self.barrier()?;
+            self.free_chain(cluster)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the entry at `old_path` to `new_path`, which may lie in another
+    /// directory. An existing file at `new_path` is replaced.
+    pub fn rename(&mut self, old_path: &str, new_path: &str) -> Result<(), FsError> {
+        let location = self.find_entry(old_path)?;
+        let (new_parent, new_name) = split_path(new_path);
+        let parent_cluster = self.resolve_dir(new_parent)?;
+
+        let is_dir = location.entry.is_directory();
+        let cluster = location.entry.get_cluster();
+        if is_dir && self.is_in_subtree(parent_cluster, cluster)? {
+            return Err(FsError::InvalidArgument);
+        }
+
+        // Renaming an entry onto itself, e.g. to change its case, simply
+        // writes the new name before dropping the old one.
+        if let Some(existing) = self.find_in_dir(parent_cluster, new_name)?
+            && (existing.lba, existing.offset) != (location.lba, location.offset)
+        {
+            if existing.entry.is_directory() || is_dir {
+                return Err(FsError::AlreadyExists);
+            }
+            self.remove(new_path)?;
+        }
+
+        // The entry keeps its timestamps under the new name.
+        // Until the old entry is gone both names share the chain, which is
+        // recoverable; dropping it first could lose the file.
+        let slot = self.add_directory_entry(parent_cluster, new_name, location.entry)?;
+        self.barrier()?;
+        self.delete_entry(&location)?;
+        self.move_open(&location, Some(slot));
+
+        if is_dir {
+            self.set_dotdot(cluster, parent_cluster)?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether `dir_cluster` is `ancestor` or lies somewhere below it.
+    fn is_in_subtree(&mut self, mut dir_cluster: u32, ancestor: u32) -> Result<bool, FsError> {
+        loop {
+            if dir_cluster == ancestor {
+                return Ok(true);
+            }
+            if dir_cluster == self.root_cluster {
+                return Ok(false);
+            }
+
+            let Some(parent) = self.find_in_dir(dir_cluster, "..")? else {
+                return Ok(false);
+            };
+            dir_cluster = match parent.entry.get_cluster() {
+                0 => self.root_cluster,
+                cluster => cluster,
+            };
+        }
+    }
+
+    fn set_dotdot(&mut self, dir_cluster: u32, parent_cluster: u32) -> Result<(), FsError> {
+        let Some(location) = self.find_in_dir(dir_cluster, "..")? else {
+            return Ok(());
+        };
+
+        let mut entry = location.entry;
+        entry.set_cluster(if parent_cluster == self.root_cluster {
+            0
+        } else {
+            parent_cluster
+        });
+        self.write_entry(&location, &entry)
+    }
+
+    /// Returns the file's first cluster, allocating one for files that have
+    /// no chain yet.
+    fn first_cluster(&mut self, entry: &mut DirectoryEntry) -> Result<u32, FsError> {
+        let cluster = entry.get_cluster();
+        if cluster >= 2 {
+            return Ok(cluster);
+        }
+
+        let cluster = self.alloc_cluster()?;
+        entry.set_cluster(cluster);
+        Ok(cluster)
+    }
+
+    fn zero_range(
+        &mut self,
+        first_cluster: u32,
+        from: u64,
+        to: u64,
+        cursor: &mut ChainCursor,
+    ) -> Result<(), FsError> {
+        let zeroes = [0u8; 512];
+        let mut position = from;
+
+        while position < to {
+            let count = (to - position).min(512) as usize;
+            self.write_span(first_cluster, position, &zeroes[..count], cursor)?;
+            position += count as u64;
+        }
+        Ok(())
+    }
+
+    /// Writes `data` at byte `offset` of the chain starting at
+    /// `first_cluster`, appending zeroed clusters as needed.
+    fn write_span(
+        &mut self,
+        first_cluster: u32,
+        offset: u64,
+        data: &[u8],
+        cursor: &mut ChainCursor,
+    ) -> Result<(), FsError> {
+        let cluster_size = (self.sectors_per_cluster * 512) as u64;
+        let mut written = 0;
+
+        while written < data.len() {
+            let position = offset + written as u64;
+            let index = (position / cluster_size) as u32;
+            let cluster = self.seek_cluster(cursor, first_cluster, index, true)?;
+
+            let in_cluster = position % cluster_size;
+            let lba = self.cluster_to_lba(cluster) + (in_cluster / 512) as u32;
+            let sector_offset = (in_cluster % 512) as usize;
+            let count = (512 - sector_offset).min(data.len() - written);
+
+            let mut buf = [0u8; 512];
+            if count < 512 {
+                self.read_sector_into_u8(lba, &mut buf)?;
+            }
+            buf[sector_offset..sector_offset + count]
+                .copy_from_slice(&data[written..written + count]);
+            self.write_sector_from_u8(lba, &buf)?;
+
+            written += count;
+        }
+        Ok(())
+    }
+
+    fn next_or_extend(&mut self, cluster: u32) -> Result<u32, FsError> {
+        if let Some(next) = self.next_cluster(cluster)? {
+            return Ok(next);
+        }
+
+        // The new cluster's zeroes and end-of-chain mark must land before
+        // the link that makes it part of the file.
+        let next = self.alloc_cluster()?;
+        self.barrier()?;
+        self.set_fat_entry(cluster, next)?;
+        Ok(next)
+    }
+
+    pub fn lookup(&mut self, filename: &str) -> Result<DirectoryEntry, FsError> {
+        self.find_entry(filename).map(|location| location.entry)
+    }
+
+    /// Calls `visit` with every live short entry of the directory starting at
+    /// `start_cluster`, along with its long name, stopping early once `visit`
+    /// returns `false`.
+    fn scan_dir(
+        &mut self,
+        start_cluster: u32,
+        mut visit: impl FnMut(EntryLocation) -> bool,
+    ) -> Result<(), FsError> {
+        let mut current_cluster = Some(start_cluster);
+        let mut long_name = LfnBuilder::default();
+
+        while let Some(cluster) = current_cluster {
+            let (start_lba, sectors) = self.dir_sectors(cluster);
+
+            for i in 0..sectors {
+                let mut buf = [0u8; 512];
+                self.read_sector_into_u8(start_lba + i, &mut buf)?;
+
+                for offset in (0..512).step_by(32) {
+                    let entry = unsafe { *(buf.as_ptr().add(offset) as *const DirectoryEntry) };
+                    if entry.is_end() {
+                        return Ok(());
+                    }
+                    if entry.is_free() {
+                        long_name.reset();
+                        continue;
+                    }
+                    if entry.is_long_name() {
+                        long_name.push(&buf[offset..offset + 32], (start_lba + i, offset));
+                        continue;
+                    }
+
+                    let (long_name, lfn_slots) = long_name
+                        .finish(entry.checksum())
+                        .map_or((None, Vec::new()), |(name, slots)| (Some(name), slots));
+                    let location = EntryLocation {
+                        lba: start_lba + i,
+                        offset,
+                        entry,
+                        long_name,
+                        lfn_slots,
+                    };
+                    if !visit(location) {
+                        return Ok(());
+                    }
+                }
+            }
+            current_cluster = self.next_dir_cluster(cluster)?;
+        }
+        Ok(())
+    }
+
+    /// The sectors of directory cluster `cluster`, where cluster 0 is the
+    /// fixed root directory region on FAT12 and FAT16.
+    fn dir_sectors(&self, cluster: u32) -> (u32, u32) {
+        if cluster == 0 {
+            (self.root_dir_sector, self.root_dir_sectors)
+        } else {
+            (self.cluster_to_lba(cluster), self.sectors_per_cluster)
+        }
+    }
+
+    fn next_dir_cluster(&mut self, cluster: u32) -> Result<Option<u32>, FsError> {
+        if cluster == 0 {
+            return Ok(None);
+        }
+        self.next_cluster(cluster)
+    }
+
+    /// Follows `path` from the root and returns the first cluster of the
+    /// directory it names.
+    fn resolve_dir(&mut self, path: &str) -> Result<u32, FsError> {
+        let mut cluster = self.root_cluster;
+
+        for name in path.split('/').filter(|c| !c.is_empty()) {
+            let location = self.find_in_dir(cluster, name)?.ok_or(FsError::NotFound)?;
+            if !location.entry.is_directory() {
+                return Err(FsError::NotDirectory);
+            }
+            cluster = location.entry.get_cluster();
+            if cluster == 0 {
+                cluster = self.root_cluster;
+            }
+        }
+        Ok(cluster)
+    }
+
+    fn find_in_dir(
+        &mut self,
+        dir_cluster: u32,
+        name: &str,
+    ) -> Result<Option<EntryLocation>, FsError> {
+        let mut found = None;
+        self.scan_dir(dir_cluster, |location| {
+            if !location.entry.is_volume_label() && location.matches(name) {
+                found = Some(location);
+                return false;
+            }
+            true
+        })?;
+        Ok(found)
+    }
+
+    fn find_entry(&mut self, path: &str) -> Result<EntryLocation, FsError> {
+        let (parent, name) = split_path(path);
+        if name.is_empty() {
+            return Err(FsError::NotFound);
+        }
+
+        let dir_cluster = self.resolve_dir(parent)?;
+        self.find_in_dir(dir_cluster, name)?
+            .ok_or(FsError::NotFound)
+    }
+
+    fn is_dir_empty(&mut self, dir_cluster: u32) -> Result<bool, FsError> {
+        let mut empty = true;
+        self.scan_dir(dir_cluster, |location| {
+            if location.entry.is_volume_label() || location.entry.is_dot() {
+                return true;
+            }
+            empty = false;
+            false
+        })?;
+        Ok(empty)
+    }
+
+    /// Lists the directory at `path`, leaving out `.` and `..`.
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<DirEntryInfo>, FsError> {
+        let dir_cluster = self.resolve_dir(path)?;
+
+        let mut entries = Vec::new();
+        self.scan_dir(dir_cluster, |location| {
+            if !location.entry.is_volume_label() && !location.entry.is_dot() {
+                entries.push(DirEntryInfo {
+                    name: location.name(),
+                    is_dir: location.entry.is_directory(),
+                    size: location.entry.size,
+                    id: location.id(),
+                });
+            }
+            true
+        })?;
+        Ok(entries)
+    }
+
+    fn write_entry(
+        &mut self,
+        location: &EntryLocation,
+        entry: &DirectoryEntry,
+    ) -> Result<(), FsError> {
+        self.write_entry_at(location.lba, location.offset, entry)
+    }
+
+    /// Marks the short entry and its LFN entries as deleted.
+    fn delete_entry(&mut self, location: &EntryLocation) -> Result<(), FsError> {
+        for &(lba, offset) in location
+            .lfn_slots
+            .iter()
+            .chain(core::iter::once(&(location.lba, location.offset)))
+        {
+            let mut buf = [0u8; 512];
+            self.read_sector_into_u8(lba, &mut buf)?;
+            buf[offset] = 0xE5;
+            self.write_sector_from_u8(lba, &buf)?;
+        }
+        Ok(())
+    }
+
+    fn write_raw_entry(&mut self, lba: u32, offset: usize, raw: &[u8; 32]) -> Result<(), FsError> {
+        let mut buf = [0u8; 512];
+        self.read_sector_into_u8(lba, &mut buf)?;
+        buf[offset..offset + 32].copy_from_slice(raw);
+        self.write_sector_from_u8(lba, &buf)
+    }
+
+    fn read_entry_at(&mut self, lba: u32, offset: usize) -> Result<DirectoryEntry, FsError> {
+        let mut buf = [0u8; 512];
+        self.read_sector_into_u8(lba, &mut buf)?;
+        Ok(unsafe { *(buf.as_ptr().add(offset) as *const DirectoryEntry) })
+    }
+
+    fn write_entry_at(
+        &mut self,
+        lba: u32,
+        offset: usize,
+        entry: &DirectoryEntry,
+    ) -> Result<(), FsError> {
+        let mut buf = [0u8; 512];
+        self.read_sector_into_u8(lba, &mut buf)?;
+
+        unsafe {
+            let ptr = buf.as_mut_ptr().add(offset) as *mut DirectoryEntry;
+            *ptr = *entry;
+        }
+
+        self.write_sector_from_u8(lba, &buf)
+    }
+
+    pub fn file_exists(&mut self, path: &str) -> Result<bool, FsError> {
+        match self.find_entry(path) {
+            Ok(_) => Ok(true),
+            Err(FsError::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Allocates a zeroed cluster, writing the zeroes before the FAT entry
+    /// that claims it.
+    fn alloc_cluster(&mut self) -> Result<u32, FsError> {
+        let [cluster] = self.reserve_clusters(1)?[..] else {
+            unreachable!()
+        };
+        if let Err(e) = self.zero_cluster(cluster) {
+            self.release_clusters(&[cluster]);
+            return Err(e);
+        }
+        self.set_fat_entry(cluster, END_OF_CHAIN)?;
+        Ok(cluster)
+    }
+
+    fn zero_cluster(&mut self, cluster: u32) -> Result<(), FsError> {
+        let start_lba = self.cluster_to_lba(cluster);
+        let zeroes = [0u8; 512];
+        for i in 0..self.sectors_per_cluster {
+            self.write_sector_from_u8(start_lba + i, &zeroes)?;
+        }
+        Ok(())
+    }
+
+    /// Finds `count` consecutive unused slots in the directory, growing it
+    /// by a cluster whenever the existing ones run out.
+    fn free_slots(&mut self, dir_cluster: u32, count: usize) -> Result<Vec<(u32, usize)>, FsError> {
+        let mut run = Vec::new();
+        let mut cluster = dir_cluster;
+
+        loop {
+            let (start_lba, sectors) = self.dir_sectors(cluster);
+            for i in 0..sectors {
+                let mut buf = [0u8; 512];
+                self.read_sector_into_u8(start_lba + i, &mut buf)?;
+
+                for offset in (0..512).step_by(32) {
+                    if buf[offset] != 0x00 && buf[offset] != 0xE5 {
+                        run.clear();
+                        continue;
+                    }
+                    run.push((start_lba + i, offset));
+                    if run.len() == count {
+                        return Ok(run);
+                    }
+                }
+            }
+
+            // The fixed root directory cannot grow.
+            if cluster == 0 {
+                return Err(FsError::NoSpace);
+            }
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => {
+                    // Stale bytes in a new directory cluster would read as
+                    // entries, so its zeroes must land before the link.
+                    let next = self.alloc_cluster()?;
+                    self.barrier()?;
+                    self.set_fat_entry(cluster, next)?;
+                    next
+                }
+            };
+        }
+    }
+
+    fn short_name_taken(
+        &mut self,
+        dir_cluster: u32,
+        name: &[u8; 8],
+        ext: &[u8; 3],
+    ) -> Result<bool, FsError> {
+        let mut taken = false;
+        self.scan_dir(dir_cluster, |location| {
+            taken = location.entry.name == *name && location.entry.ext == *ext;
+            !taken
+        })?;
+        Ok(taken)
+    }
+
+    /// Adds `entry` under the name `filename` and returns where the short
+    /// entry went. Names that are not plain upper-case 8.3 get LFN entries in
+    /// front of a unique short alias.
+    fn add_directory_entry(
+        &mut self,
+        dir_cluster: u32,
+        filename: &str,
+        mut entry: DirectoryEntry,
+    ) -> Result<(u32, usize), FsError> {
+        if !lfn::is_valid_long_name(filename) {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let (name, ext, long_entries) = match lfn::exact_short_name(filename) {
+            Some((name, ext)) => (name, ext, Vec::new()),
+            None => {
+                let mut alias = None;
+                for (name, ext) in lfn::short_aliases(filename) {
+                    if !self.short_name_taken(dir_cluster, &name, &ext)? {
+                        alias = Some((name, ext));
+                        break;
+                    }
+                }
+                let (name, ext) = alias.ok_or(FsError::NoSpace)?;
+                (name, ext, lfn::encode(filename, lfn::checksum(&name, &ext)))
+            }
+        };
+
+        let slots = self.free_slots(dir_cluster, long_entries.len() + 1)?;
+        for (raw, &(lba, offset)) in long_entries.iter().zip(slots.iter()) {
+            self.write_raw_entry(lba, offset, raw)?;
+        }
+
+        let (lba, offset) = slots[long_entries.len()];
+        entry.name = name;
+        entry.ext = ext;
+        self.write_entry_at(lba, offset, &entry)?;
+        Ok((lba, offset))
+    }
+}
+
+/// Splits `path` into its parent directory and final component.
+fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(index) => (&path[..index], &path[index + 1..]),
+        None => ("", path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::block::RamDisk;
+
+    const TYPES: [FatType; 3] = [FatType::Fat12, FatType::Fat16, FatType::Fat32];
+
+    /// Formats a blank volume of `fat_type` on a RAM disk, the way mkfs.fat
+    /// lays one out: two FATs, and a fixed root directory below FAT32.
+    fn format(fat_type: FatType) -> Arc<RamDisk> {
+        let (total, per_cluster, reserved, root_entries): (u32, u32, u32, u32) = match fat_type {
+            FatType::Fat12 => (8192, 4, 1, 512),
+            FatType::Fat16 => (8192, 1, 1, 512),
+            FatType::Fat32 => (67072, 1, 32, 0),
+        };
+        let root_sectors = root_entries * 32 / 512;
+        let most_clusters = (total - reserved - root_sectors) / per_cluster;
+        let fat_size = (fat_type.entry_offset(most_clusters + 2) + 4).div_ceil(512);
+
+        let disk = Arc::new(RamDisk::new(512, total as usize));
+        let mut boot = [0u8; 512];
+        boot[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        boot[11..13].copy_from_slice(&512u16.to_le_bytes());
+        boot[13] = per_cluster as u8;
+        boot[14..16].copy_from_slice(&(reserved as u16).to_le_bytes());
+        boot[16] = 2;
+        boot[17..19].copy_from_slice(&(root_entries as u16).to_le_bytes());
+        boot[21] = 0xF8;
+        boot[32..36].copy_from_slice(&total.to_le_bytes());
+        if fat_type == FatType::Fat32 {
+            boot[36..40].copy_from_slice(&fat_size.to_le_bytes());
+            boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+            boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+
+            let mut fs_info = [0u8; 512];
+            fs_info[0..4].copy_from_slice(&FSINFO_LEAD_SIGNATURE.to_le_bytes());
+            fs_info[484..488].copy_from_slice(&FSINFO_STRUCT_SIGNATURE.to_le_bytes());
+            fs_info[FSINFO_FREE_COUNT..FSINFO_NEXT_FREE + 4].fill(0xFF);
+            fs_info[508..512].copy_from_slice(&0xAA55_0000u32.to_le_bytes());
+            disk.write_blocks(1, &fs_info).unwrap();
+        } else {
+            boot[22..24].copy_from_slice(&(fat_size as u16).to_le_bytes());
+        }
+        boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+        disk.write_blocks(0, &boot).unwrap();
+
+        let mut fat = [0u8; 512];
+        fat_type.encode(&mut fat, 0, 0, 0x0FFF_FFF8);
+        let offset = fat_type.entry_offset(1) as usize;
+        fat_type.encode(&mut fat, offset, 1, 0x0FFF_FFFF);
+        if fat_type == FatType::Fat32 {
+            fat_type.encode(&mut fat, 8, 2, END_OF_CHAIN);
+        }
+        for copy in 0..2 {
+            disk.write_blocks((reserved + copy * fat_size) as u64, &fat)
+                .unwrap();
+        }
+        disk
+    }
+
+    fn mount(disk: &Arc<RamDisk>) -> Fat32Driver {
+        Fat32Driver::new(disk.clone()).unwrap()
+    }
+
+    /// Sets the FAT entry of `cluster` in the first FAT only, as a crash or
+    /// a stray write would.
+    fn damage_fat(disk: &Arc<RamDisk>, driver: &Fat32Driver, cluster: u32, value: u32) {
+        let offset = driver.fat_type.entry_offset(cluster);
+        let lba = (driver.fat_start_sector + offset / 512) as u64;
+        let mut buf = [0u8; 1024];
+        disk.read_blocks(lba, &mut buf).unwrap();
+        driver
+            .fat_type
+            .encode(&mut buf, (offset % 512) as usize, cluster, value);
+        disk.write_blocks(lba, &buf).unwrap();
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn detects_type_of_fresh_volumes() {
+        for fat_type in TYPES {
+            let driver = mount(&format(fat_type));
+            assert_eq!(driver.fat_type(), fat_type);
+            assert!(!driver.was_dirty());
+        }
+    }
+
+    #[test]
+    fn create_and_write_round_trip() {
+        for fat_type in TYPES {
+            let disk = format(fat_type);
+            let mut driver = mount(&disk);
+            let free = driver.free_clusters();
+
+            driver.create_file("/hello.txt", b"hello").unwrap();
+            assert_eq!(driver.read_file("/HELLO.TXT").unwrap(), b"hello");
+            assert!(matches!(
+                driver.create_file("/Hello.txt", b""),
+                Err(FsError::AlreadyExists)
+            ));
+
+            let data = pattern(5000);
+            driver
+                .create_file("/A file with a long name.bin", &data)
+                .unwrap();
+            assert_eq!(
+                driver.read_file("/a file with a long name.bin").unwrap(),
+                data
+            );
+
+            let mut cursor = ChainCursor::default();
+            assert_eq!(
+                driver
+                    .write_at("/hello.txt", 10, b"world", &mut cursor)
+                    .unwrap(),
+                5
+            );
+            assert_eq!(
+                driver.read_file("/hello.txt").unwrap(),
+                b"hello\0\0\0\0\0world"
+            );
+
+            let mut buf = [0u8; 100];
+            let read = driver
+                .read_at("/a file with a long name.bin", 4090, &mut buf, &mut cursor)
+                .unwrap();
+            assert_eq!(&buf[..read], &data[4090..4190]);
+
+            driver.unmount().unwrap();
+            let mut driver = mount(&disk);
+            assert!(!driver.was_dirty());
+            assert_eq!(driver.read_file("/hello.txt").unwrap().len(), 15);
+            assert_eq!(
+                driver.read_file("/A file with a long name.bin").unwrap(),
+                data
+            );
+
+            driver.remove("/hello.txt").unwrap();
+            driver.remove("/A file with a long name.bin").unwrap();
+            assert_eq!(driver.free_clusters(), free);
+            assert!(driver.check(false).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn truncate_shrinks_and_grows() {
+        for fat_type in TYPES {
+            let mut driver = mount(&format(fat_type));
+            let free = driver.free_clusters();
+            let data = pattern(3000);
+            driver.create_file("/data", &data).unwrap();
+
+            driver.truncate("/data", 700).unwrap();
+            assert_eq!(driver.read_file("/data").unwrap(), &data[..700]);
+            driver.truncate("/data", 900).unwrap();
+            let grown = driver.read_file("/data").unwrap();
+            assert_eq!(&grown[..700], &data[..700]);
+            assert!(grown[700..].iter().all(|&b| b == 0));
+
+            driver.truncate("/data", 0).unwrap();
+            assert_eq!(driver.lookup("/data").unwrap().get_cluster(), 0);
+            assert_eq!(driver.free_clusters(), free);
+            assert!(driver.check(false).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn directories_and_rename() {
+        for fat_type in TYPES {
+            let mut driver = mount(&format(fat_type));
+            let free = driver.free_clusters();
+
+            driver.mkdir("/docs").unwrap();
+            driver.mkdir("/docs/old").unwrap();
+            driver.create_file("/notes.txt", b"notes").unwrap();
+            driver.create_file("/docs/a.txt", b"a").unwrap();
+
+            driver.rename("/notes.txt", "/docs/old/b.txt").unwrap();
+            assert!(matches!(
+                driver.lookup("/notes.txt"),
+                Err(FsError::NotFound)
+            ));
+            assert_eq!(driver.read_file("/docs/old/b.txt").unwrap(), b"notes");
+
+            // Replacing a file drops the old one; a directory cannot move
+            // below itself.
+            driver.rename("/docs/a.txt", "/docs/old/b.txt").unwrap();
+            assert_eq!(driver.read_file("/docs/old/b.txt").unwrap(), b"a");
+            assert!(matches!(
+                driver.rename("/docs", "/docs/old/docs"),
+                Err(FsError::InvalidArgument)
+            ));
+            driver.rename("/docs/old", "/old").unwrap();
+
+            let mut names = driver.list_dir("/old").unwrap();
+            names.sort();
+            assert_eq!(names, ["b.txt"]);
+            assert!(driver.check(false).unwrap().is_empty());
+
+            assert!(matches!(driver.rmdir("/old"), Err(FsError::NotEmpty)));
+            driver.remove("/old/b.txt").unwrap();
+            driver.rmdir("/old").unwrap();
+            driver.rmdir("/docs").unwrap();
+            assert!(matches!(driver.lookup("/old"), Err(FsError::NotFound)));
+            assert_eq!(driver.free_clusters(), free);
+        }
+    }
+
+    #[test]
+    fn open_files_follow_renames_and_outlive_removal() {
+        let mut driver = mount(&format(FatType::Fat16));
+        let free = driver.free_clusters();
+        driver.create_file("/log", b"first").unwrap();
+
+        let handle = driver.open("/log").unwrap();
+        driver.rename("/log", "/log.old").unwrap();
+        let mut cursor = ChainCursor::default();
+        driver
+            .write_open(handle, 5, b" second", &mut cursor)
+            .unwrap();
+        assert_eq!(driver.read_file("/log.old").unwrap(), b"first second");
+
+        driver.remove("/log.old").unwrap();
+        let mut buf = [0u8; 32];
+        let read = driver
+            .read_open(handle, 0, &mut buf, &mut ChainCursor::default())
+            .unwrap();
+        assert_eq!(&buf[..read], b"first second");
+        assert!(driver.free_clusters() < free);
+        assert!(driver.check(false).unwrap().is_empty());
+
+        driver.close(handle).unwrap();
+        assert_eq!(driver.free_clusters(), free);
+    }
+
+    #[test]
+    fn check_repairs_a_damaged_volume() {
+        for fat_type in TYPES {
+            let disk = format(fat_type);
+            let mut driver = mount(&disk);
+            let cluster_size = (driver.sectors_per_cluster * 512) as usize;
+            let data = pattern(3 * cluster_size);
+            driver.create_file("/file.bin", &data).unwrap();
+            let first = driver.lookup("/file.bin").unwrap().get_cluster();
+            let second = driver.next_cluster(first).unwrap().unwrap();
+            driver.unmount().unwrap();
+
+            // Cut the chain after its second cluster, which strands the
+            // rest, and allocate a cluster nothing refers to.
+            damage_fat(&disk, &driver, second, 0);
+            damage_fat(&disk, &driver, 100, 0x0FFF_FFFF);
+
+            let mut driver = mount(&disk);
+            let issues = driver.check(false).unwrap();
+            assert!(issues.iter().any(|issue| matches!(
+                issue,
+                Issue::BrokenChain { path, cluster } if path == "/file.bin" && *cluster == second
+            )));
+            assert!(
+                issues
+                    .iter()
+                    .any(|issue| matches!(issue, Issue::LostClusters { .. }))
+            );
+            assert!(
+                issues
+                    .iter()
+                    .any(|issue| matches!(issue, Issue::FatMismatch { copy: 1, .. }))
+            );
+
+            assert!(!driver.check(true).unwrap().is_empty());
+            assert!(driver.check(false).unwrap().is_empty());
+            assert_eq!(
+                driver.read_file("/file.bin").unwrap(),
+                &data[..2 * cluster_size]
+            );
+        }
+    }
+}
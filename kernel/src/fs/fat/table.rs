@@ -0,0 +1,179 @@
+/// FAT variant, which fixes the width of the allocation table entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// The variant is decided by the number of data clusters alone, whatever
+    /// the BPB's type string says.
+    pub fn from_cluster_count(clusters: u32) -> Self {
+        if clusters < 4085 {
+            FatType::Fat12
+        } else if clusters < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FatType::Fat12 => "fat12",
+            FatType::Fat16 => "fat16",
+            FatType::Fat32 => "fat32",
+        }
+    }
+
+    /// Smallest entry value that marks the end of a chain.
+    pub fn end_of_chain(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFF8,
+            FatType::Fat16 => 0xFFF8,
+            FatType::Fat32 => 0x0FFF_FFF8,
+        }
+    }
+
+    /// Entry value that marks a cluster as unusable.
+    pub fn bad_cluster(self) -> u32 {
+        self.end_of_chain() - 1
+    }
+
+    /// Bit of FAT[1] that stays set while the volume is cleanly unmounted.
+    /// FAT12 has no such flag.
+    pub fn clean_bit(self) -> Option<u32> {
+        match self {
+            FatType::Fat12 => None,
+            FatType::Fat16 => Some(0x8000),
+            FatType::Fat32 => Some(0x0800_0000),
+        }
+    }
+
+    /// Number of entries a FAT of `sectors` sectors holds.
+    pub fn entries_in(self, sectors: u32) -> u32 {
+        let bytes = sectors.saturating_mul(512);
+        match self {
+            FatType::Fat12 => bytes / 3 * 2,
+            FatType::Fat16 => bytes / 2,
+            FatType::Fat32 => bytes / 4,
+        }
+    }
+
+    /// Byte offset of the entry for `cluster` from the start of the FAT.
+    pub fn entry_offset(self, cluster: u32) -> u32 {
+        match self {
+            FatType::Fat12 => cluster + cluster / 2,
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        }
+    }
+
+    /// Whether an entry starting at `offset` within a sector runs into the
+    /// next one. Only 12-bit entries can.
+    pub fn straddles(self, offset: usize) -> bool {
+        self == FatType::Fat12 && offset == 511
+    }
+
+    /// Reads the entry for `cluster` stored at `offset` in `buf`.
+    pub fn decode(self, buf: &[u8], offset: usize, cluster: u32) -> u32 {
+        match self {
+            FatType::Fat12 => {
+                let pair = u16::from_le_bytes([buf[offset], buf[offset + 1]]) as u32;
+                if cluster & 1 != 0 {
+                    pair >> 4
+                } else {
+                    pair & 0xFFF
+                }
+            }
+            FatType::Fat16 => u16::from_le_bytes([buf[offset], buf[offset + 1]]) as u32,
+            FatType::Fat32 => super::read_u32(buf, offset) & 0x0FFF_FFFF,
+        }
+    }
+
+    /// Stores `value` as the entry for `cluster` at `offset` in `buf`,
+    /// keeping the bits that belong to neighbouring entries or are reserved.
+    pub fn encode(self, buf: &mut [u8], offset: usize, cluster: u32, value: u32) {
+        match self {
+            FatType::Fat12 => {
+                let pair = u16::from_le_bytes([buf[offset], buf[offset + 1]]);
+                let value = (value & 0xFFF) as u16;
+                let pair = if cluster & 1 != 0 {
+                    (pair & 0x000F) | (value << 4)
+                } else {
+                    (pair & 0xF000) | value
+                };
+                buf[offset..offset + 2].copy_from_slice(&pair.to_le_bytes());
+            }
+            FatType::Fat16 => {
+                buf[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes());
+            }
+            FatType::Fat32 => {
+                let old = super::read_u32(buf, offset);
+                let new = (old & 0xF000_0000) | (value & 0x0FFF_FFFF);
+                buf[offset..offset + 4].copy_from_slice(&new.to_le_bytes());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fat12_neighbours_share_a_byte() {
+        let mut buf = [0u8; 6];
+        let fat = FatType::Fat12;
+        fat.encode(&mut buf, fat.entry_offset(2) as usize, 2, 0xABC);
+        fat.encode(&mut buf, fat.entry_offset(3) as usize, 3, 0x123);
+
+        assert_eq!(buf[3..6], [0xBC, 0x3A, 0x12]);
+        assert_eq!(fat.decode(&buf, 3, 2), 0xABC);
+        assert_eq!(fat.decode(&buf, 4, 3), 0x123);
+
+        fat.encode(&mut buf, 3, 2, 0);
+        assert_eq!(fat.decode(&buf, 3, 2), 0);
+        assert_eq!(fat.decode(&buf, 4, 3), 0x123);
+    }
+
+    #[test]
+    fn fat12_entry_straddling_sectors() {
+        // Cluster 341 starts at byte 511, so its high byte is the first of
+        // the next sector.
+        let fat = FatType::Fat12;
+        assert_eq!(fat.entry_offset(341), 511);
+        assert!(fat.straddles(511));
+        assert!(!fat.straddles(510));
+        assert!(!FatType::Fat16.straddles(511));
+
+        let mut buf = [0u8; 1024];
+        fat.encode(&mut buf, 510, 340, 0x456);
+        fat.encode(&mut buf, 511, 341, 0xFF8);
+        assert_eq!(buf[510..513], [0x56, 0x84, 0xFF]);
+        assert_eq!(fat.decode(&buf, 510, 340), 0x456);
+        assert_eq!(fat.decode(&buf, 511, 341), 0xFF8);
+        assert!(fat.decode(&buf, 511, 341) >= fat.end_of_chain());
+    }
+
+    #[test]
+    fn fat32_keeps_reserved_bits() {
+        let mut buf = [0xFFu8; 8];
+        FatType::Fat32.encode(&mut buf, 4, 1, 0x0000_1234);
+        assert_eq!(
+            u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+            0xF000_1234
+        );
+        assert_eq!(FatType::Fat32.decode(&buf, 4, 1), 0x1234);
+    }
+
+    #[test]
+    fn type_follows_cluster_count() {
+        assert_eq!(FatType::from_cluster_count(4084), FatType::Fat12);
+        assert_eq!(FatType::from_cluster_count(4085), FatType::Fat16);
+        assert_eq!(FatType::from_cluster_count(65524), FatType::Fat16);
+        assert_eq!(FatType::from_cluster_count(65525), FatType::Fat32);
+        assert_eq!(FatType::Fat12.entries_in(3), 1024);
+    }
+}
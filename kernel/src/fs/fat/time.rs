@@ -0,0 +1,32 @@
+use crate::drivers::rtc::DateTime;
+
+/// 1980-01-01, the earliest date FAT can store.
+const EPOCH_DATE: u16 = (1 << 5) | 1;
+
+/// Packs `time` into a FAT date, a FAT time with two-second resolution and
+/// the 10 ms count that refines it. Years outside 1980..=2107 are clamped.
+pub fn encode(time: &DateTime) -> (u16, u16, u8) {
+    if time.year < 1980 {
+        return (EPOCH_DATE, 0, 0);
+    }
+
+    let date = ((time.year.min(2107) - 1980) << 9) | ((time.month as u16) << 5) | time.day as u16;
+    let clock = ((time.hour as u16) << 11) | ((time.minute as u16) << 5) | (time.second as u16 / 2);
+    (date, clock, (time.second % 2) * 100)
+}
+
+/// Unpacks what `encode` stored. A zero date means the time was never set.
+pub fn decode(date: u16, clock: u16, hundredths: u8) -> Option<DateTime> {
+    if date == 0 {
+        return None;
+    }
+
+    Some(DateTime {
+        year: 1980 + (date >> 9),
+        month: ((date >> 5) & 0x0F) as u8,
+        day: (date & 0x1F) as u8,
+        hour: (clock >> 11) as u8,
+        minute: ((clock >> 5) & 0x3F) as u8,
+        second: (clock & 0x1F) as u8 * 2 + hundredths / 100,
+    })
+}
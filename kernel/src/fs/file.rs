@@ -0,0 +1,340 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::FsError;
+use super::vfs::{self, DirEntry, File, Inode, InodeKind};
+use crate::drivers::console::ConsoleDevice;
+use crate::ipc::poll::PollFlags;
+use crate::ipc::shm::SharedMemory;
+use crate::ipc::wait::WaitQueue;
+
+pub const O_RDONLY: u32 = 0;
+pub const O_WRONLY: u32 = 1;
+pub const O_RDWR: u32 = 2;
+pub const O_ACCMODE: u32 = 3;
+pub const O_CREAT: u32 = 0o100;
+pub const O_TRUNC: u32 = 0o1000;
+pub const O_APPEND: u32 = 0o2000;
+
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+pub const DT_UNKNOWN: u8 = 0;
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_BLK: u8 = 6;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFBLK: u32 = 0o060000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileStat {
+    pub mode: u32,
+    pub nlink: u32,
+    pub size: u64,
+    pub blksize: u64,
+    pub blocks: u64,
+    /// Times in seconds since the Unix epoch, or 0 where the filesystem
+    /// keeps none: last access, last modification and creation.
+    pub atime: u64,
+    pub mtime: u64,
+    pub btime: u64,
+    /// Inode number within its filesystem, or 0 where it has none.
+    pub ino: u64,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Fixed part of a record produced by `OpenFile::read_dir`. The name follows
+/// it, NUL-terminated, and `reclen` covers the whole record padded to 8 bytes.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct DirentHeader {
+    pub ino: u64,
+    pub next: u64,
+    pub size: u64,
+    pub reclen: u16,
+    pub kind: u8,
+}
+
+fn dirent_type(kind: InodeKind) -> u8 {
+    match kind {
+        InodeKind::File => DT_REG,
+        InodeKind::Directory => DT_DIR,
+        InodeKind::CharDevice => DT_CHR,
+        InodeKind::BlockDevice => DT_BLK,
+        InodeKind::Symlink => DT_LNK,
+    }
+}
+
+/// An open file description. Descriptors created by `dup` share one of these,
+/// and with it the file offset.
+pub struct OpenFile {
+    inode: Arc<dyn Inode>,
+    file: Arc<dyn File>,
+    path: String,
+    flags: u32,
+    offset: Mutex<u64>,
+    /// The directory listing `read_dir` is working through, taken on the
+    /// first call and again after seeking back to the start.
+    listing: Mutex<Option<Vec<DirEntry>>>,
+}
+
+impl OpenFile {
+    pub fn new(inode: Arc<dyn Inode>, path: String, flags: u32) -> Result<Self, FsError> {
+        let file = vfs::open_inode(&inode)?;
+        Ok(Self::with_file(inode, file, path, flags))
+    }
+
+    fn with_file(inode: Arc<dyn Inode>, file: Arc<dyn File>, path: String, flags: u32) -> Self {
+        Self {
+            file,
+            inode,
+            path,
+            flags,
+            offset: Mutex::new(0),
+            listing: Mutex::new(None),
+        }
+    }
+
+    pub fn console(flags: u32) -> Self {
+        let console = Arc::new(ConsoleDevice);
+        Self::with_file(
+            console.clone(),
+            console,
+            String::from("/dev/console"),
+            flags,
+        )
+    }
+
+    /// Opens `path`, resolved against `cwd` when relative.
+    pub fn open(cwd: &str, path: &str, flags: u32) -> Result<Self, FsError> {
+        let path = vfs::path::normalize(cwd, path);
+        let inode = match vfs::lookup("/", &path) {
+            Ok(inode) => inode,
+            Err(FsError::NotFound) if flags & O_CREAT != 0 => {
+                vfs::create("/", &path, InodeKind::File)?
+            }
+            Err(e) => return Err(e),
+        };
+
+        let kind = inode.kind();
+        if kind == InodeKind::Directory && flags & O_ACCMODE != O_RDONLY {
+            return Err(FsError::IsDirectory);
+        }
+
+        let file = Self::new(inode, path, flags)?;
+        if kind == InodeKind::File && flags & O_TRUNC != 0 && file.is_writable() {
+            file.inode.truncate(0)?;
+        }
+        Ok(file)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    fn is_readable(&self) -> bool {
+        self.flags & O_ACCMODE != O_WRONLY
+    }
+
+    fn is_writable(&self) -> bool {
+        matches!(self.flags & O_ACCMODE, O_WRONLY | O_RDWR)
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        if !self.is_readable() {
+            return Err(FsError::BadFileMode);
+        }
+        if self.inode.kind() == InodeKind::Directory {
+            return Err(FsError::IsDirectory);
+        }
+        if !self.file.is_seekable() {
+            return self.file.read(0, buf);
+        }
+
+        let mut offset = self.offset.lock();
+        let count = self.file.read(*offset, buf)?;
+        *offset += count as u64;
+        Ok(count)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        if !self.is_writable() {
+            return Err(FsError::BadFileMode);
+        }
+        if self.inode.kind() == InodeKind::Directory {
+            return Err(FsError::IsDirectory);
+        }
+        if !self.file.is_seekable() {
+            return self.file.write(0, buf);
+        }
+
+        let mut offset = self.offset.lock();
+        let start = if self.flags & O_APPEND != 0 {
+            self.file.size()?
+        } else {
+            *offset
+        };
+
+        let count = self.file.write(start, buf)?;
+        *offset = start + count as u64;
+        Ok(count)
+    }
+
+    pub fn seek(&self, offset: i64, whence: u32) -> Result<u64, FsError> {
+        if !self.file.is_seekable() {
+            return Err(FsError::NotSeekable);
+        }
+
+        let mut current = self.offset.lock();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => *current as i64,
+            SEEK_END => self.file.size()? as i64,
+            _ => return Err(FsError::InvalidArgument),
+        };
+
+        let target = base.checked_add(offset).ok_or(FsError::InvalidArgument)?;
+        if target < 0 {
+            return Err(FsError::InvalidArgument);
+        }
+
+        if target == 0 {
+            *self.listing.lock() = None;
+        }
+        *current = target as u64;
+        Ok(*current)
+    }
+
+    pub fn truncate(&self, size: u64) -> Result<(), FsError> {
+        if !self.is_writable() {
+            return Err(FsError::BadFileMode);
+        }
+        if self.inode.kind() != InodeKind::File {
+            return Err(FsError::InvalidArgument);
+        }
+        self.inode.truncate(size)
+    }
+
+    pub fn stat(&self) -> Result<FileStat, FsError> {
+        self.inode.stat()
+    }
+
+    pub fn readiness(&self) -> PollFlags {
+        self.file.readiness()
+    }
+
+    pub fn wait_queue(&self) -> Option<&'static WaitQueue> {
+        self.file.wait_queue()
+    }
+
+    pub fn ioctl(&self, request: u32, buf: &mut [u8]) -> Result<usize, FsError> {
+        self.file.ioctl(request, buf)
+    }
+
+    /// The device memory to map, if the file was opened with the access the
+    /// mapping needs.
+    pub fn mmap(&self, writable: bool) -> Result<Arc<SharedMemory>, FsError> {
+        if !self.is_readable() || (writable && !self.is_writable()) {
+            return Err(FsError::PermissionDenied);
+        }
+        self.file.mmap()
+    }
+
+    /// Fills `buf` with as many directory records as fit, starting at the
+    /// entry index kept in the file offset. Returns 0 once the listing is
+    /// exhausted.
+    pub fn read_dir(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        let mut cursor = self.offset.lock();
+        let mut listing = self.listing.lock();
+        let entries = match &mut *listing {
+            Some(entries) => entries,
+            slot => slot.insert(vfs::read_dir(&self.path, &self.inode)?),
+        };
+
+        let header_len = core::mem::size_of::<DirentHeader>();
+        let mut written = 0;
+
+        for (index, entry) in entries.iter().enumerate().skip(*cursor as usize) {
+            let reclen = (header_len + entry.name.len() + 1).next_multiple_of(8);
+            if written + reclen > buf.len() {
+                if written == 0 {
+                    return Err(FsError::InvalidArgument);
+                }
+                break;
+            }
+
+            let header = DirentHeader {
+                ino: entry.id,
+                next: index as u64 + 1,
+                size: entry.size,
+                reclen: reclen as u16,
+                kind: dirent_type(entry.kind),
+            };
+
+            let record = &mut buf[written..written + reclen];
+            record.fill(0);
+            unsafe {
+                core::ptr::write_unaligned(record.as_mut_ptr() as *mut DirentHeader, header);
+            }
+            record[header_len..header_len + entry.name.len()]
+                .copy_from_slice(entry.name.as_bytes());
+
+            written += reclen;
+            *cursor = index as u64 + 1;
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::vfs::tests::scratch;
+
+    /// Reads the listing one record per call, returning the names.
+    fn names(dir: &OpenFile, between: impl Fn()) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut buf = [0u8; 40];
+        while dir.read_dir(&mut buf).unwrap() > 0 {
+            let name = &buf[core::mem::size_of::<DirentHeader>()..];
+            let len = name.iter().position(|&b| b == 0).unwrap();
+            names.push(String::from(core::str::from_utf8(&name[..len]).unwrap()));
+            between();
+        }
+        names
+    }
+
+    #[test]
+    fn read_dir_lists_a_snapshot() {
+        scratch("/listing");
+        for name in ["a", "b", "c"] {
+            vfs::create("/listing", name, InodeKind::File).unwrap();
+        }
+
+        let dir = OpenFile::open("/", "/listing", O_RDONLY).unwrap();
+        let listed = names(&dir, || {
+            let _ = vfs::unlink("/listing", "a");
+        });
+        assert_eq!(listed, ["a", "b", "c"]);
+
+        dir.seek(0, SEEK_SET).unwrap();
+        assert_eq!(names(&dir, || {}), ["b", "c"]);
+    }
+}
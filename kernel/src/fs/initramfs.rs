@@ -0,0 +1,243 @@
+use alloc::sync::Arc;
+use core::str;
+
+use super::FsError;
+use super::file::{S_IFDIR, S_IFLNK, S_IFMT, S_IFREG};
+use super::vfs::{Inode, InodeKind};
+
+/// cpio "newc" header: a magic followed by thirteen 8-digit hex fields.
+const HEADER_LEN: usize = 110;
+const MAGIC: &[u8] = b"070701";
+/// The same layout with a checksum in the last field, which is not checked.
+const MAGIC_CRC: &[u8] = b"070702";
+const TRAILER: &str = "TRAILER!!!";
+
+struct Entry<'a> {
+    name: &'a str,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    data: &'a [u8],
+}
+
+fn hex_field(header: &[u8], index: usize) -> Result<u32, FsError> {
+    let start = MAGIC.len() + index * 8;
+    let digits = str::from_utf8(&header[start..start + 8]).map_err(|_| FsError::Corrupted)?;
+    u32::from_str_radix(digits, 16).map_err(|_| FsError::Corrupted)
+}
+
+/// Splits the entry at `offset` off the archive, returning it and the offset
+/// of the next one. Names and data are padded to 4 bytes.
+fn parse_entry(archive: &[u8], offset: usize) -> Result<(Entry<'_>, usize), FsError> {
+    let header = archive
+        .get(offset..offset + HEADER_LEN)
+        .ok_or(FsError::Corrupted)?;
+    if &header[..MAGIC.len()] != MAGIC && &header[..MAGIC.len()] != MAGIC_CRC {
+        return Err(FsError::Corrupted);
+    }
+
+    let mode = hex_field(header, 1)?;
+    let uid = hex_field(header, 2)?;
+    let gid = hex_field(header, 3)?;
+    let file_size = hex_field(header, 6)? as usize;
+    let name_size = hex_field(header, 11)? as usize;
+
+    let name_start = offset + HEADER_LEN;
+    let name = archive
+        .get(name_start..name_start + name_size)
+        .and_then(|name| name.strip_suffix(&[0]))
+        .ok_or(FsError::Corrupted)?;
+    let name = str::from_utf8(name).map_err(|_| FsError::Corrupted)?;
+
+    let data_start = (name_start + name_size).next_multiple_of(4);
+    let data = archive
+        .get(data_start..data_start + file_size)
+        .ok_or(FsError::Corrupted)?;
+    let next = (data_start + file_size).next_multiple_of(4);
+
+    let entry = Entry {
+        name,
+        mode,
+        uid,
+        gid,
+        data,
+    };
+    Ok((entry, next))
+}
+
+/// Looks up `name` in `dir`, creating it as `kind` if it is missing.
+fn lookup_or_create(
+    dir: &Arc<dyn Inode>,
+    name: &str,
+    kind: InodeKind,
+) -> Result<Arc<dyn Inode>, FsError> {
+    match dir.lookup(name) {
+        Ok(inode) if inode.kind() == kind => Ok(inode),
+        Ok(_) => Err(FsError::AlreadyExists),
+        Err(FsError::NotFound) => dir.create(name, kind),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_all(inode: &Arc<dyn Inode>, data: &[u8]) -> Result<(), FsError> {
+    inode.truncate(0)?;
+    let mut written = 0;
+    while written < data.len() {
+        match inode.write_at(written as u64, &data[written..])? {
+            0 => return Err(FsError::NoSpace),
+            count => written += count,
+        }
+    }
+    Ok(())
+}
+
+fn extract(root: &Arc<dyn Inode>, entry: &Entry) -> Result<(), FsError> {
+    let mut components = entry.name.split('/').filter(|c| !c.is_empty() && *c != ".");
+    let Some(name) = components.next_back() else {
+        // The archive root itself.
+        return root.set_mode(entry.mode & 0o7777);
+    };
+
+    let mut dir = root.clone();
+    for component in components {
+        if component == ".." {
+            return Err(FsError::InvalidArgument);
+        }
+        dir = lookup_or_create(&dir, component, InodeKind::Directory)?;
+    }
+
+    let inode = match entry.mode & S_IFMT {
+        S_IFDIR => lookup_or_create(&dir, name, InodeKind::Directory)?,
+        S_IFREG => {
+            let inode = lookup_or_create(&dir, name, InodeKind::File)?;
+            write_all(&inode, entry.data)?;
+            inode
+        }
+        S_IFLNK => {
+            let target = str::from_utf8(entry.data).map_err(|_| FsError::Corrupted)?;
+            dir.symlink(name, target)?
+        }
+        _ => return Err(FsError::NotSupported),
+    };
+
+    inode.set_mode(entry.mode & 0o7777)?;
+    inode.set_owner(entry.uid, entry.gid)
+}
+
+/// Unpacks a cpio archive in the "newc" format into the directory `root`,
+/// returning how many entries it held. Entries of a type other than files,
+/// directories and symlinks are skipped, and hard links come out as separate
+/// files, of which only the last has data.
+pub fn unpack(root: &Arc<dyn Inode>, archive: &[u8]) -> Result<usize, FsError> {
+    let mut offset = 0;
+    let mut count = 0;
+
+    loop {
+        let (entry, next) = parse_entry(archive, offset)?;
+        if entry.name == TRAILER {
+            return Ok(count);
+        }
+
+        match extract(root, &entry) {
+            Ok(()) => count += 1,
+            Err(FsError::NotSupported) => {
+                crate::serial_println!("[INITRAMFS] Skipping {}: unsupported type", entry.name);
+            }
+            Err(e) => {
+                crate::serial_println!("[INITRAMFS] Cannot unpack {}: {:?}", entry.name, e);
+                return Err(e);
+            }
+        }
+        offset = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::tmpfs::TmpFs;
+    use crate::fs::vfs::FileSystem;
+    use alloc::format;
+    use alloc::vec::Vec;
+
+    fn push_entry(archive: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+        let fields = [0, mode, 1000, 100, 1, 0, data.len() as u32, 0, 0, 0, 0];
+        archive.extend_from_slice(MAGIC);
+        for field in fields {
+            archive.extend_from_slice(format!("{:08x}", field).as_bytes());
+        }
+        archive.extend_from_slice(format!("{:08x}{:08x}", name.len() + 1, 0).as_bytes());
+        archive.extend_from_slice(name.as_bytes());
+        archive.push(0);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut archive = Vec::new();
+        push_entry(&mut archive, ".", S_IFDIR | 0o755, b"");
+        push_entry(&mut archive, "bin", S_IFDIR | 0o755, b"");
+        push_entry(&mut archive, "bin/init", S_IFREG | 0o755, b"\x7fELF...");
+        push_entry(&mut archive, "etc/motd", S_IFREG | 0o644, b"hello");
+        push_entry(&mut archive, "sbin", S_IFLNK | 0o777, b"bin");
+        push_entry(&mut archive, TRAILER, 0, b"");
+        archive
+    }
+
+    #[test]
+    fn parses_padded_entries() {
+        let archive = sample();
+        let (root, next) = parse_entry(&archive, 0).unwrap();
+        assert_eq!(root.name, ".");
+        assert_eq!(next, 112);
+
+        let (bin, next) = parse_entry(&archive, next).unwrap();
+        let (init, _) = parse_entry(&archive, next).unwrap();
+        assert_eq!(bin.mode, S_IFDIR | 0o755);
+        assert_eq!(init.name, "bin/init");
+        assert_eq!(init.data, b"\x7fELF...");
+        assert_eq!((init.uid, init.gid), (1000, 100));
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let mut archive = sample();
+        archive[0] = b'1';
+        assert!(matches!(parse_entry(&archive, 0), Err(FsError::Corrupted)));
+
+        let archive = sample();
+        assert!(parse_entry(&archive[..120], 0).is_ok());
+        assert!(matches!(
+            parse_entry(&archive[..100], 0),
+            Err(FsError::Corrupted)
+        ));
+        assert!(matches!(parse_entry(&archive, 4), Err(FsError::Corrupted)));
+    }
+
+    #[test]
+    fn unpacks_into_a_directory() {
+        let fs = TmpFs::new(1 << 20);
+        let root = fs.root();
+        assert_eq!(unpack(&root, &sample()).unwrap(), 5);
+
+        let motd = root.lookup("etc").unwrap().lookup("motd").unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(motd.read_at(0, &mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(motd.stat().unwrap().mode & 0o7777, 0o644);
+        assert_eq!(root.lookup("sbin").unwrap().readlink().unwrap(), "bin");
+    }
+
+    #[test]
+    fn refuses_to_climb_out() {
+        let fs = TmpFs::new(1 << 20);
+        let mut archive = Vec::new();
+        push_entry(&mut archive, "../escape", S_IFREG | 0o644, b"x");
+        push_entry(&mut archive, TRAILER, 0, b"");
+        assert!(matches!(
+            unpack(&fs.root(), &archive),
+            Err(FsError::InvalidArgument)
+        ));
+    }
+}
@@ -1,36 +1,238 @@
+pub mod devfs;
+pub mod ext2;
 pub mod fat;
+pub mod file;
+pub mod initramfs;
+pub mod procfs;
+pub mod tmpfs;
+pub mod vfs;
+
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
 use crate::drivers::ata::{AtaDrive, Bus};
-use crate::fs::fat::Fat32Driver;
+use crate::drivers::block::{BlockDevice, DeviceNode};
+use crate::drivers::block_cache::CachedDevice;
+use crate::drivers::partition;
+use crate::fs::devfs::DevFs;
+use crate::fs::ext2::{Ext2Driver, Ext2Fs};
+use crate::fs::fat::{Fat32Driver, FatFs};
+use crate::fs::procfs::ProcFs;
+use crate::fs::tmpfs::TmpFs;
+use crate::fs::vfs::FileSystem;
 
 use spin::Mutex;
 
-pub static FILESYSTEM: Mutex<Option<Fat32Driver>> = Mutex::new(None);
+static BOOT_DISK: Mutex<Option<Arc<dyn BlockDevice>>> = Mutex::new(None);
+
+/// IDE positions in the order their `/dev` names count up.
+const DRIVES: [(&str, Bus, bool); 4] = [
+    ("hda", Bus::Primary, true),
+    ("hdb", Bus::Primary, false),
+    ("hdc", Bus::Secondary, true),
+    ("hdd", Bus::Secondary, false),
+];
+/// The drive the root filesystem is looked for on.
+const BOOT_DRIVE: &str = "hdb";
 
-pub fn init_fs() {
-    let drive = AtaDrive::new(Bus::Primary, false);
+/// A disk and the partitions found on it.
+type Disk = (Arc<dyn BlockDevice>, Vec<Arc<dyn BlockDevice>>);
 
-    let driver = Fat32Driver::new(drive);
+/// Most heap `/tmp` may take.
+const TMP_SIZE_LIMIT: usize = crate::allocator::HEAP_SIZE / 8;
+/// Most heap a root filesystem unpacked from the initramfs may take. With
+/// `/tmp` full as well, over half the heap is left for the rest of the
+/// kernel.
+const RAMDISK_ROOT_LIMIT: usize = crate::allocator::HEAP_SIZE / 4;
 
-    *FILESYSTEM.lock() = Some(driver);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotInitialized,
+    IsDirectory,
+    NotDirectory,
+    PermissionDenied,
+    /// The open file's access mode does not allow the operation.
+    BadFileMode,
+    NotSeekable,
+    InvalidArgument,
+    AlreadyExists,
+    NotEmpty,
+    Busy,
+    CrossDevice,
+    NotSupported,
+    NoSpace,
+    FileTooLarge,
+    /// On-disk structures are inconsistent or fail validation.
+    Corrupted,
+    Io,
+    /// The filesystem can only be read.
+    ReadOnly,
+    /// An inode already has as many links as it can hold.
+    TooManyLinks,
+    /// Resolving a path followed too many symlinks.
+    SymlinkLoop,
 }
 
-pub fn read_sector(lba: u32) -> Result<[u8; 512], &'static str> {
-    let mut lock = FILESYSTEM.lock();
+/// Mounts the root filesystem: the boot disk if there is one, or else the
+/// initramfs the bootloader loaded, unpacked into memory. On failure the
+/// kernel carries on without a root filesystem.
+pub fn init_fs(ramdisk: Option<&[u8]>) -> Result<(), FsError> {
+    let boot_disk = probe_disks();
+    let disk_error = match mount_boot_disk(boot_disk) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    let Some(archive) = ramdisk else {
+        return Err(disk_error);
+    };
 
-    if let Some(fs) = lock.as_mut() {
-        let mut raw_buffer = [0u16; 256];
+    crate::serial_println!("[FS] No boot disk ({:?}), using the initramfs", disk_error);
+    let fs = TmpFs::new(RAMDISK_ROOT_LIMIT);
+    let count = initramfs::unpack(&fs.root(), archive)?;
+    crate::serial_println!(
+        "[FS] Unpacked {} entries ({} bytes) from the initramfs",
+        count,
+        fs.usage().0
+    );
+    vfs::mount("/", fs)
+}
 
-        fs.drive.read(lba, 1, &mut raw_buffer)?;
+/// Looks for a drive at every IDE position and registers each one found,
+/// and each partition on it, as a block device in `/dev`. Returns the boot
+/// drive if it is present.
+fn probe_disks() -> Option<Disk> {
+    let mut boot_disk = None;
+
+    for (name, bus, is_master) in DRIVES {
+        let mut drive = AtaDrive::new(bus, is_master);
+        if drive.identify().is_err() {
+            continue;
+        }
+        let disk: Arc<dyn BlockDevice> = Arc::new(CachedDevice::new(Arc::new(Mutex::new(drive))));
+        devfs::register(name, Arc::new(DeviceNode::new(disk.clone())));
 
-        let mut byte_buffer = [0u8; 512];
-        for (i, &word) in raw_buffer.iter().enumerate() {
-            byte_buffer[i * 2] = (word & 0xFF) as u8;
-            byte_buffer[i * 2 + 1] = ((word >> 8) & 0xFF) as u8;
+        let partitions = partition::scan(&disk).unwrap_or_else(|e| {
+            crate::serial_println!("[FS] Ignoring partition table on {}: {:?}", name, e);
+            Vec::new()
+        });
+        let mut devices: Vec<Arc<dyn BlockDevice>> = Vec::new();
+        for (i, part) in partitions.into_iter().enumerate() {
+            let part_name = format!("{}{}", name, i + 1);
+            crate::serial_println!(
+                "[FS] {}: {:?} {:?} at {} ({} blocks)",
+                part_name,
+                part.kind,
+                part.name,
+                part.start,
+                part.blocks
+            );
+            let part: Arc<dyn BlockDevice> = Arc::new(part);
+            devfs::register(&part_name, Arc::new(DeviceNode::new(part.clone())));
+            devices.push(part);
         }
 
-        Ok(byte_buffer)
-    } else {
-        Err("Filesystem not initialized")
+        if name == BOOT_DRIVE {
+            boot_disk = Some((disk, devices));
+        }
     }
+    boot_disk
+}
+
+/// Mounts the boot volume at `/`: the first partition holding a FAT or ext2
+/// filesystem, or the whole disk if it has no partition table.
+fn mount_boot_disk(boot_disk: Option<Disk>) -> Result<(), FsError> {
+    let (disk, mut candidates) = boot_disk.ok_or(FsError::NotInitialized)?;
+    *BOOT_DISK.lock() = Some(disk.clone());
+
+    if candidates.is_empty() {
+        candidates.push(disk);
+    }
+
+    let mut last_error = FsError::NotFound;
+    for device in candidates {
+        match open_volume(device) {
+            Ok(fs) => {
+                vfs::mount("/", fs)?;
+                check_root();
+                return Ok(());
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Mounts an empty tmpfs at `/tmp`.
+pub fn mount_tmp() -> Result<(), FsError> {
+    vfs::mount("/tmp", TmpFs::new(TMP_SIZE_LIMIT))
+}
+
+/// Mounts the device directory at `/dev`.
+pub fn mount_dev() -> Result<(), FsError> {
+    vfs::mount("/dev", Arc::new(DevFs))
+}
+
+/// Mounts process and kernel information at `/proc`.
+pub fn mount_proc() -> Result<(), FsError> {
+    vfs::mount("/proc", Arc::new(ProcFs))
+}
+
+/// Opens the filesystem on `device`, trying FAT and then ext2. A device that
+/// is neither reports why FAT refused it.
+fn open_volume(device: Arc<dyn BlockDevice>) -> Result<Arc<dyn FileSystem>, FsError> {
+    let fat_error = match Fat32Driver::new(device.clone()) {
+        Ok(driver) => {
+            if driver.was_dirty() {
+                crate::serial_println!("[FS] Volume was not cleanly unmounted");
+            }
+            return Ok(FatFs::new(driver));
+        }
+        Err(e) => e,
+    };
+
+    let driver = match Ext2Driver::new(device) {
+        Ok(driver) => driver,
+        Err(FsError::NotSupported) => return Err(fat_error),
+        Err(e) => return Err(e),
+    };
+    if driver.was_dirty() {
+        crate::serial_println!("[FS] Volume was not cleanly unmounted");
+    }
+    if driver.is_read_only() {
+        crate::serial_println!("[FS] Volume has features that only allow reading it");
+    }
+    Ok(Ext2Fs::new(driver))
+}
+
+/// Reports problems on the root filesystem at boot without touching it;
+/// repairs are left to an explicit `fsck`.
+fn check_root() {
+    match vfs::check("/", false) {
+        Ok(problems) => {
+            for problem in &problems {
+                crate::serial_println!("[FSCK] {}", problem);
+            }
+            if !problems.is_empty() {
+                crate::serial_println!(
+                    "[FS] Root filesystem has {} problems; run fsck to repair",
+                    problems.len()
+                );
+            }
+        }
+        Err(FsError::NotSupported) => {}
+        Err(e) => {
+            crate::serial_println!("[FS] Cannot check root filesystem: {:?}", e);
+        }
+    }
+}
+
+/// Reads a sector of the boot disk, ignoring any partitioning.
+pub fn read_sector(lba: u32) -> Result<[u8; 512], FsError> {
+    let disk = BOOT_DISK.lock().clone().ok_or(FsError::NotInitialized)?;
+
+    let mut buf = [0u8; 512];
+    disk.read_blocks(lba as u64, &mut buf)?;
+    Ok(buf)
 }
@@ -0,0 +1,387 @@
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use super::FsError;
+use super::file::{FileStat, S_IFDIR, S_IFLNK, S_IFREG};
+use super::vfs::{self, DirEntry, File, FileSystem, Inode, InodeKind};
+use crate::drivers::block_cache;
+use crate::ipc::handle::{Handle, KernelObject};
+use crate::memory::pmm::PMM;
+use crate::process::{self, PROCESSES, Pid, Process};
+
+const PAGE_SIZE: u64 = 4096;
+
+/// What a file in `/proc` reports. Its contents are generated afresh each
+/// time it is opened.
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    MemInfo,
+    Uptime,
+    Interrupts,
+    Mounts,
+    Log,
+    Status(Pid),
+    Maps(Pid),
+    Cmdline(Pid),
+}
+
+const GLOBAL_FILES: [(&str, Source); 5] = [
+    ("meminfo", Source::MemInfo),
+    ("uptime", Source::Uptime),
+    ("interrupts", Source::Interrupts),
+    ("mounts", Source::Mounts),
+    ("log", Source::Log),
+];
+
+const PROCESS_FILES: [&str; 3] = ["status", "maps", "cmdline"];
+
+fn process_file(pid: Pid, name: &str) -> Option<Source> {
+    match name {
+        "status" => Some(Source::Status(pid)),
+        "maps" => Some(Source::Maps(pid)),
+        "cmdline" => Some(Source::Cmdline(pid)),
+        _ => None,
+    }
+}
+
+/// Runs `f` on the process `pid`, which fails once it has gone.
+fn with_process<R>(pid: Pid, f: impl FnOnce(&Process) -> R) -> Result<R, FsError> {
+    PROCESSES.lock().get(&pid).map(f).ok_or(FsError::NotFound)
+}
+
+/// Formats `ticks` of the timer as seconds with two decimals.
+fn seconds(ticks: u64) -> String {
+    let centis = ticks * 100 / crate::interrupts::PIT_FREQUENCY_HZ;
+    alloc::format!("{}.{:02}", centis / 100, centis % 100)
+}
+
+fn generate(source: Source) -> Result<Vec<u8>, FsError> {
+    let mut out = String::new();
+
+    match source {
+        Source::MemInfo => {
+            let (used, total) = PMM.lock().as_ref().map_or((0, 0), |pmm| pmm.stats());
+            let (heap_used, heap_total) = crate::allocator::heap_usage();
+            let cache = block_cache::stats();
+
+            let kib = |frames: usize| frames as u64 * PAGE_SIZE / 1024;
+            let _ = writeln!(out, "MemTotal:     {:>10} kB", kib(total));
+            let _ = writeln!(out, "MemFree:      {:>10} kB", kib(total - used));
+            let _ = writeln!(out, "HeapTotal:    {:>10} kB", heap_total / 1024);
+            let _ = writeln!(out, "HeapUsed:     {:>10} kB", heap_used / 1024);
+            let _ = writeln!(out, "CachedBlocks: {:>10}", cache.blocks);
+            let _ = writeln!(out, "DirtyBlocks:  {:>10}", cache.dirty);
+        }
+        Source::Uptime => {
+            let _ = writeln!(out, "{}", seconds(crate::interrupts::ticks()));
+        }
+        Source::Interrupts => {
+            for (vector, name) in crate::interrupts::HANDLED_VECTORS {
+                let count = crate::interrupts::interrupt_count(vector);
+                let _ = writeln!(out, "{:>3}: {:>10}  {}", vector, count, name);
+            }
+        }
+        Source::Mounts => {
+            for (path, name) in vfs::mounts() {
+                let _ = writeln!(out, "{} {}", path, name);
+            }
+        }
+        Source::Log => return Ok(crate::serial::log_contents()),
+        Source::Status(pid) => with_process(pid, |process| {
+            let mapped: u64 = process
+                .regions
+                .iter()
+                .map(|region| region.end - region.start)
+                .chain(process.shm_mappings.iter().map(|m| m.object.size() as u64))
+                .sum();
+
+            let _ = writeln!(out, "Name:\t{}", process.name);
+            let _ = writeln!(out, "Pid:\t{}", process.pid);
+            let _ = writeln!(out, "Cwd:\t{}", process.cwd);
+            let _ = writeln!(out, "Handles:\t{}", process.handles.len());
+            let _ = writeln!(out, "VmSize:\t{} kB", mapped / 1024);
+            let _ = writeln!(out, "Started:\t{}", seconds(process.started));
+        })?,
+        Source::Maps(pid) => with_process(pid, |process| {
+            for region in &process.regions {
+                let _ = writeln!(
+                    out,
+                    "{:016x}-{:016x} r{}{}p {}",
+                    region.start.as_u64(),
+                    region.end.as_u64(),
+                    if region.writable { 'w' } else { '-' },
+                    if region.executable { 'x' } else { '-' },
+                    region.label
+                );
+            }
+            for mapping in &process.shm_mappings {
+                let _ = writeln!(
+                    out,
+                    "{:016x}-{:016x} r{}-s [shm]",
+                    mapping.base.as_u64(),
+                    mapping.base.as_u64() + mapping.object.size() as u64,
+                    if mapping.writable { 'w' } else { '-' }
+                );
+            }
+        })?,
+        Source::Cmdline(pid) => with_process(pid, |process| {
+            out.push_str(&process.name);
+            out.push('\0');
+        })?,
+    }
+
+    Ok(out.into_bytes())
+}
+
+/// Process information and kernel statistics as files. Each process has a
+/// directory named by its PID, and `self` links to the caller's. PIDs are
+/// never reused, so a directory cached after its process exits only fails
+/// with `NotFound`.
+pub struct ProcFs;
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &'static str {
+        "proc"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(ProcRoot)
+    }
+}
+
+fn dir_stat(nlink: u32) -> Result<FileStat, FsError> {
+    Ok(FileStat {
+        mode: S_IFDIR | 0o555,
+        nlink,
+        ..FileStat::default()
+    })
+}
+
+/// Numbers directory entries in the order given.
+fn numbered(entries: Vec<(String, InodeKind)>) -> Vec<DirEntry> {
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, kind))| DirEntry {
+            name,
+            kind,
+            size: 0,
+            id: i as u64 + 1,
+        })
+        .collect()
+}
+
+struct ProcRoot;
+
+impl Inode for ProcRoot {
+    fn kind(&self) -> InodeKind {
+        InodeKind::Directory
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        dir_stat(2 + PROCESSES.lock().len() as u32)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        if name == "self" {
+            return Ok(Arc::new(SelfLink));
+        }
+        if let Some(&(_, source)) = GLOBAL_FILES.iter().find(|(file, _)| *file == name) {
+            return Ok(Arc::new(ProcFile { source }));
+        }
+
+        let pid: Pid = name.parse().map_err(|_| FsError::NotFound)?;
+        with_process(pid, |_| ())?;
+        Ok(Arc::new(ProcessDir { pid }))
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let mut entries: Vec<(String, InodeKind)> = GLOBAL_FILES
+            .iter()
+            .map(|(name, _)| (name.to_string(), InodeKind::File))
+            .collect();
+        entries.push((String::from("self"), InodeKind::Symlink));
+        entries.extend(
+            PROCESSES
+                .lock()
+                .keys()
+                .map(|pid| (pid.to_string(), InodeKind::Directory)),
+        );
+        Ok(numbered(entries))
+    }
+}
+
+struct ProcessDir {
+    pid: Pid,
+}
+
+impl Inode for ProcessDir {
+    fn kind(&self) -> InodeKind {
+        InodeKind::Directory
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        with_process(self.pid, |_| ())?;
+        dir_stat(3)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        with_process(self.pid, |_| ())?;
+        if name == "fd" {
+            return Ok(Arc::new(FdDir { pid: self.pid }));
+        }
+        let source = process_file(self.pid, name).ok_or(FsError::NotFound)?;
+        Ok(Arc::new(ProcFile { source }))
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        with_process(self.pid, |_| ())?;
+        let mut entries: Vec<(String, InodeKind)> = PROCESS_FILES
+            .iter()
+            .map(|name| (name.to_string(), InodeKind::File))
+            .collect();
+        entries.push((String::from("fd"), InodeKind::Directory));
+        Ok(numbered(entries))
+    }
+}
+
+/// A process's open handles, each a symlink named by its number.
+struct FdDir {
+    pid: Pid,
+}
+
+impl Inode for FdDir {
+    fn kind(&self) -> InodeKind {
+        InodeKind::Directory
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        with_process(self.pid, |_| ())?;
+        dir_stat(2)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let handle: Handle = name.parse().map_err(|_| FsError::NotFound)?;
+        let target = with_process(self.pid, |process| {
+            process.handles.get(handle).map(describe)
+        })?;
+        let target = target.ok_or(FsError::NotFound)?;
+        Ok(Arc::new(Link { target }))
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let entries = with_process(self.pid, |process| {
+            process
+                .handles
+                .iter()
+                .map(|(handle, _)| (handle.to_string(), InodeKind::Symlink))
+                .collect()
+        })?;
+        Ok(numbered(entries))
+    }
+}
+
+/// What a handle refers to: the path of a file, or the kind of object.
+fn describe(object: &KernelObject) -> String {
+    match object {
+        KernelObject::File(file) => String::from(file.path()),
+        KernelObject::Channel(_) => String::from("[channel]"),
+        KernelObject::SharedMemory(_) => String::from("[shm]"),
+    }
+}
+
+fn link_stat(target: &str) -> Result<FileStat, FsError> {
+    Ok(FileStat {
+        mode: S_IFLNK | 0o777,
+        nlink: 1,
+        size: target.len() as u64,
+        ..FileStat::default()
+    })
+}
+
+/// A symlink to a fixed target, taken when it was looked up.
+struct Link {
+    target: String,
+}
+
+impl Inode for Link {
+    fn kind(&self) -> InodeKind {
+        InodeKind::Symlink
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        link_stat(&self.target)
+    }
+
+    fn readlink(&self) -> Result<String, FsError> {
+        Ok(self.target.clone())
+    }
+}
+
+/// `/proc/self`, which points at the directory of whichever process reads it.
+struct SelfLink;
+
+impl Inode for SelfLink {
+    fn kind(&self) -> InodeKind {
+        InodeKind::Symlink
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        link_stat(&process::current_pid().to_string())
+    }
+
+    fn readlink(&self) -> Result<String, FsError> {
+        Ok(process::current_pid().to_string())
+    }
+}
+
+struct ProcFile {
+    source: Source,
+}
+
+impl Inode for ProcFile {
+    fn kind(&self) -> InodeKind {
+        InodeKind::File
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        Ok(FileStat {
+            mode: S_IFREG | 0o444,
+            nlink: 1,
+            size: generate(self.source)?.len() as u64,
+            ..FileStat::default()
+        })
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        Snapshot(generate(self.source)?).read(offset, buf)
+    }
+
+    fn open(&self) -> Result<Option<Arc<dyn File>>, FsError> {
+        let data = generate(self.source)?;
+        Ok(Some(Arc::new(Snapshot(data))))
+    }
+}
+
+/// The contents of a `/proc` file as they were when it was opened, so that
+/// reading it in pieces gives a consistent whole.
+struct Snapshot(Vec<u8>);
+
+impl File for Snapshot {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let data = self.0.get(offset as usize..).unwrap_or(&[]);
+        let count = buf.len().min(data.len());
+        buf[..count].copy_from_slice(&data[..count]);
+        Ok(count)
+    }
+
+    fn write(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn size(&self) -> Result<u64, FsError> {
+        Ok(self.0.len() as u64)
+    }
+}
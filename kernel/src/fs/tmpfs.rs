@@ -0,0 +1,658 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use super::FsError;
+use super::file::{FileStat, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG};
+use super::vfs::{DirEntry, FileSystem, Inode, InodeKind, path};
+use crate::drivers::rtc;
+
+const ROOT_INO: u64 = 1;
+const NAME_MAX: usize = 255;
+/// Block size reported by `stat`. Data is not stored in blocks, but
+/// programs size their buffers by it.
+const BLOCK_SIZE: u64 = 4096;
+/// Charged for every node on top of its content, roughly what the node
+/// itself takes from the heap, so empty files still count against the limit.
+const NODE_COST: usize = 256;
+/// Charged for every directory entry on top of the length of its name.
+const ENTRY_COST: usize = 64;
+
+fn now() -> u64 {
+    rtc::unix_time()
+}
+
+fn check_name(name: &str) -> Result<(), FsError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(FsError::InvalidArgument);
+    }
+    if name.len() > NAME_MAX {
+        return Err(FsError::InvalidArgument);
+    }
+    Ok(())
+}
+
+fn entry_cost(name: &str) -> usize {
+    ENTRY_COST + name.len()
+}
+
+/// Heap held by one filesystem, against its size limit: every node and
+/// directory entry, and the capacity of file data and symlink targets.
+struct Space {
+    used: Mutex<usize>,
+    limit: usize,
+}
+
+impl Space {
+    fn reserve(&self, bytes: usize) -> Result<(), FsError> {
+        let mut used = self.used.lock();
+        match used.checked_add(bytes) {
+            Some(total) if total <= self.limit => {
+                *used = total;
+                Ok(())
+            }
+            _ => Err(FsError::NoSpace),
+        }
+    }
+
+    fn release(&self, bytes: usize) {
+        *self.used.lock() -= bytes;
+    }
+
+    /// Makes room in `data` for `len` bytes, charging whatever capacity it
+    /// gains so that the charge always matches the capacity.
+    fn grow(&self, data: &mut Vec<u8>, len: usize) -> Result<(), FsError> {
+        let capacity = data.capacity();
+        if len <= capacity {
+            return Ok(());
+        }
+
+        self.reserve(len - capacity)?;
+        if data.try_reserve_exact(len - data.len()).is_err() {
+            self.release(len - capacity);
+            return Err(FsError::NoSpace);
+        }
+        // The allocator is free to hand out more than asked for.
+        *self.used.lock() += data.capacity() - len;
+        Ok(())
+    }
+
+    /// Gives back the capacity of `data` once it is mostly unused.
+    fn shrink(&self, data: &mut Vec<u8>) {
+        let capacity = data.capacity();
+        if data.len() < capacity / 2 {
+            data.shrink_to_fit();
+            self.release(capacity - data.capacity());
+        }
+    }
+}
+
+enum Content {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<Node>>),
+    Symlink(String),
+}
+
+impl Content {
+    /// Bytes charged against the size limit, besides `NODE_COST`.
+    fn charged(&self) -> usize {
+        match self {
+            Content::File(data) => data.capacity(),
+            Content::Directory(entries) => entries.keys().map(|name| entry_cost(name)).sum(),
+            Content::Symlink(target) => target.capacity(),
+        }
+    }
+}
+
+struct NodeState {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    /// Names referring to this node. Directories count only the one in their
+    /// parent; `stat` adds `.` and the `..` of each subdirectory.
+    links: u32,
+    atime: u64,
+    mtime: u64,
+    btime: u64,
+    content: Content,
+}
+
+/// A file, directory or symlink. Directory entries and open handles share
+/// it, so an unlinked file lives on until the last handle goes away.
+struct Node {
+    ino: u64,
+    kind: InodeKind,
+    space: Arc<Space>,
+    state: Mutex<NodeState>,
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.space
+            .release(NODE_COST + self.state.get_mut().content.charged());
+    }
+}
+
+impl Node {
+    fn child(&self, name: &str) -> Result<Arc<Node>, FsError> {
+        match &self.state.lock().content {
+            Content::Directory(entries) => entries.get(name).cloned().ok_or(FsError::NotFound),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+
+    fn is_empty_dir(&self) -> bool {
+        matches!(&self.state.lock().content, Content::Directory(entries) if entries.is_empty())
+    }
+
+    /// Applies `change` to the entries of this directory and updates its
+    /// modification time.
+    fn edit_dir<R>(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, Arc<Node>>) -> R,
+    ) -> Result<R, FsError> {
+        let mut state = self.state.lock();
+        let Content::Directory(entries) = &mut state.content else {
+            return Err(FsError::NotDirectory);
+        };
+        let result = change(entries);
+        state.mtime = now();
+        Ok(result)
+    }
+
+    fn adjust_links(&self, delta: i32) {
+        let mut state = self.state.lock();
+        state.links = state.links.saturating_add_signed(delta);
+    }
+}
+
+pub struct TmpFs {
+    root: Arc<Node>,
+    space: Arc<Space>,
+    next_ino: AtomicU64,
+    /// Held across every change to the namespace, so operations spanning two
+    /// directories see a consistent tree while locking one node at a time.
+    namespace: Mutex<()>,
+    this: Weak<TmpFs>,
+}
+
+impl TmpFs {
+    /// Creates an empty filesystem taking at most `limit` bytes of heap.
+    pub fn new(limit: usize) -> Arc<Self> {
+        // The root is charged like any other node, even if it does not fit.
+        let space = Arc::new(Space {
+            used: Mutex::new(NODE_COST),
+            limit,
+        });
+        let root = Self::make_node(
+            &space,
+            ROOT_INO,
+            S_IFDIR | 0o1777,
+            Content::Directory(BTreeMap::new()),
+        );
+
+        Arc::new_cyclic(|this| Self {
+            root,
+            space,
+            next_ino: AtomicU64::new(ROOT_INO + 1),
+            namespace: Mutex::new(()),
+            this: this.clone(),
+        })
+    }
+
+    /// Bytes in use and the limit.
+    pub fn usage(&self) -> (usize, usize) {
+        (*self.space.used.lock(), self.space.limit)
+    }
+
+    fn make_node(space: &Arc<Space>, ino: u64, mode: u32, content: Content) -> Arc<Node> {
+        let kind = match mode & S_IFMT {
+            S_IFDIR => InodeKind::Directory,
+            S_IFLNK => InodeKind::Symlink,
+            _ => InodeKind::File,
+        };
+        let time = now();
+
+        Arc::new(Node {
+            ino,
+            kind,
+            space: space.clone(),
+            state: Mutex::new(NodeState {
+                mode,
+                uid: 0,
+                gid: 0,
+                links: 1,
+                atime: time,
+                mtime: time,
+                btime: time,
+                content,
+            }),
+        })
+    }
+
+    fn handle(&self, node: Arc<Node>) -> Arc<dyn Inode> {
+        Arc::new(TmpInode {
+            fs: self.this.upgrade().expect("TmpFs dropped while in use"),
+            node,
+        })
+    }
+
+    /// Adds a new node named `name` to `dir`, charging for the node, its
+    /// entry and any symlink target. Files start empty.
+    fn add_node(
+        &self,
+        dir: &Node,
+        name: &str,
+        mode: u32,
+        content: Content,
+    ) -> Result<Arc<Node>, FsError> {
+        check_name(name)?;
+        let _namespace = self.namespace.lock();
+        if dir.state.lock().links == 0 {
+            return Err(FsError::NotFound);
+        }
+
+        let entry = entry_cost(name);
+        self.space.reserve(NODE_COST + content.charged() + entry)?;
+        let ino = self.next_ino.fetch_add(1, Ordering::Relaxed);
+        let node = Self::make_node(&self.space, ino, mode, content);
+
+        let added = dir.edit_dir(|entries| {
+            if entries.contains_key(name) {
+                return false;
+            }
+            entries.insert(name.to_string(), node.clone());
+            true
+        });
+        if added != Ok(true) {
+            // Dropping the node gives back what it was charged.
+            self.space.release(entry);
+            added?;
+            return Err(FsError::AlreadyExists);
+        }
+        Ok(node)
+    }
+
+    /// Walks a path from the root of the filesystem to the directory holding
+    /// its final component. Symlinks along the way are not followed.
+    fn resolve_parent<'a>(&self, path: &'a str) -> Result<(Arc<Node>, &'a str), FsError> {
+        let (parent, name) = path::split_parent(path).ok_or(FsError::Busy)?;
+
+        let mut dir = self.root.clone();
+        for component in parent.split('/').filter(|c| !c.is_empty()) {
+            dir = dir.child(component)?;
+        }
+        Ok((dir, name))
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.handle(self.root.clone())
+    }
+
+    fn rename(&self, old_path: &str, new_path: &str) -> Result<(), FsError> {
+        let (old_dir, old_name) = self.resolve_parent(old_path)?;
+        let (new_dir, new_name) = self.resolve_parent(new_path)?;
+        check_name(new_name)?;
+
+        let _namespace = self.namespace.lock();
+        let node = old_dir.child(old_name)?;
+        let is_dir = node.kind == InodeKind::Directory;
+
+        let replaced = match new_dir.child(new_name) {
+            Ok(existing) if Arc::ptr_eq(&existing, &node) => return Ok(()),
+            Ok(existing) => Some(existing),
+            Err(FsError::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+        if let Some(existing) = &replaced {
+            match (is_dir, existing.kind == InodeKind::Directory) {
+                (true, false) => return Err(FsError::NotDirectory),
+                (false, true) => return Err(FsError::IsDirectory),
+                (true, true) if !existing.is_empty_dir() => return Err(FsError::NotEmpty),
+                _ => {}
+            }
+        } else {
+            self.space.reserve(entry_cost(new_name))?;
+        }
+
+        old_dir.edit_dir(|entries| entries.remove(old_name))?;
+        new_dir.edit_dir(|entries| entries.insert(new_name.to_string(), node))?;
+        self.space.release(entry_cost(old_name));
+        if let Some(existing) = replaced {
+            existing.adjust_links(-1);
+        }
+        Ok(())
+    }
+
+    fn link(&self, old_path: &str, new_path: &str) -> Result<(), FsError> {
+        let (old_dir, old_name) = self.resolve_parent(old_path)?;
+        let (new_dir, new_name) = self.resolve_parent(new_path)?;
+        check_name(new_name)?;
+
+        let _namespace = self.namespace.lock();
+        let node = old_dir.child(old_name)?;
+        if node.kind == InodeKind::Directory {
+            return Err(FsError::IsDirectory);
+        }
+
+        let entry = entry_cost(new_name);
+        self.space.reserve(entry)?;
+        let added = new_dir.edit_dir(|entries| {
+            if entries.contains_key(new_name) {
+                return false;
+            }
+            entries.insert(new_name.to_string(), node.clone());
+            true
+        });
+        if added != Ok(true) {
+            self.space.release(entry);
+            added?;
+            return Err(FsError::AlreadyExists);
+        }
+        node.adjust_links(1);
+        Ok(())
+    }
+}
+
+struct TmpInode {
+    fs: Arc<TmpFs>,
+    node: Arc<Node>,
+}
+
+impl TmpInode {
+    fn check_dir(&self) -> Result<(), FsError> {
+        if self.node.kind != InodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+        Ok(())
+    }
+
+    fn check_file(&self) -> Result<(), FsError> {
+        match self.node.kind {
+            InodeKind::File => Ok(()),
+            InodeKind::Directory => Err(FsError::IsDirectory),
+            _ => Err(FsError::NotSupported),
+        }
+    }
+}
+
+impl Inode for TmpInode {
+    fn kind(&self) -> InodeKind {
+        self.node.kind
+    }
+
+    fn stat(&self) -> Result<FileStat, FsError> {
+        let state = self.node.state.lock();
+        let (size, nlink) = match &state.content {
+            Content::File(data) => (data.len() as u64, state.links),
+            Content::Symlink(target) => (target.len() as u64, state.links),
+            Content::Directory(entries) => {
+                let subdirs = entries
+                    .values()
+                    .filter(|node| node.kind == InodeKind::Directory)
+                    .count() as u32;
+                let nlink = if state.links == 0 { 0 } else { 2 + subdirs };
+                (0, nlink)
+            }
+        };
+
+        Ok(FileStat {
+            mode: state.mode,
+            nlink,
+            size,
+            blksize: BLOCK_SIZE,
+            blocks: state.content.charged().div_ceil(512) as u64,
+            atime: state.atime,
+            mtime: state.mtime,
+            btime: state.btime,
+            ino: self.node.ino,
+            uid: state.uid,
+            gid: state.gid,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_dir()?;
+        Ok(self.fs.handle(self.node.child(name)?))
+    }
+
+    fn create(&self, name: &str, kind: InodeKind) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_dir()?;
+        let (mode, content) = match kind {
+            InodeKind::File => (S_IFREG | 0o644, Content::File(Vec::new())),
+            InodeKind::Directory => (S_IFDIR | 0o755, Content::Directory(BTreeMap::new())),
+            _ => return Err(FsError::NotSupported),
+        };
+
+        let node = self.fs.add_node(&self.node, name, mode, content)?;
+        Ok(self.fs.handle(node))
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_dir()?;
+        if target.is_empty() {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let content = Content::Symlink(target.to_string());
+        let node = self
+            .fs
+            .add_node(&self.node, name, S_IFLNK | 0o777, content)?;
+        Ok(self.fs.handle(node))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        self.check_dir()?;
+        let _namespace = self.fs.namespace.lock();
+        let node = self.node.child(name)?;
+        if node.kind == InodeKind::Directory {
+            return Err(FsError::IsDirectory);
+        }
+
+        self.node.edit_dir(|entries| entries.remove(name))?;
+        self.fs.space.release(entry_cost(name));
+        node.adjust_links(-1);
+        Ok(())
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), FsError> {
+        self.check_dir()?;
+        let _namespace = self.fs.namespace.lock();
+        let node = self.node.child(name)?;
+        if node.kind != InodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+        if !node.is_empty_dir() {
+            return Err(FsError::NotEmpty);
+        }
+
+        self.node.edit_dir(|entries| entries.remove(name))?;
+        self.fs.space.release(entry_cost(name));
+        node.adjust_links(-1);
+        Ok(())
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        self.check_dir()?;
+        let entries: Vec<(String, Arc<Node>)> = match &self.node.state.lock().content {
+            Content::Directory(entries) => entries
+                .iter()
+                .map(|(name, node)| (name.clone(), node.clone()))
+                .collect(),
+            _ => return Err(FsError::NotDirectory),
+        };
+
+        Ok(entries
+            .into_iter()
+            .map(|(name, node)| {
+                let size = match &node.state.lock().content {
+                    Content::File(data) => data.len() as u64,
+                    Content::Symlink(target) => target.len() as u64,
+                    Content::Directory(_) => 0,
+                };
+                DirEntry {
+                    name,
+                    kind: node.kind,
+                    size,
+                    id: node.ino,
+                }
+            })
+            .collect())
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        self.check_file()?;
+        let mut state = self.node.state.lock();
+        let Content::File(data) = &state.content else {
+            return Err(FsError::NotSupported);
+        };
+
+        let start = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(data.len());
+        let count = buf.len().min(data.len() - start);
+        buf[..count].copy_from_slice(&data[start..start + count]);
+        state.atime = now();
+        Ok(count)
+    }
+
+    /// Writes as much of `buf` as the size limit allows, failing only when
+    /// nothing fits.
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        self.check_file()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut state = self.node.state.lock();
+        let Content::File(data) = &mut state.content else {
+            return Err(FsError::NotSupported);
+        };
+
+        let start = usize::try_from(offset).map_err(|_| FsError::FileTooLarge)?;
+        let mut end = start.checked_add(buf.len()).ok_or(FsError::FileTooLarge)?;
+        if end > data.len() {
+            let (used, limit) = self.fs.usage();
+            let room = data.capacity() + limit.saturating_sub(used);
+            end = end.min(room);
+            if end <= start {
+                return Err(FsError::NoSpace);
+            }
+            self.fs.space.grow(data, end)?;
+            data.resize(end, 0);
+        }
+
+        let count = end - start;
+        data[start..end].copy_from_slice(&buf[..count]);
+        state.mtime = now();
+        Ok(count)
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        self.check_file()?;
+        let size = usize::try_from(size).map_err(|_| FsError::FileTooLarge)?;
+
+        let mut state = self.node.state.lock();
+        let Content::File(data) = &mut state.content else {
+            return Err(FsError::NotSupported);
+        };
+
+        self.fs.space.grow(data, size)?;
+        data.resize(size, 0);
+        self.fs.space.shrink(data);
+        state.mtime = now();
+        Ok(())
+    }
+
+    fn readlink(&self) -> Result<String, FsError> {
+        match &self.node.state.lock().content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn set_mode(&self, mode: u32) -> Result<(), FsError> {
+        let mut state = self.node.state.lock();
+        state.mode = (state.mode & S_IFMT) | (mode & 0o7777);
+        Ok(())
+    }
+
+    fn set_owner(&self, uid: u32, gid: u32) -> Result<(), FsError> {
+        let mut state = self.node.state.lock();
+        state.uid = uid;
+        state.gid = gid;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test]
+    fn charges_nodes_entries_and_capacity() {
+        let fs = TmpFs::new(64 * 1024);
+        let root = fs.root();
+        assert_eq!(fs.usage().0, NODE_COST);
+
+        let file = root.create("file", InodeKind::File).unwrap();
+        let with_file = NODE_COST * 2 + entry_cost("file");
+        assert_eq!(fs.usage().0, with_file);
+
+        file.write_at(0, &[1; 1000]).unwrap();
+        assert_eq!(fs.usage().0, with_file + 1000);
+        file.truncate(900).unwrap();
+        assert_eq!(fs.usage().0, with_file + 1000);
+        file.truncate(10).unwrap();
+        assert_eq!(fs.usage().0, with_file + 10);
+
+        fs.link("/file", "/other").unwrap();
+        assert_eq!(fs.usage().0, with_file + 10 + entry_cost("other"));
+        fs.rename("/other", "/renamed").unwrap();
+        assert_eq!(fs.usage().0, with_file + 10 + entry_cost("renamed"));
+
+        root.unlink("file").unwrap();
+        root.unlink("renamed").unwrap();
+        assert_eq!(fs.usage().0, NODE_COST * 2 + 10);
+        drop(file);
+        assert_eq!(fs.usage().0, NODE_COST);
+    }
+
+    #[test]
+    fn empty_files_use_up_space() {
+        let fs = TmpFs::new(4096);
+        let root = fs.root();
+
+        let mut count = 0;
+        let error = loop {
+            match root.create(&format!("f{count}"), InodeKind::File) {
+                Ok(_) => count += 1,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(error, FsError::NoSpace);
+        assert!(count < 4096 / NODE_COST);
+    }
+
+    #[test]
+    fn writes_stop_at_the_limit() {
+        let fs = TmpFs::new(8192);
+        let file = fs.root().create("f", InodeKind::File).unwrap();
+        let (used, limit) = fs.usage();
+
+        let room = limit - used;
+        assert_eq!(file.write_at(0, &[0; 16384]), Ok(room));
+        assert_eq!(file.write_at(room as u64, b"x"), Err(FsError::NoSpace));
+        assert_eq!(fs.usage().0, limit);
+    }
+}
@@ -0,0 +1,467 @@
+pub mod mount;
+pub mod path;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::FsError;
+use super::file::FileStat;
+use crate::ipc::poll::PollFlags;
+use crate::ipc::shm::SharedMemory;
+use crate::ipc::wait::WaitQueue;
+
+pub use mount::{mount, mounts, sync, unmount};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InodeKind {
+    File,
+    Directory,
+    CharDevice,
+    BlockDevice,
+    Symlink,
+}
+
+pub struct DirEntry {
+    pub name: String,
+    pub kind: InodeKind,
+    pub size: u64,
+    pub id: u64,
+}
+
+pub trait FileSystem: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Moves an entry within this filesystem. Both paths are relative to
+    /// its root.
+    fn rename(&self, _old_path: &str, _new_path: &str) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Adds `new_path` as another name for the non-directory at `old_path`.
+    fn link(&self, _old_path: &str, _new_path: &str) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Whether resolved paths may be cached. A filesystem that matches
+    /// names loosely opts out, since a cached path would outlive changes
+    /// made under another spelling of it.
+    fn caches_lookups(&self) -> bool {
+        true
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+
+    /// Called once when the filesystem is detached, to leave it consistent
+    /// on disk. It is not used afterwards.
+    fn unmount(&self) -> Result<(), FsError> {
+        self.sync()
+    }
+
+    /// Checks the on-disk structures for consistency and, with `repair`,
+    /// fixes what it can. Returns a description of each problem found.
+    fn check(&self, _repair: bool) -> Result<Vec<String>, FsError> {
+        Err(FsError::NotSupported)
+    }
+}
+
+/// A node in some filesystem's namespace. Directory operations default to
+/// `NotDirectory` and data operations to `IsDirectory`, so each filesystem
+/// only implements what its node types support.
+pub trait Inode: Send + Sync {
+    fn kind(&self) -> InodeKind;
+    fn stat(&self) -> Result<FileStat, FsError>;
+
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn create(&self, _name: &str, _kind: InodeKind) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    /// Removes the non-directory entry `name`.
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    /// Removes the empty subdirectory `name`.
+    fn rmdir(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Err(FsError::IsDirectory)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::IsDirectory)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(FsError::IsDirectory)
+    }
+
+    fn readlink(&self) -> Result<String, FsError> {
+        Err(FsError::InvalidArgument)
+    }
+
+    /// Replaces the permission bits, on filesystems that keep them.
+    fn set_mode(&self, _mode: u32) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn set_owner(&self, _uid: u32, _gid: u32) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Nodes that keep per-open state, devices among them, hand out the
+    /// object that services their reads and writes. The rest are read and
+    /// written through the inode itself.
+    fn open(&self) -> Result<Option<Arc<dyn File>>, FsError> {
+        Ok(None)
+    }
+}
+
+/// The operations behind an open file description.
+pub trait File: Send + Sync {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError>;
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError>;
+
+    fn size(&self) -> Result<u64, FsError> {
+        Ok(0)
+    }
+
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn readiness(&self) -> PollFlags {
+        PollFlags::READABLE | PollFlags::WRITABLE
+    }
+
+    fn wait_queue(&self) -> Option<&'static WaitQueue> {
+        None
+    }
+
+    /// Carries out a device-specific `request`. Data travels through `buf`,
+    /// whose layout each request defines; returns how many bytes of it were
+    /// filled in.
+    fn ioctl(&self, _request: u32, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    /// The memory behind a device that can be mapped into an address space
+    /// whole.
+    fn mmap(&self) -> Result<Arc<SharedMemory>, FsError> {
+        Err(FsError::NotSupported)
+    }
+}
+
+/// Serves regular files and directories straight from their inode.
+struct InodeFile(Arc<dyn Inode>);
+
+impl File for InodeFile {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        self.0.read_at(offset, buf)
+    }
+
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        self.0.write_at(offset, buf)
+    }
+
+    fn size(&self) -> Result<u64, FsError> {
+        Ok(self.0.stat()?.size)
+    }
+}
+
+pub fn open_inode(inode: &Arc<dyn Inode>) -> Result<Arc<dyn File>, FsError> {
+    Ok(inode
+        .open()?
+        .unwrap_or_else(|| Arc::new(InodeFile(inode.clone()))))
+}
+
+pub fn lookup(cwd: &str, path: &str) -> Result<Arc<dyn Inode>, FsError> {
+    mount::resolve(&path::normalize(cwd, path))
+}
+
+/// Like `lookup`, but a symlink in the final component is returned itself
+/// rather than followed.
+pub fn lookup_link(cwd: &str, path: &str) -> Result<Arc<dyn Inode>, FsError> {
+    let full = path::normalize(cwd, path);
+    let is_mount_point = mount::mount_for(&full).is_some_and(|(mount_path, _)| mount_path == full);
+
+    match path::split_parent(&full) {
+        Some((parent, name)) if !is_mount_point => mount::resolve(parent)?.lookup(name),
+        _ => mount::resolve(&full),
+    }
+}
+
+/// Lists `dir`, the directory at `path`, along with the mount points right
+/// below it that have no entry of their own there. Those are listed with
+/// the inode number of the mounted root.
+pub fn read_dir(path: &str, dir: &Arc<dyn Inode>) -> Result<Vec<DirEntry>, FsError> {
+    let mut entries = dir.read_dir()?;
+
+    for (mount_path, _) in mount::mounts() {
+        if let Some((parent, name)) = path::split_parent(&mount_path)
+            && parent == path
+            && !entries.iter().any(|entry| entry.name == name)
+            && let Some(fs) = mount::mount_at(&mount_path)
+        {
+            entries.push(DirEntry {
+                name: String::from(name),
+                kind: InodeKind::Directory,
+                size: 0,
+                id: fs.root().stat()?.ino,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Resolves the directory holding `name`, along with the path `name` has
+/// once the symlinks leading to it are followed. Cached lookups are keyed
+/// by that path, so it is the one to check for mounts and invalidate.
+fn resolve_entry(parent: &str, name: &str) -> Result<(Arc<dyn Inode>, String), FsError> {
+    let (parent, dir) = mount::resolve_path(parent)?;
+    Ok((dir, path::join(&parent, name)))
+}
+
+/// The path of the entry at `full` once the symlinks leading to it are
+/// followed, which is where the filesystem will look for it. The root,
+/// which is no entry, fails with `root_error`.
+fn entry_path(full: &str, root_error: FsError) -> Result<String, FsError> {
+    let (parent, name) = path::split_parent(full).ok_or(root_error)?;
+    let (_, full) = resolve_entry(parent, name)?;
+    Ok(full)
+}
+
+pub fn create(cwd: &str, path: &str, kind: InodeKind) -> Result<Arc<dyn Inode>, FsError> {
+    let full = path::normalize(cwd, path);
+    let (parent, name) = path::split_parent(&full).ok_or(FsError::AlreadyExists)?;
+    let (dir, full) = resolve_entry(parent, name)?;
+    if mount::mount_at(&full).is_some() {
+        return Err(FsError::AlreadyExists);
+    }
+
+    let inode = dir.create(name, kind)?;
+    mount::invalidate(&full);
+    Ok(inode)
+}
+
+/// Creates a symlink at `path` holding `target`, which is stored as given
+/// and resolved only when the link is followed.
+pub fn symlink(cwd: &str, target: &str, path: &str) -> Result<Arc<dyn Inode>, FsError> {
+    let full = path::normalize(cwd, path);
+    let (parent, name) = path::split_parent(&full).ok_or(FsError::AlreadyExists)?;
+    let (dir, full) = resolve_entry(parent, name)?;
+    if mount::mount_at(&full).is_some() {
+        return Err(FsError::AlreadyExists);
+    }
+
+    let inode = dir.symlink(name, target)?;
+    mount::invalidate(&full);
+    Ok(inode)
+}
+
+/// Adds `new` as a hard link to `old`. Both must be on the same mounted
+/// filesystem.
+pub fn link(cwd: &str, old: &str, new: &str) -> Result<(), FsError> {
+    let old = entry_path(&path::normalize(cwd, old), FsError::IsDirectory)?;
+    let new = entry_path(&path::normalize(cwd, new), FsError::AlreadyExists)?;
+
+    let (old_mount, fs) = mount::mount_for(&old).ok_or(FsError::NotInitialized)?;
+    let (new_mount, _) = mount::mount_for(&new).ok_or(FsError::NotInitialized)?;
+    if old_mount != new_mount {
+        return Err(FsError::CrossDevice);
+    }
+    if new == new_mount {
+        return Err(FsError::AlreadyExists);
+    }
+
+    fs.link(
+        mount::relative(&old, &old_mount),
+        mount::relative(&new, &new_mount),
+    )?;
+    mount::invalidate(&new);
+    Ok(())
+}
+
+pub fn rmdir(cwd: &str, path: &str) -> Result<(), FsError> {
+    let full = path::normalize(cwd, path);
+    let (parent, name) = path::split_parent(&full).ok_or(FsError::Busy)?;
+    let (dir, full) = resolve_entry(parent, name)?;
+    if mount::mount_at(&full).is_some() {
+        return Err(FsError::Busy);
+    }
+
+    dir.rmdir(name)?;
+    mount::invalidate(&full);
+    Ok(())
+}
+
+pub fn unlink(cwd: &str, path: &str) -> Result<(), FsError> {
+    let full = path::normalize(cwd, path);
+    let (parent, name) = path::split_parent(&full).ok_or(FsError::IsDirectory)?;
+    let (dir, full) = resolve_entry(parent, name)?;
+
+    dir.unlink(name)?;
+    mount::invalidate(&full);
+    Ok(())
+}
+
+/// Renames `old` to `new`. Both must live on the same mounted filesystem,
+/// and a directory cannot be moved below itself.
+pub fn rename(cwd: &str, old: &str, new: &str) -> Result<(), FsError> {
+    let old = entry_path(&path::normalize(cwd, old), FsError::Busy)?;
+    let new = entry_path(&path::normalize(cwd, new), FsError::Busy)?;
+
+    if old == new {
+        return Ok(());
+    }
+    if path::is_within(&new, &old) {
+        return Err(FsError::InvalidArgument);
+    }
+
+    let (old_mount, fs) = mount::mount_for(&old).ok_or(FsError::NotInitialized)?;
+    let (new_mount, _) = mount::mount_for(&new).ok_or(FsError::NotInitialized)?;
+    if old_mount != new_mount {
+        return Err(FsError::CrossDevice);
+    }
+    if old == old_mount || new == new_mount {
+        return Err(FsError::Busy);
+    }
+
+    fs.rename(
+        mount::relative(&old, &old_mount),
+        mount::relative(&new, &new_mount),
+    )?;
+    mount::invalidate(&old);
+    mount::invalidate(&new);
+    Ok(())
+}
+
+/// Runs the consistency check of the filesystem mounted at `path`.
+pub fn check(path: &str, repair: bool) -> Result<Vec<String>, FsError> {
+    let path = path::normalize("/", path);
+    let (mount_path, fs) = mount::mount_for(&path).ok_or(FsError::NotInitialized)?;
+    if mount_path != path {
+        return Err(FsError::InvalidArgument);
+    }
+
+    let problems = fs.check(repair)?;
+    if repair {
+        // Repairs may have dropped entries the dentry cache still holds.
+        mount::invalidate(&path);
+    }
+    Ok(problems)
+}
+
+pub fn read_file(path: &str) -> Result<Vec<u8>, FsError> {
+    let inode = lookup("/", path)?;
+    if inode.kind() == InodeKind::Directory {
+        return Err(FsError::IsDirectory);
+    }
+
+    let size = inode.stat()?.size as usize;
+    let mut data = vec![0u8; size];
+    let mut filled = 0;
+
+    while filled < size {
+        let count = inode.read_at(filled as u64, &mut data[filled..])?;
+        if count == 0 {
+            break;
+        }
+        filled += count;
+    }
+
+    data.truncate(filled);
+    Ok(data)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::fs::tmpfs::TmpFs;
+    use spin::Once;
+
+    /// Makes `dir` on a tmpfs root that the tests share, mounting it the
+    /// first time round.
+    pub(crate) fn scratch(dir: &str) {
+        static ROOT: Once = Once::new();
+        ROOT.call_once(|| mount("/", TmpFs::new(1 << 20)).unwrap());
+        create("/", dir, InodeKind::Directory).unwrap();
+    }
+
+    #[test]
+    fn unlink_through_symlinked_directory() {
+        scratch("/unlink");
+        create("/unlink", "real", InodeKind::Directory).unwrap();
+        symlink("/unlink", "real", "alias").unwrap();
+        create("/unlink", "alias/f", InodeKind::File).unwrap();
+
+        // Both spellings resolve, leaving the real path cached.
+        lookup("/", "/unlink/alias/f").unwrap();
+        lookup("/", "/unlink/real/f").unwrap();
+
+        unlink("/unlink", "alias/f").unwrap();
+        assert_eq!(lookup("/", "/unlink/real/f").err(), Some(FsError::NotFound));
+
+        create("/unlink", "alias/d", InodeKind::Directory).unwrap();
+        lookup("/", "/unlink/real/d").unwrap();
+        rmdir("/unlink", "alias/d").unwrap();
+        assert_eq!(lookup("/", "/unlink/real/d").err(), Some(FsError::NotFound));
+    }
+
+    #[test]
+    fn rename_and_link_through_symlinked_directory() {
+        scratch("/moves");
+        create("/moves", "real", InodeKind::Directory).unwrap();
+        symlink("/moves", "real", "alias").unwrap();
+        create("/moves", "alias/a", InodeKind::File).unwrap();
+        lookup("/", "/moves/real/a").unwrap();
+
+        rename("/moves", "alias/a", "alias/b").unwrap();
+        assert_eq!(lookup("/", "/moves/real/a").err(), Some(FsError::NotFound));
+        lookup("/", "/moves/real/b").unwrap();
+
+        link("/moves", "alias/b", "real/c").unwrap();
+        assert_eq!(
+            lookup("/", "/moves/alias/c").unwrap().stat().unwrap().nlink,
+            2
+        );
+
+        // The alias names the same directory, so this is a move into itself.
+        create("/moves", "real/sub", InodeKind::Directory).unwrap();
+        assert_eq!(
+            rename("/moves", "real/sub", "alias/sub/inner").err(),
+            Some(FsError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn mount_points_list_the_mounted_root() {
+        scratch("/listed");
+        let fs = TmpFs::new(1 << 20);
+        let ino = fs.root().stat().unwrap().ino;
+        mount("/listed/m", fs).unwrap();
+
+        let dir = lookup("/", "/listed").unwrap();
+        let entries = read_dir("/listed", &dir).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].name.as_str(), entries[0].id), ("m", ino));
+    }
+}
@@ -0,0 +1,258 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::path::{self, is_within};
+use super::{FileSystem, Inode, InodeKind};
+use crate::fs::FsError;
+
+const DENTRY_CACHE_CAPACITY: usize = 256;
+/// Symlinks followed while resolving one path before giving up on it as a
+/// loop.
+const MAX_SYMLINKS: usize = 8;
+
+struct Mount {
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+/// Resolved paths, evicted oldest-first once the cache is full.
+struct DentryCache {
+    entries: BTreeMap<String, Arc<dyn Inode>>,
+    order: VecDeque<String>,
+}
+
+impl DentryCache {
+    const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, path: &str) -> Option<Arc<dyn Inode>> {
+        self.entries.get(path).cloned()
+    }
+
+    fn insert(&mut self, path: String, inode: Arc<dyn Inode>) {
+        if self.entries.insert(path.clone(), inode).is_some() {
+            return;
+        }
+
+        self.order.push_back(path);
+        while self.order.len() > DENTRY_CACHE_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    fn invalidate(&mut self, prefix: &str) {
+        self.entries.retain(|path, _| !is_within(path, prefix));
+        self.order.retain(|path| !is_within(path, prefix));
+    }
+}
+
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+static DENTRIES: Mutex<DentryCache> = Mutex::new(DentryCache::new());
+
+/// Attaches `fs` at `path`, which must be an existing directory or a
+/// missing name in one. A mount point without a directory of its own lasts
+/// only as long as the mount, and nothing is written to the filesystem
+/// below to make room for it.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    let path = path::normalize("/", path);
+
+    if mount_at(&path).is_some() {
+        return Err(FsError::AlreadyExists);
+    }
+    if let Some((parent, _)) = path::split_parent(&path) {
+        let covered = match resolve(&path) {
+            Err(FsError::NotFound) => resolve(parent)?,
+            inode => inode?,
+        };
+        if covered.kind() != InodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+    }
+
+    MOUNTS.lock().push(Mount {
+        path: path.clone(),
+        fs,
+    });
+    invalidate(&path);
+    Ok(())
+}
+
+/// Flushes and detaches the filesystem mounted at `path`. Fails while other
+/// filesystems are mounted below it.
+pub fn unmount(path: &str) -> Result<Arc<dyn FileSystem>, FsError> {
+    let path = path::normalize("/", path);
+    let mut mounts = MOUNTS.lock();
+
+    let index = mounts
+        .iter()
+        .position(|m| m.path == path)
+        .ok_or(FsError::NotFound)?;
+    if mounts
+        .iter()
+        .any(|m| m.path != path && is_within(&m.path, &path))
+    {
+        return Err(FsError::Busy);
+    }
+
+    mounts[index].fs.unmount()?;
+    let mount = mounts.remove(index);
+    drop(mounts);
+
+    invalidate(&path);
+    Ok(mount.fs)
+}
+
+/// Mount points and the names of the filesystems attached to them.
+pub fn mounts() -> Vec<(String, &'static str)> {
+    MOUNTS
+        .lock()
+        .iter()
+        .map(|m| (m.path.clone(), m.fs.name()))
+        .collect()
+}
+
+/// Flushes every mounted filesystem, carrying on past failures and
+/// reporting the first one.
+pub fn sync() -> Result<(), FsError> {
+    let filesystems: Vec<Arc<dyn FileSystem>> =
+        MOUNTS.lock().iter().map(|m| m.fs.clone()).collect();
+
+    let mut result = Ok(());
+    for fs in filesystems {
+        if let Err(e) = fs.sync() {
+            result = result.and(Err(e));
+        }
+    }
+    result
+}
+
+pub fn mount_at(path: &str) -> Option<Arc<dyn FileSystem>> {
+    MOUNTS
+        .lock()
+        .iter()
+        .find(|m| m.path == path)
+        .map(|m| m.fs.clone())
+}
+
+/// The innermost mount containing `path`, as its mount point and filesystem.
+pub fn mount_for(path: &str) -> Option<(String, Arc<dyn FileSystem>)> {
+    MOUNTS
+        .lock()
+        .iter()
+        .filter(|m| is_within(path, &m.path))
+        .max_by_key(|m| m.path.len())
+        .map(|m| (m.path.clone(), m.fs.clone()))
+}
+
+/// Strips the mount point from `path`, leaving a path from the root of the
+/// mounted filesystem.
+pub fn relative<'a>(path: &'a str, mount_point: &str) -> &'a str {
+    if mount_point == "/" {
+        return path;
+    }
+    match &path[mount_point.len()..] {
+        "" => "/",
+        rest => rest,
+    }
+}
+
+/// Drops cached lookups at or below `path`. Callers that change a directory's
+/// contents invalidate the affected names.
+pub fn invalidate(path: &str) {
+    DENTRIES.lock().invalidate(path);
+}
+
+/// Walks a normalized absolute path from the root, crossing into mounted
+/// filesystems at their mount points and following symlinks.
+pub fn resolve(path: &str) -> Result<Arc<dyn Inode>, FsError> {
+    resolve_path(path).map(|(_, inode)| inode)
+}
+
+/// Like `resolve`, but also returns the path the walk ended on, with every
+/// symlink along the way followed. That is the name the dentry cache knows
+/// the inode by, so changes made below it must invalidate that path rather
+/// than the one given.
+pub fn resolve_path(path: &str) -> Result<(String, Arc<dyn Inode>), FsError> {
+    let mut path = String::from(path);
+
+    for _ in 0..=MAX_SYMLINKS {
+        match walk(&path)? {
+            Walk::Found(inode) => return Ok((path, inode)),
+            Walk::Symlink { at, target, rest } => {
+                // The target replaces the link, relative to the directory
+                // holding it, and the rest of the path carries on below it.
+                let parent = path::split_parent(&at).map_or("/", |(parent, _)| parent);
+                path = path::normalize(parent, &path::join(&target, rest));
+            }
+        }
+    }
+    Err(FsError::SymlinkLoop)
+}
+
+enum Walk<'a> {
+    Found(Arc<dyn Inode>),
+    /// A symlink at path `at`, with what was left of the path after it.
+    Symlink {
+        at: String,
+        target: String,
+        rest: &'a str,
+    },
+}
+
+/// Resolves `path` up to its first symlink. Only paths free of symlinks
+/// are cached, and only on filesystems that allow it, so each inode is
+/// cached under at most one name: the one `resolve_path` reports.
+fn walk(path: &str) -> Result<Walk<'_>, FsError> {
+    if let Some(inode) = DENTRIES.lock().get(path) {
+        return Ok(Walk::Found(inode));
+    }
+
+    let mut fs = mount_at("/").ok_or(FsError::NotInitialized)?;
+    let mut inode = fs.root();
+    let mut current = String::from("/");
+
+    for name in path.split('/').filter(|c| !c.is_empty()) {
+        current = path::join(&current, name);
+
+        let mounted = mount_at(&current);
+        if let Some(mounted) = &mounted {
+            fs = mounted.clone();
+        }
+
+        let cached = DENTRIES.lock().get(&current);
+        inode = match cached {
+            Some(cached) => cached,
+            None => {
+                let next = match mounted {
+                    Some(_) => fs.root(),
+                    None => inode.lookup(name)?,
+                };
+                if next.kind() != InodeKind::Symlink && fs.caches_lookups() {
+                    DENTRIES.lock().insert(current.clone(), next.clone());
+                }
+                next
+            }
+        };
+
+        if inode.kind() == InodeKind::Symlink {
+            // `path` is normalized, so the walk so far is a prefix of it.
+            let rest = &path[current.len()..];
+            return Ok(Walk::Symlink {
+                at: current,
+                target: inode.readlink()?,
+                rest,
+            });
+        }
+    }
+
+    Ok(Walk::Found(inode))
+}
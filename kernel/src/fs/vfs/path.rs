@@ -0,0 +1,101 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Turns `path` into a canonical absolute path, interpreting relative paths
+/// against `cwd` and folding `.` and `..` components. `..` at the root stays
+/// at the root.
+///
+/// The folding is lexical and happens before any symlink is followed, so
+/// `link/..` is the directory holding `link` rather than the parent of its
+/// target, as with a shell's logical paths. Symlink targets are folded the
+/// same way once joined to the directory holding the link.
+pub fn normalize(cwd: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+
+    let base = if path.starts_with('/') { "" } else { cwd };
+    for component in base.split('/').chain(path.split('/')) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name),
+        }
+    }
+
+    if components.is_empty() {
+        return String::from("/");
+    }
+
+    let mut normalized = String::new();
+    for component in components {
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+    normalized
+}
+
+/// Splits a normalized absolute path into its parent directory and final
+/// component. The root has no parent.
+pub fn split_parent(path: &str) -> Option<(&str, &str)> {
+    if path == "/" {
+        return None;
+    }
+
+    let index = path.rfind('/')?;
+    let parent = if index == 0 { "/" } else { &path[..index] };
+    Some((parent, &path[index + 1..]))
+}
+
+pub fn join(parent: &str, name: &str) -> String {
+    let mut path = String::from(parent);
+    if !path.ends_with('/') {
+        path.push('/');
+    }
+    path.push_str(name);
+    path
+}
+
+/// Returns whether `path` lies at or below `prefix`.
+pub fn is_within(path: &str, prefix: &str) -> bool {
+    prefix == "/"
+        || path == prefix
+        || (path.starts_with(prefix) && path.as_bytes().get(prefix.len()) == Some(&b'/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_folds_dots() {
+        assert_eq!(normalize("/", "/"), "/");
+        assert_eq!(
+            normalize("/home/user", "docs/./a.txt"),
+            "/home/user/docs/a.txt"
+        );
+        assert_eq!(normalize("/home/user", "../other//b"), "/home/other/b");
+        assert_eq!(normalize("/home/user", "/etc/../bin/"), "/bin");
+        assert_eq!(normalize("/", "../../.."), "/");
+        assert_eq!(normalize("/a", ""), "/a");
+    }
+
+    #[test]
+    fn split_parent_of_paths() {
+        assert_eq!(split_parent("/"), None);
+        assert_eq!(split_parent("/etc"), Some(("/", "etc")));
+        assert_eq!(split_parent("/etc/motd"), Some(("/etc", "motd")));
+        assert_eq!(join("/", "etc"), "/etc");
+        assert_eq!(join("/etc", "motd"), "/etc/motd");
+    }
+
+    #[test]
+    fn is_within_respects_component_boundaries() {
+        assert!(is_within("/mnt/disk", "/mnt"));
+        assert!(is_within("/mnt", "/mnt"));
+        assert!(is_within("/anything", "/"));
+        assert!(!is_within("/mnt2", "/mnt"));
+        assert!(!is_within("/mn", "/mnt"));
+        assert!(!is_within("/", "/mnt"));
+    }
+}
@@ -88,6 +88,7 @@ impl DisplayDevice {
 }
 
 pub fn init_display(info: FrameBufferInfo, framebuffer: &'static mut [u8]) {
+    crate::drivers::framebuffer::init(&info, framebuffer);
     let mut display = DISPLAY.lock();
     *display = Some(DisplayDevice::new(info, framebuffer));
 }
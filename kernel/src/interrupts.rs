@@ -1,6 +1,7 @@
 use crate::serial_println;
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
+use core::sync::atomic::{AtomicU64, Ordering};
 use spin::Mutex;
 use x86_64::instructions::port::Port;
 use x86_64::registers::control::Cr2;
@@ -9,6 +10,22 @@ use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, Pag
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
+pub const PIT_FREQUENCY_HZ: u64 = 100;
+const PIT_BASE_FREQUENCY_HZ: u64 = 1_193_182;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Interrupts and exceptions taken since boot, by vector.
+static INTERRUPT_COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+/// Vectors with a handler installed, and what raises them.
+pub const HANDLED_VECTORS: [(u8, &str); 5] = [
+    (3, "breakpoint"),
+    (8, "double fault"),
+    (14, "page fault"),
+    (InterruptIndex::Timer as u8, "timer"),
+    (InterruptIndex::Keyboard as u8, "keyboard"),
+];
+
 pub static PICS: Mutex<ChainedPics> =
     Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
@@ -63,16 +80,34 @@ pub fn init_pics() {
 pub fn init_pit() {
     let mut command_port = Port::new(0x43);
     let mut data_port = Port::new(0x40);
+    let divisor = (PIT_BASE_FREQUENCY_HZ / PIT_FREQUENCY_HZ) as u16;
 
     // Channel 0 | Lo/Hi byte | Mode 3 (Square Wave) | Binary
     unsafe {
         command_port.write(0x36 as u8);
-        data_port.write(0x00 as u8);
-        data_port.write(0x00 as u8);
+        data_port.write((divisor & 0xFF) as u8);
+        data_port.write((divisor >> 8) as u8);
     }
 }
 
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / PIT_FREQUENCY_HZ
+}
+
+pub fn interrupt_count(vector: u8) -> u64 {
+    INTERRUPT_COUNTS[vector as usize].load(Ordering::Relaxed)
+}
+
+fn count_interrupt(vector: u8) {
+    INTERRUPT_COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    count_interrupt(3);
     serial_println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
@@ -82,6 +117,8 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::segmentation::{CS, Segment};
 
+    count_interrupt(14);
+
     serial_println!("EXCEPTION: PAGE FAULT");
     serial_println!("Accessed Address: {:?}", Cr2::read());
     serial_println!("Error Code: {:?}", error_code);
@@ -104,10 +141,14 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    count_interrupt(8);
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    count_interrupt(InterruptIndex::Timer.as_u8());
+
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
@@ -117,6 +158,7 @@ extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFr
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
+    count_interrupt(InterruptIndex::Keyboard.as_u8());
 
     crate::task::keyboard::add_scancode(scancode);
 
@@ -0,0 +1,201 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+
+use super::handle::KernelObject;
+use super::poll::PollFlags;
+use super::wait::WaitQueue;
+
+pub const MAX_MESSAGE_SIZE: usize = 256;
+pub const MAX_MESSAGE_HANDLES: usize = 4;
+const MAX_QUEUED_MESSAGES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelError {
+    ShouldWait,
+    PeerClosed,
+    QueueFull,
+    MessageTooLarge,
+    TooManyHandles,
+}
+
+pub struct Message {
+    pub txid: u32,
+    pub data: Vec<u8>,
+    pub handles: Vec<KernelObject>,
+}
+
+impl Message {
+    pub fn new(data: &[u8], handles: Vec<KernelObject>) -> Result<Self, ChannelError> {
+        if data.len() > MAX_MESSAGE_SIZE {
+            return Err(ChannelError::MessageTooLarge);
+        }
+        if handles.len() > MAX_MESSAGE_HANDLES {
+            return Err(ChannelError::TooManyHandles);
+        }
+
+        Ok(Self {
+            txid: 0,
+            data: Vec::from(data),
+            handles,
+        })
+    }
+}
+
+struct ChannelState {
+    queues: [VecDeque<Message>; 2],
+    closed: [bool; 2],
+}
+
+struct Channel {
+    state: Mutex<ChannelState>,
+    waiters: [WaitQueue; 2],
+}
+
+/// One side of a bidirectional channel. Messages sent on an endpoint are
+/// queued for its peer; dropping the last reference closes that side.
+pub struct Endpoint {
+    channel: Arc<Channel>,
+    side: usize,
+}
+
+pub fn create() -> (Endpoint, Endpoint) {
+    let channel = Arc::new(Channel {
+        state: Mutex::new(ChannelState {
+            queues: [VecDeque::new(), VecDeque::new()],
+            closed: [false, false],
+        }),
+        waiters: [WaitQueue::new(), WaitQueue::new()],
+    });
+
+    (
+        Endpoint {
+            channel: channel.clone(),
+            side: 0,
+        },
+        Endpoint { channel, side: 1 },
+    )
+}
+
+fn next_txid() -> u32 {
+    static NEXT_TXID: AtomicU32 = AtomicU32::new(1);
+    // Zero is reserved for messages that are not part of a call.
+    loop {
+        let txid = NEXT_TXID.fetch_add(1, Ordering::Relaxed);
+        if txid != 0 {
+            return txid;
+        }
+    }
+}
+
+impl Endpoint {
+    fn peer(&self) -> usize {
+        1 - self.side
+    }
+
+    pub fn is_same_channel(&self, other: &Endpoint) -> bool {
+        Arc::ptr_eq(&self.channel, &other.channel)
+    }
+
+    pub fn wait_queue(&self) -> &WaitQueue {
+        &self.channel.waiters[self.side]
+    }
+
+    pub fn readiness(&self) -> PollFlags {
+        let state = self.channel.state.lock();
+        let peer = self.peer();
+        let mut flags = PollFlags::empty();
+
+        if !state.queues[self.side].is_empty() {
+            flags |= PollFlags::READABLE;
+        }
+        if state.closed[peer] {
+            flags |= PollFlags::HANGUP;
+        } else if state.queues[peer].len() < MAX_QUEUED_MESSAGES {
+            flags |= PollFlags::WRITABLE;
+        }
+        flags
+    }
+
+    pub fn send(&self, message: Message) -> Result<(), (ChannelError, Message)> {
+        let peer = self.peer();
+        {
+            let mut state = self.channel.state.lock();
+
+            if state.closed[peer] {
+                return Err((ChannelError::PeerClosed, message));
+            }
+            if state.queues[peer].len() >= MAX_QUEUED_MESSAGES {
+                return Err((ChannelError::QueueFull, message));
+            }
+
+            state.queues[peer].push_back(message);
+        }
+
+        self.channel.waiters[peer].wake_all();
+        Ok(())
+    }
+
+    fn take_message(&self, matches: impl Fn(&Message) -> bool) -> Result<Message, ChannelError> {
+        let message = {
+            let mut guard = self.channel.state.lock();
+            let state = &mut *guard;
+            let queue = &mut state.queues[self.side];
+
+            match queue.iter().position(matches) {
+                Some(pos) => queue.remove(pos).unwrap(),
+                None if state.closed[self.peer()] => return Err(ChannelError::PeerClosed),
+                None => return Err(ChannelError::ShouldWait),
+            }
+        };
+
+        // The peer may have been waiting for room in our queue.
+        self.channel.waiters[self.peer()].wake_all();
+        Ok(message)
+    }
+
+    pub fn try_recv(&self) -> Result<Message, ChannelError> {
+        self.take_message(|_| true)
+    }
+
+    fn try_recv_reply(&self, txid: u32) -> Result<Message, ChannelError> {
+        self.take_message(|m| m.txid == txid)
+    }
+
+    pub fn recv(&self) -> Result<Message, ChannelError> {
+        super::wait_until(|| match self.try_recv() {
+            Err(ChannelError::ShouldWait) => None,
+            result => Some(result),
+        })
+    }
+
+    /// Sends `message` under a fresh transaction id and blocks until the peer
+    /// answers with a message carrying the same id. Other messages stay queued.
+    pub fn call(&self, mut message: Message) -> Result<Message, (ChannelError, Option<Message>)> {
+        let txid = next_txid();
+        message.txid = txid;
+        self.send(message).map_err(|(e, m)| (e, Some(m)))?;
+
+        super::wait_until(|| match self.try_recv_reply(txid) {
+            Err(ChannelError::ShouldWait) => None,
+            result => Some(result),
+        })
+        .map_err(|e| (e, None))
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        let pending = {
+            let mut state = self.channel.state.lock();
+            state.closed[self.side] = true;
+            core::mem::take(&mut state.queues[self.side])
+        };
+        self.channel.waiters[self.peer()].wake_all();
+        // Queued messages can carry other endpoints, whose drop takes their
+        // own channel lock, so release them only after ours is gone.
+        drop(pending);
+    }
+}
@@ -0,0 +1,93 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+use super::channel::Endpoint;
+use super::poll::PollFlags;
+use super::shm::SharedMemory;
+use super::wait::WaitQueue;
+use crate::fs::file::OpenFile;
+
+pub type Handle = u32;
+
+pub const MAX_HANDLES: usize = 256;
+
+#[derive(Clone)]
+pub enum KernelObject {
+    Channel(Arc<Endpoint>),
+    SharedMemory(Arc<SharedMemory>),
+    File(Arc<OpenFile>),
+}
+
+impl KernelObject {
+    pub fn readiness(&self) -> PollFlags {
+        match self {
+            KernelObject::Channel(endpoint) => endpoint.readiness(),
+            KernelObject::SharedMemory(_) => PollFlags::READABLE | PollFlags::WRITABLE,
+            KernelObject::File(file) => file.readiness(),
+        }
+    }
+
+    pub fn wait_queue(&self) -> Option<&WaitQueue> {
+        match self {
+            KernelObject::Channel(endpoint) => Some(endpoint.wait_queue()),
+            KernelObject::SharedMemory(_) => None,
+            KernelObject::File(file) => file.wait_queue(),
+        }
+    }
+}
+
+pub struct HandleTable {
+    entries: BTreeMap<Handle, KernelObject>,
+}
+
+impl Default for HandleTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HandleTable {
+    pub const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, object: KernelObject) -> Result<Handle, &'static str> {
+        if self.entries.len() >= MAX_HANDLES {
+            return Err("Handle table full");
+        }
+
+        let mut handle = 0;
+        while self.entries.contains_key(&handle) {
+            handle += 1;
+        }
+
+        self.entries.insert(handle, object);
+        Ok(handle)
+    }
+
+    pub fn insert_at(&mut self, handle: Handle, object: KernelObject) -> Option<KernelObject> {
+        self.entries.insert(handle, object)
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<&KernelObject> {
+        self.entries.get(&handle)
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> Option<KernelObject> {
+        self.entries.remove(&handle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Handle, &KernelObject)> {
+        self.entries.iter()
+    }
+}
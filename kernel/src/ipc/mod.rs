@@ -0,0 +1,23 @@
+pub mod channel;
+pub mod handle;
+pub mod poll;
+pub mod shm;
+pub mod wait;
+
+use x86_64::instructions::interrupts;
+
+/// Blocks the caller until `poll` produces a value, halting between attempts
+/// so interrupt handlers get a chance to make progress.
+pub fn wait_until<T>(mut poll: impl FnMut() -> Option<T>) -> T {
+    loop {
+        if let Some(value) = poll() {
+            return value;
+        }
+
+        let were_enabled = interrupts::are_enabled();
+        interrupts::enable_and_hlt();
+        if !were_enabled {
+            interrupts::disable();
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::future::Future;
+use core::ops::{BitAnd, BitOr, BitOrAssign};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use super::handle::KernelObject;
+use crate::interrupts::{self, PIT_FREQUENCY_HZ};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PollFlags(u16);
+
+impl PollFlags {
+    pub const READABLE: Self = Self(0x01);
+    pub const WRITABLE: Self = Self(0x04);
+    pub const ERROR: Self = Self(0x08);
+    pub const HANGUP: Self = Self(0x10);
+    pub const INVALID: Self = Self(0x20);
+
+    const ALL: u16 = 0x01 | 0x04 | 0x08 | 0x10 | 0x20;
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn from_bits_truncate(bits: u16) -> Self {
+        Self(bits & Self::ALL)
+    }
+
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Error and hangup conditions are always reported, whether or not the
+    /// caller asked for them.
+    fn mask(self) -> Self {
+        Self(self.0 | Self::ERROR.0 | Self::HANGUP.0)
+    }
+}
+
+impl BitOr for PollFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for PollFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for PollFlags {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+/// Resolves once `object` reports any of `events`, so executor tasks can
+/// wait on the same conditions as the `poll` syscall.
+pub struct Readiness {
+    object: KernelObject,
+    events: PollFlags,
+}
+
+pub fn ready(object: KernelObject, events: PollFlags) -> Readiness {
+    Readiness { object, events }
+}
+
+impl Future for Readiness {
+    type Output = PollFlags;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<PollFlags> {
+        if let Some(queue) = self.object.wait_queue() {
+            queue.register(cx.waker());
+        }
+
+        let ready = self.object.readiness() & self.events.mask();
+        if ready.is_empty() {
+            return Poll::Pending;
+        }
+
+        if let Some(queue) = self.object.wait_queue() {
+            queue.unregister(cx.waker());
+        }
+        Poll::Ready(ready)
+    }
+}
+
+pub struct PollEntry {
+    pub object: Option<KernelObject>,
+    pub events: PollFlags,
+    pub revents: PollFlags,
+}
+
+struct PollSignal(AtomicBool);
+
+impl Wake for PollSignal {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// Saturates rather than wraps, so huge timeouts simply never expire.
+fn ms_to_ticks(ms: u64) -> u64 {
+    ms.saturating_mul(PIT_FREQUENCY_HZ).div_ceil(1000)
+}
+
+/// Waits until at least one entry is ready or `timeout_ms` elapses, filling
+/// in `revents` and returning the number of ready entries. Entries without
+/// an object are reported as `INVALID`.
+pub fn poll(entries: &mut [PollEntry], timeout_ms: Option<u64>) -> usize {
+    let signal = Arc::new(PollSignal(AtomicBool::new(true)));
+    let waker = Waker::from(signal.clone());
+    let deadline = timeout_ms.map(|ms| interrupts::ticks().saturating_add(ms_to_ticks(ms)));
+
+    let count = super::wait_until(|| {
+        let expired = deadline.is_some_and(|d| interrupts::ticks() >= d);
+        if !signal.0.swap(false, Ordering::Acquire) && !expired {
+            return None;
+        }
+
+        let mut count = 0;
+        for entry in entries.iter_mut() {
+            entry.revents = match &entry.object {
+                Some(object) => {
+                    if let Some(queue) = object.wait_queue() {
+                        queue.register(&waker);
+                    }
+                    object.readiness() & entry.events.mask()
+                }
+                None => PollFlags::INVALID,
+            };
+            if !entry.revents.is_empty() {
+                count += 1;
+            }
+        }
+
+        if count > 0 || expired {
+            Some(count)
+        } else {
+            None
+        }
+    });
+
+    // The waker dies with this call, so leave no queue holding on to it.
+    for object in entries.iter().filter_map(|entry| entry.object.as_ref()) {
+        if let Some(queue) = object.wait_queue() {
+            queue.unregister(&waker);
+        }
+    }
+    count
+}
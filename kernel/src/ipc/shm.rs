@@ -0,0 +1,124 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::memory::{self, pmm::PMM, vmm};
+
+const PAGE_SIZE: usize = 4096;
+pub const MAX_SHM_SIZE: usize = 64 * 1024 * 1024;
+pub const SHM_REGION_START: u64 = 0x0000_6000_0000_0000;
+
+/// A set of physical frames that can be mapped into several address spaces.
+/// The frames go back to the PMM once the last handle and mapping are gone.
+pub struct SharedMemory {
+    frames: Vec<PhysAddr>,
+    owned: bool,
+}
+
+impl SharedMemory {
+    pub fn new(size: usize) -> Result<Self, &'static str> {
+        if size == 0 || size > MAX_SHM_SIZE {
+            return Err("Invalid shared memory size");
+        }
+
+        let pages = size.div_ceil(PAGE_SIZE);
+        let mut shm = Self {
+            frames: Vec::with_capacity(pages),
+            owned: true,
+        };
+
+        let offset = vmm::phys_offset();
+        for _ in 0..pages {
+            let frame = {
+                let mut pmm = PMM.lock();
+                let pmm = pmm.as_mut().ok_or("PMM not initialized")?;
+                pmm.alloc_frame().ok_or("Out of memory for shared memory")?
+            };
+            unsafe {
+                core::ptr::write_bytes((offset + frame.as_u64()).as_mut_ptr::<u8>(), 0, PAGE_SIZE);
+            }
+            shm.frames.push(frame);
+        }
+
+        Ok(shm)
+    }
+
+    /// Wraps frames the kernel does not own, such as device memory. They are
+    /// never returned to the PMM.
+    pub fn from_frames(frames: Vec<PhysAddr>) -> Self {
+        Self {
+            frames,
+            owned: false,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+
+    pub fn frames(&self) -> &[PhysAddr] {
+        &self.frames
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+
+        let mut pmm = PMM.lock();
+        if let Some(pmm) = pmm.as_mut() {
+            for &frame in &self.frames {
+                pmm.free_frame(frame);
+            }
+        }
+    }
+}
+
+pub struct ShmMapping {
+    pub base: VirtAddr,
+    pub writable: bool,
+    pub object: Arc<SharedMemory>,
+}
+
+impl ShmMapping {
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr >= self.base && addr < self.base + self.object.size() as u64
+    }
+}
+
+pub fn map(
+    pml4: PhysAddr,
+    base: VirtAddr,
+    object: Arc<SharedMemory>,
+    writable: bool,
+) -> Result<ShmMapping, &'static str> {
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if writable {
+        flags |= PageTableFlags::WRITABLE;
+    }
+
+    for (i, &frame) in object.frames().iter().enumerate() {
+        let virt = base + (i * PAGE_SIZE) as u64;
+        if let Err(e) = memory::map_page_in(pml4, virt, frame, flags) {
+            for j in 0..i {
+                let _ = memory::unmap_page_in(pml4, base + (j * PAGE_SIZE) as u64);
+            }
+            return Err(e);
+        }
+    }
+
+    Ok(ShmMapping {
+        base,
+        writable,
+        object,
+    })
+}
+
+pub fn unmap(pml4: PhysAddr, mapping: ShmMapping) {
+    for i in 0..mapping.object.frames().len() {
+        let _ = memory::unmap_page_in(pml4, mapping.base + (i * PAGE_SIZE) as u64);
+    }
+}
@@ -0,0 +1,48 @@
+use alloc::vec::Vec;
+use core::task::Waker;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Wakers interested in state changes of a single kernel object. Objects
+/// call `wake_all` whenever their readiness may have changed.
+pub struct WaitQueue {
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            wakers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn register(&self, waker: &Waker) {
+        interrupts::without_interrupts(|| {
+            let mut wakers = self.wakers.lock();
+            if !wakers.iter().any(|w| w.will_wake(waker)) {
+                wakers.push(waker.clone());
+            }
+        });
+    }
+
+    /// Forgets `waker`, for waiters that stop caring before the object
+    /// changes. Wakers otherwise stay queued until the next `wake_all`.
+    pub fn unregister(&self, waker: &Waker) {
+        interrupts::without_interrupts(|| {
+            self.wakers.lock().retain(|w| !w.will_wake(waker));
+        });
+    }
+
+    pub fn wake_all(&self) {
+        let wakers = interrupts::without_interrupts(|| core::mem::take(&mut *self.wakers.lock()));
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(abi_x86_interrupt)]
 
 extern crate alloc;
@@ -12,6 +12,7 @@ pub mod fs;
 pub mod gdt;
 pub mod graphics;
 pub mod interrupts;
+pub mod ipc;
 pub mod memory;
 pub mod panic;
 pub mod process;
@@ -48,8 +49,51 @@ pub fn init_all(boot_info: &'static mut BootInfo) {
     syscall::init_syscall();
     serial_println!("[INIT] Syscalls initialized.");
 
-    fs::init_fs();
-    serial_println!("[INIT] Filesystem initialized.");
+    drivers::console::init();
+    serial_println!("[INIT] Console initialized.");
+
+    drivers::mem::init();
+    drivers::random::init();
+    drivers::keyboard::init();
+    serial_println!("[INIT] Character devices registered.");
+
+    // The bootloader may map the ramdisk in the lower half, which process
+    // address spaces do not share, so it is only read during boot.
+    let ramdisk = boot_info.ramdisk_addr.into_option().map(|addr| unsafe {
+        core::slice::from_raw_parts(addr as *const u8, boot_info.ramdisk_len as usize)
+    });
+    if let Err(e) = fs::init_fs(ramdisk) {
+        serial_println!("[INIT] No root filesystem mounted: {:?}", e);
+    } else {
+        serial_println!("[INIT] Filesystem initialized.");
+
+        match fs::mount_tmp() {
+            Ok(()) => {
+                serial_println!("[INIT] tmpfs mounted at /tmp.");
+            }
+            Err(e) => {
+                serial_println!("[INIT] /tmp not mounted: {:?}", e);
+            }
+        }
+
+        match fs::mount_dev() {
+            Ok(()) => {
+                serial_println!("[INIT] devfs mounted at /dev.");
+            }
+            Err(e) => {
+                serial_println!("[INIT] /dev not mounted: {:?}", e);
+            }
+        }
+
+        match fs::mount_proc() {
+            Ok(()) => {
+                serial_println!("[INIT] procfs mounted at /proc.");
+            }
+            Err(e) => {
+                serial_println!("[INIT] /proc not mounted: {:?}", e);
+            }
+        }
+    }
 
     if let Some(framebuffer) = boot_info.framebuffer.as_mut() {
         let info = framebuffer.info();
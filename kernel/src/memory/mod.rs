@@ -7,7 +7,7 @@ use x86_64::VirtAddr;
 pub use vmm::{
     get_mapper, is_user_readable, is_user_writable, translate as translate_addr,
     map_page, unmap_page, set_page_flags, create_address_space, switch_address_space,
-    map_page_in,
+    map_page_in, unmap_page_in, current_address_space,
 };
 pub use pmm::PMM;
 
@@ -260,9 +260,48 @@ pub fn map_page_in(
 
         l1[indices[3]].set_addr(phys, flags);
     }
+    flush_if_active(pml4_phys, virt);
     Ok(())
 }
 
+pub fn unmap_page_in(pml4_phys: PhysAddr, virt: VirtAddr) -> Result<PhysAddr, &'static str> {
+    let offset = phys_offset();
+
+    let phys = unsafe {
+        let mut table: &mut PageTable = &mut *((offset + pml4_phys.as_u64()).as_mut_ptr());
+
+        for level in [39, 30, 21] {
+            let entry = &table[((virt.as_u64() >> level) & 0x1FF) as usize];
+            if !entry.flags().contains(PageTableFlags::PRESENT) {
+                return Err("unmap_page_in: page not mapped");
+            }
+            table = &mut *((offset + entry.addr().as_u64()).as_mut_ptr());
+        }
+
+        let entry = &mut table[((virt.as_u64() >> 12) & 0x1FF) as usize];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return Err("unmap_page_in: page not mapped");
+        }
+        let phys = entry.addr();
+        entry.set_unused();
+        phys
+    };
+
+    flush_if_active(pml4_phys, virt);
+    Ok(phys)
+}
+
+fn flush_if_active(pml4_phys: PhysAddr, virt: VirtAddr) {
+    let (active, _) = Cr3::read();
+    if active.start_address() == pml4_phys {
+        x86_64::instructions::tlb::flush(virt);
+    }
+}
+
+pub fn current_address_space() -> PhysAddr {
+    Cr3::read().0.start_address()
+}
+
 unsafe fn ensure_table(
     parent: &mut PageTable,
     index: usize,
@@ -1,9 +1,6 @@
-use crate::serial::{QemuExitCode, exit_qemu};
-use crate::serial_println;
-
 #[panic_handler]
 #[cfg(not(test))]
 pub fn panic(info: &core::panic::PanicInfo) -> ! {
-    serial_println!("PANIC: {}", info);
-    exit_qemu(QemuExitCode::Failed);
+    crate::serial_println!("PANIC: {}", info);
+    crate::serial::exit_qemu(crate::serial::QemuExitCode::Failed);
 }
@@ -1,29 +1,181 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
 use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
 use x86_64::{
-    VirtAddr,
+    PhysAddr, VirtAddr,
     structures::paging::{Page, PageTableFlags, Size4KiB},
 };
 use xmas_elf::ElfFile;
+use xmas_elf::header::{Class, MAGIC};
 use xmas_elf::program::Type;
 
-use crate::fs::FILESYSTEM;
+use crate::fs::FsError;
+use crate::fs::file::{O_RDONLY, O_WRONLY, OpenFile};
+use crate::fs::vfs::{self, File, InodeKind};
+use crate::ipc::handle::{HandleTable, KernelObject};
+use crate::ipc::shm::{self, SHM_REGION_START, SharedMemory, ShmMapping};
 use crate::memory;
 use crate::memory::pmm::PMM;
 
+pub type Pid = u64;
+
+pub const KERNEL_PID: Pid = 0;
+
+/// A range of the address space set up when the program was loaded. The
+/// permissions are the ones the program header asked for.
+pub struct Region {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    pub writable: bool,
+    pub executable: bool,
+    pub label: String,
+}
+
+pub struct Process {
+    pub pid: Pid,
+    pub name: String,
+    pub address_space: PhysAddr,
+    pub cwd: String,
+    pub handles: HandleTable,
+    pub shm_mappings: Vec<ShmMapping>,
+    pub regions: Vec<Region>,
+    /// Timer ticks since boot when the process was created.
+    pub started: u64,
+    next_shm_base: u64,
+}
+
+impl Process {
+    /// Maps `object` at the next free address of the shared memory region.
+    /// The address range is only used up once the mapping succeeds.
+    pub fn map_shared(
+        &mut self,
+        object: Arc<SharedMemory>,
+        writable: bool,
+    ) -> Result<VirtAddr, &'static str> {
+        let base = VirtAddr::new(self.next_shm_base);
+        let size = object.size() as u64;
+        let mapping = shm::map(self.address_space, base, object, writable)?;
+
+        // Leave an unmapped guard page between consecutive mappings.
+        self.next_shm_base += size.div_ceil(4096) * 4096 + 4096;
+        self.shm_mappings.push(mapping);
+        Ok(base)
+    }
+}
+
+pub static PROCESSES: Mutex<BTreeMap<Pid, Process>> = Mutex::new(BTreeMap::new());
+static CURRENT_PID: AtomicU64 = AtomicU64::new(KERNEL_PID);
+
+pub fn create_process(name: &str) -> Pid {
+    static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+    let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+
+    // Descriptors 0, 1 and 2 start out on the console.
+    let mut handles = HandleTable::new();
+    let stdin = Arc::new(OpenFile::console(O_RDONLY));
+    let stdout = Arc::new(OpenFile::console(O_WRONLY));
+    handles.insert_at(0, KernelObject::File(stdin));
+    handles.insert_at(1, KernelObject::File(stdout.clone()));
+    handles.insert_at(2, KernelObject::File(stdout));
+
+    PROCESSES.lock().insert(
+        pid,
+        Process {
+            pid,
+            name: String::from(name),
+            address_space: memory::current_address_space(),
+            cwd: String::from("/"),
+            handles,
+            shm_mappings: Vec::new(),
+            regions: Vec::new(),
+            started: crate::interrupts::ticks(),
+            next_shm_base: SHM_REGION_START,
+        },
+    );
+    pid
+}
+
+pub fn current_pid() -> Pid {
+    CURRENT_PID.load(Ordering::Relaxed)
+}
+
+pub fn set_current(pid: Pid) {
+    CURRENT_PID.store(pid, Ordering::Relaxed);
+}
+
+pub fn with_current<R>(f: impl FnOnce(&mut Process) -> R) -> Result<R, &'static str> {
+    let mut processes = PROCESSES.lock();
+    let process = processes
+        .get_mut(&current_pid())
+        .ok_or("No current process")?;
+    Ok(f(process))
+}
+
+const ELF64_HEADER_SIZE: usize = 64;
+/// Upper bound on the ELF and program headers, which are the only part of
+/// the image held in memory while loading.
+const MAX_ELF_HEADERS: u64 = 64 * 1024;
+
+/// Fills `buf` from byte `offset` of `file`, which must not end first.
+fn read_exact(file: &dyn File, offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let count = file.read(offset + filled as u64, &mut buf[filled..])?;
+        if count == 0 {
+            return Err(FsError::Io);
+        }
+        filled += count;
+    }
+    Ok(())
+}
+
+/// Loads the executable at `filename` and enters it. Segments are read from
+/// the file straight into their pages, so the image never has to fit in the
+/// kernel heap.
 pub fn load_elf(filename: &str) -> Result<(), String> {
-    let file_data: Vec<u8> = {
-        let mut fs_lock = FILESYSTEM.lock();
-        let fs = fs_lock.as_mut().ok_or("Filesystem not initialized")?;
-        fs.read_file(filename).ok_or("File not found")?
+    let inode =
+        vfs::lookup("/", filename).map_err(|e| format!("Cannot open {}: {:?}", filename, e))?;
+    if inode.kind() != InodeKind::File {
+        return Err(format!("{} is not a regular file", filename));
+    }
+
+    let file = vfs::open_inode(&inode).map_err(|e| format!("Cannot open {}: {:?}", filename, e))?;
+    let read = |offset: u64, buf: &mut [u8]| {
+        read_exact(&*file, offset, buf).map_err(|e| format!("Cannot read {}: {:?}", filename, e))
+    };
+    let file_len = file
+        .size()
+        .map_err(|e| format!("Cannot stat {}: {:?}", filename, e))?;
+
+    let mut headers = vec![0u8; ELF64_HEADER_SIZE];
+    read(0, &mut headers)?;
+    let headers_len = {
+        let elf = ElfFile::new(&headers).map_err(|_| "Elf parse error")?;
+        let pt2 = &elf.header.pt2;
+        pt2.ph_offset() + pt2.ph_entry_size() as u64 * pt2.ph_count() as u64
     };
+    if headers_len > file_len || headers_len > MAX_ELF_HEADERS {
+        return Err(String::from("Program header table out of range"));
+    }
+    if headers_len > headers.len() as u64 {
+        headers.resize(headers_len as usize, 0);
+        read(0, &mut headers)?;
+    }
 
-    let elf = ElfFile::new(&file_data).map_err(|_| "Elf parse error")?;
-    xmas_elf::header::sanity_check(&elf).map_err(|_| "ELF sanity check failed")?;
+    let elf = ElfFile::new(&headers).map_err(|_| "Elf parse error")?;
+    if elf.header.pt1.magic != MAGIC || elf.header.pt1.class() != Class::SixtyFour {
+        return Err(String::from("Not a 64-bit ELF executable"));
+    }
 
     let flags = PageTableFlags::PRESENT
         | PageTableFlags::WRITABLE
         | PageTableFlags::USER_ACCESSIBLE;
+    let mut regions = Vec::new();
 
     for ph in elf.program_iter() {
         if ph.get_type().map_err(|_| "Invalid Segment Type")? == Type::Load {
@@ -35,6 +187,13 @@ pub fn load_elf(filename: &str) -> Result<(), String> {
             if virt_addr == 0 {
                 continue;
             }
+            if file_size > mem_size
+                || file_offset
+                    .checked_add(file_size)
+                    .is_none_or(|end| end > file_len)
+            {
+                return Err(String::from("Segment out of range"));
+            }
 
             let start_addr = VirtAddr::new(virt_addr);
             let start_page: Page<Size4KiB> = Page::containing_address(start_addr);
@@ -53,11 +212,18 @@ pub fn load_elf(filename: &str) -> Result<(), String> {
                 }
             }
 
-            unsafe {
-                let src_ptr = file_data.as_ptr().add(file_offset as usize);
-                let dest_ptr = virt_addr as *mut u8;
-                core::ptr::copy_nonoverlapping(src_ptr, dest_ptr, file_size as usize);
-            }
+            regions.push(Region {
+                start: start_addr,
+                end: end_addr,
+                writable: ph.flags().is_write(),
+                executable: ph.flags().is_execute(),
+                label: String::from(filename),
+            });
+
+            let dest = unsafe {
+                core::slice::from_raw_parts_mut(virt_addr as *mut u8, file_size as usize)
+            };
+            read(file_offset, dest)?;
 
             if mem_size > file_size {
                 unsafe {
@@ -89,6 +255,20 @@ pub fn load_elf(filename: &str) -> Result<(), String> {
         }
     }
 
+    regions.push(Region {
+        start: stack_start_page.start_address(),
+        end: stack_start,
+        writable: true,
+        executable: false,
+        label: String::from("[stack]"),
+    });
+
+    let pid = create_process(filename);
+    if let Some(process) = PROCESSES.lock().get_mut(&pid) {
+        process.regions = regions;
+    }
+    set_current(pid);
+
     unsafe {
         crate::syscall::enter_userspace(elf.header.pt2.entry_point(), stack_start.as_u64());
     }
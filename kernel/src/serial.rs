@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+use core::fmt;
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
@@ -31,12 +33,61 @@ lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = Mutex::new(serial());
 }
 
+/// Bytes of kernel output kept in memory; older output is dropped.
+const LOG_SIZE: usize = 64 * 1024;
+
+/// The most recent kernel output, as a ring of bytes.
+struct LogBuffer {
+    data: [u8; LOG_SIZE],
+    start: usize,
+    len: usize,
+}
+
+impl fmt::Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            let end = (self.start + self.len) % LOG_SIZE;
+            self.data[end] = byte;
+            if self.len < LOG_SIZE {
+                self.len += 1;
+            } else {
+                self.start = (self.start + 1) % LOG_SIZE;
+            }
+        }
+        Ok(())
+    }
+}
+
+static LOG: Mutex<LogBuffer> = Mutex::new(LogBuffer {
+    data: [0; LOG_SIZE],
+    start: 0,
+    len: 0,
+});
+
+/// A copy of the kernel output still held in the log, oldest first.
+pub fn log_contents() -> Vec<u8> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let log = LOG.lock();
+        let (tail, head) = log.data.split_at(log.start);
+        head.iter().chain(tail).take(log.len).copied().collect()
+    })
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
     use x86_64::instructions::interrupts;
 
+    // Host-side tests have no serial port; the log still gets the output.
+    if cfg!(test) {
+        let _ = LOG.lock().write_fmt(args);
+        return;
+    }
+
     interrupts::without_interrupts(|| {
+        let _ = LOG.lock().write_fmt(args);
         SERIAL1
             .lock()
             .write_fmt(args)
@@ -0,0 +1,300 @@
+use alloc::sync::Arc;
+
+use super::{Errno, SyscallResult, user_slice, user_slice_mut, write_user};
+use crate::fs::FsError;
+use crate::fs::file::OpenFile;
+use crate::fs::vfs::{self, InodeKind};
+use crate::ipc::handle::{Handle, KernelObject, MAX_HANDLES};
+use crate::process::{Process, with_current};
+
+pub const MAX_PATH_LEN: usize = 256;
+pub const MMAP_WRITE: usize = 1;
+
+impl From<FsError> for Errno {
+    fn from(error: FsError) -> Self {
+        match error {
+            FsError::NotFound => Errno::ENOENT,
+            FsError::NotInitialized => Errno::ENODEV,
+            FsError::IsDirectory => Errno::EISDIR,
+            FsError::NotDirectory => Errno::ENOTDIR,
+            FsError::PermissionDenied => Errno::EACCES,
+            FsError::BadFileMode => Errno::EBADF,
+            FsError::NotSeekable => Errno::ESPIPE,
+            FsError::InvalidArgument => Errno::EINVAL,
+            FsError::AlreadyExists => Errno::EEXIST,
+            FsError::NotEmpty => Errno::ENOTEMPTY,
+            FsError::Busy => Errno::EBUSY,
+            FsError::CrossDevice => Errno::EXDEV,
+            FsError::NotSupported => Errno::EOPNOTSUPP,
+            FsError::NoSpace => Errno::ENOSPC,
+            FsError::FileTooLarge => Errno::EFBIG,
+            FsError::Corrupted => Errno::EIO,
+            FsError::Io => Errno::EIO,
+            FsError::ReadOnly => Errno::EROFS,
+            FsError::TooManyLinks => Errno::EMLINK,
+            FsError::SymlinkLoop => Errno::ELOOP,
+        }
+    }
+}
+
+fn current<R>(f: impl FnOnce(&mut Process) -> Result<R, Errno>) -> Result<R, Errno> {
+    with_current(f).map_err(|_| Errno::EPERM)?
+}
+
+fn get_file(fd: usize) -> Result<Arc<OpenFile>, Errno> {
+    current(|process| match process.handles.get(fd as Handle) {
+        Some(KernelObject::File(file)) => Ok(file.clone()),
+        Some(_) => Err(Errno::EINVAL),
+        None => Err(Errno::EBADF),
+    })
+}
+
+pub(super) fn user_path<'a>(ptr: usize, len: usize) -> Result<&'a str, Errno> {
+    if len > MAX_PATH_LEN {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    core::str::from_utf8(user_slice(ptr, len)?).map_err(|_| Errno::EINVAL)
+}
+
+pub fn sys_open(path_ptr: usize, path_len: usize, flags: usize) -> SyscallResult {
+    let path = user_path(path_ptr, path_len)?;
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+    let file = OpenFile::open(&cwd, path, flags as u32)?;
+
+    current(|process| {
+        process
+            .handles
+            .insert(KernelObject::File(Arc::new(file)))
+            .map(|fd| fd as usize)
+            .map_err(|_| Errno::EMFILE)
+    })
+}
+
+pub fn sys_read(fd: usize, buf_ptr: usize, len: usize) -> SyscallResult {
+    let buf = user_slice_mut(buf_ptr, len)?;
+    Ok(get_file(fd)?.read(buf)?)
+}
+
+pub fn sys_write(fd: usize, buf_ptr: usize, len: usize) -> SyscallResult {
+    let buf = user_slice(buf_ptr, len)?;
+    Ok(get_file(fd)?.write(buf)?)
+}
+
+pub fn sys_lseek(fd: usize, offset: usize, whence: usize) -> SyscallResult {
+    let position = get_file(fd)?.seek(offset as i64, whence as u32)?;
+    Ok(position as usize)
+}
+
+pub fn sys_fstat(fd: usize, stat_ptr: usize) -> SyscallResult {
+    let stat = get_file(fd)?.stat()?;
+    write_user(stat_ptr, &stat)?;
+    Ok(0)
+}
+
+pub fn sys_dup(fd: usize) -> SyscallResult {
+    current(|process| {
+        let object = process
+            .handles
+            .get(fd as Handle)
+            .cloned()
+            .ok_or(Errno::EBADF)?;
+        process
+            .handles
+            .insert(object)
+            .map(|new_fd| new_fd as usize)
+            .map_err(|_| Errno::EMFILE)
+    })
+}
+
+pub fn sys_dup2(old_fd: usize, new_fd: usize) -> SyscallResult {
+    if new_fd >= MAX_HANDLES {
+        return Err(Errno::EBADF);
+    }
+
+    let replaced = current(|process| {
+        let object = process
+            .handles
+            .get(old_fd as Handle)
+            .cloned()
+            .ok_or(Errno::EBADF)?;
+        if old_fd == new_fd {
+            return Ok(None);
+        }
+        // `insert_at` does not check the table size itself.
+        if process.handles.get(new_fd as Handle).is_none() && process.handles.len() >= MAX_HANDLES {
+            return Err(Errno::EMFILE);
+        }
+        Ok(process.handles.insert_at(new_fd as Handle, object))
+    })?;
+
+    // Whatever used to live at `new_fd` is released outside the table lock.
+    drop(replaced);
+    Ok(new_fd)
+}
+
+pub fn sys_getdents(fd: usize, buf_ptr: usize, len: usize) -> SyscallResult {
+    let buf = user_slice_mut(buf_ptr, len)?;
+    Ok(get_file(fd)?.read_dir(buf)?)
+}
+
+pub fn sys_chdir(path_ptr: usize, path_len: usize) -> SyscallResult {
+    let path = user_path(path_ptr, path_len)?;
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+
+    let target = vfs::path::normalize(&cwd, path);
+    if vfs::lookup("/", &target)?.kind() != InodeKind::Directory {
+        return Err(Errno::ENOTDIR);
+    }
+
+    current(|process| {
+        process.cwd = target;
+        Ok(0)
+    })
+}
+
+/// Copies the NUL-terminated working directory into the user buffer and
+/// returns its length including the terminator.
+pub fn sys_getcwd(buf_ptr: usize, len: usize) -> SyscallResult {
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+    if cwd.len() + 1 > len {
+        return Err(Errno::ERANGE);
+    }
+
+    let buf = user_slice_mut(buf_ptr, cwd.len() + 1)?;
+    buf[..cwd.len()].copy_from_slice(cwd.as_bytes());
+    buf[cwd.len()] = 0;
+    Ok(cwd.len() + 1)
+}
+
+pub fn sys_mkdir(path_ptr: usize, path_len: usize) -> SyscallResult {
+    let path = user_path(path_ptr, path_len)?;
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+    vfs::create(&cwd, path, InodeKind::Directory)?;
+    Ok(0)
+}
+
+pub fn sys_rmdir(path_ptr: usize, path_len: usize) -> SyscallResult {
+    let path = user_path(path_ptr, path_len)?;
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+    vfs::rmdir(&cwd, path)?;
+    Ok(0)
+}
+
+pub fn sys_unlink(path_ptr: usize, path_len: usize) -> SyscallResult {
+    let path = user_path(path_ptr, path_len)?;
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+    vfs::unlink(&cwd, path)?;
+    Ok(0)
+}
+
+pub fn sys_rename(old_ptr: usize, old_len: usize, new_ptr: usize, new_len: usize) -> SyscallResult {
+    let old = user_path(old_ptr, old_len)?;
+    let new = user_path(new_ptr, new_len)?;
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+    vfs::rename(&cwd, old, new)?;
+    Ok(0)
+}
+
+pub fn sys_symlink(
+    target_ptr: usize,
+    target_len: usize,
+    path_ptr: usize,
+    path_len: usize,
+) -> SyscallResult {
+    let target = user_path(target_ptr, target_len)?;
+    let path = user_path(path_ptr, path_len)?;
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+    vfs::symlink(&cwd, target, path)?;
+    Ok(0)
+}
+
+/// Copies the target of the symlink at the given path into the user buffer,
+/// truncated to fit and without a terminator, and returns its length.
+pub fn sys_readlink(path_ptr: usize, path_len: usize, buf_ptr: usize, len: usize) -> SyscallResult {
+    let path = user_path(path_ptr, path_len)?;
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+    let target = vfs::lookup_link(&cwd, path)?.readlink()?;
+
+    let count = target.len().min(len);
+    user_slice_mut(buf_ptr, count)?.copy_from_slice(&target.as_bytes()[..count]);
+    Ok(count)
+}
+
+pub fn sys_link(old_ptr: usize, old_len: usize, new_ptr: usize, new_len: usize) -> SyscallResult {
+    let old = user_path(old_ptr, old_len)?;
+    let new = user_path(new_ptr, new_len)?;
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+    vfs::link(&cwd, old, new)?;
+    Ok(0)
+}
+
+pub fn sys_chmod(path_ptr: usize, path_len: usize, mode: usize) -> SyscallResult {
+    let path = user_path(path_ptr, path_len)?;
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+    vfs::lookup(&cwd, path)?.set_mode(mode as u32)?;
+    Ok(0)
+}
+
+pub fn sys_chown(path_ptr: usize, path_len: usize, uid: usize, gid: usize) -> SyscallResult {
+    let path = user_path(path_ptr, path_len)?;
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+    vfs::lookup(&cwd, path)?.set_owner(uid as u32, gid as u32)?;
+    Ok(0)
+}
+
+pub fn sys_ftruncate(fd: usize, size: usize) -> SyscallResult {
+    get_file(fd)?.truncate(size as u64)?;
+    Ok(0)
+}
+
+pub fn sys_sync() -> SyscallResult {
+    vfs::sync()?;
+    Ok(0)
+}
+
+/// `sys_fsck` flag asking for problems to be repaired, not just reported.
+pub const FSCK_REPAIR: usize = 1;
+
+/// Checks the filesystem mounted at the given path, logging each problem,
+/// and returns how many were found.
+pub fn sys_fsck(path_ptr: usize, path_len: usize, flags: usize) -> SyscallResult {
+    let path = user_path(path_ptr, path_len)?;
+    let cwd = current(|process| Ok(process.cwd.clone()))?;
+    let path = vfs::path::normalize(&cwd, path);
+
+    let problems = vfs::check(&path, flags & FSCK_REPAIR != 0)?;
+    for problem in &problems {
+        crate::serial_println!("[FSCK] {}: {}", path, problem);
+    }
+    Ok(problems.len())
+}
+
+/// Carries out a device-specific request on `fd`. Files that take none
+/// fail with `ENOTTY`.
+pub fn sys_ioctl(fd: usize, request: usize, buf_ptr: usize, len: usize) -> SyscallResult {
+    let file = get_file(fd)?;
+    let buf = user_slice_mut(buf_ptr, len)?;
+    match file.ioctl(request as u32, buf) {
+        Ok(count) => Ok(count),
+        Err(FsError::NotSupported) => Err(Errno::ENOTTY),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Maps the device behind `fd` whole, returning its address. The mapping
+/// stays after `fd` is closed and goes with `munmap`.
+pub fn sys_mmap(fd: usize, flags: usize) -> SyscallResult {
+    let writable = flags & MMAP_WRITE != 0;
+    let object = match get_file(fd)?.mmap(writable) {
+        Ok(object) => object,
+        Err(FsError::NotSupported) => return Err(Errno::ENODEV),
+        Err(e) => return Err(e.into()),
+    };
+
+    current(|process| {
+        let base = process
+            .map_shared(object, writable)
+            .map_err(|_| Errno::ENOMEM)?;
+        Ok(base.as_u64() as usize)
+    })
+}
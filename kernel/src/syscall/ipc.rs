@@ -0,0 +1,333 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use x86_64::VirtAddr;
+
+use super::{Errno, SyscallResult, read_user, user_slice_mut, write_user};
+use crate::ipc::channel::{
+    self, ChannelError, Endpoint, MAX_MESSAGE_HANDLES, MAX_MESSAGE_SIZE, Message,
+};
+use crate::ipc::handle::{Handle, KernelObject, MAX_HANDLES};
+use crate::ipc::poll::{self, PollEntry, PollFlags};
+use crate::ipc::shm::{self, SharedMemory};
+use crate::memory;
+use crate::process::{Process, with_current};
+
+pub const RECV_NONBLOCK: usize = 1;
+pub const SHM_MAP_WRITE: usize = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct UserPollFd {
+    pub handle: i32,
+    pub events: u16,
+    pub revents: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct UserMessage {
+    pub txid: u32,
+    pub len: u32,
+    pub handle_count: u32,
+    pub handles: [Handle; MAX_MESSAGE_HANDLES],
+    pub data: [u8; MAX_MESSAGE_SIZE],
+}
+
+impl From<ChannelError> for Errno {
+    fn from(error: ChannelError) -> Self {
+        match error {
+            ChannelError::ShouldWait => Errno::EAGAIN,
+            ChannelError::PeerClosed => Errno::EPIPE,
+            ChannelError::QueueFull => Errno::EAGAIN,
+            ChannelError::MessageTooLarge => Errno::EMSGSIZE,
+            ChannelError::TooManyHandles => Errno::EINVAL,
+        }
+    }
+}
+
+fn current<R>(f: impl FnOnce(&mut Process) -> Result<R, Errno>) -> Result<R, Errno> {
+    with_current(f).map_err(|_| Errno::EPERM)?
+}
+
+fn get_endpoint(process: &Process, handle: Handle) -> Result<Arc<Endpoint>, Errno> {
+    match process.handles.get(handle) {
+        Some(KernelObject::Channel(endpoint)) => Ok(endpoint.clone()),
+        Some(_) => Err(Errno::EINVAL),
+        None => Err(Errno::EBADF),
+    }
+}
+
+fn check_user_message(ptr: usize) -> Result<(), Errno> {
+    let size = core::mem::size_of::<UserMessage>();
+    if !memory::is_user_writable(VirtAddr::new(ptr as u64), size) {
+        return Err(Errno::EFAULT);
+    }
+    Ok(())
+}
+
+/// Detaches the handles listed in `user` from the caller's table so they can
+/// travel inside a message. Nothing is removed if any handle is invalid.
+fn take_handles(
+    process: &mut Process,
+    endpoint: &Endpoint,
+    user: &UserMessage,
+) -> Result<Vec<(Handle, KernelObject)>, Errno> {
+    let count = user.handle_count as usize;
+    if count > MAX_MESSAGE_HANDLES {
+        return Err(Errno::EINVAL);
+    }
+
+    let wanted = &user.handles[..count];
+    for (i, &handle) in wanted.iter().enumerate() {
+        if wanted[..i].contains(&handle) {
+            return Err(Errno::EINVAL);
+        }
+        match process.handles.get(handle) {
+            Some(KernelObject::Channel(other)) if other.is_same_channel(endpoint) => {
+                return Err(Errno::EINVAL);
+            }
+            Some(_) => {}
+            None => return Err(Errno::EBADF),
+        }
+    }
+
+    Ok(wanted
+        .iter()
+        .map(|&handle| (handle, process.handles.remove(handle).unwrap()))
+        .collect())
+}
+
+fn restore_handles(taken: Vec<Handle>, message: Message) {
+    let _ = with_current(|process| {
+        for (handle, object) in taken.into_iter().zip(message.handles) {
+            process.handles.insert_at(handle, object);
+        }
+    });
+}
+
+fn build_message(
+    process: &mut Process,
+    endpoint: &Endpoint,
+    user: &UserMessage,
+) -> Result<(Vec<Handle>, Message), Errno> {
+    let len = user.len as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(Errno::EMSGSIZE);
+    }
+
+    let taken = take_handles(process, endpoint, user)?;
+    let (numbers, objects): (Vec<Handle>, Vec<KernelObject>) = taken.into_iter().unzip();
+
+    let mut message = Message::new(&user.data[..len], objects)?;
+    message.txid = user.txid;
+    Ok((numbers, message))
+}
+
+fn deliver(message: Message, ptr: usize) -> SyscallResult {
+    let mut user = UserMessage {
+        txid: message.txid,
+        len: message.data.len() as u32,
+        handle_count: 0,
+        handles: [0; MAX_MESSAGE_HANDLES],
+        data: [0; MAX_MESSAGE_SIZE],
+    };
+    user.data[..message.data.len()].copy_from_slice(&message.data);
+
+    // Either every handle in the message is installed or none is.
+    current(|process| {
+        if process.handles.len() + message.handles.len() > MAX_HANDLES {
+            return Err(Errno::EMFILE);
+        }
+        for object in message.handles {
+            let handle = process.handles.insert(object).map_err(|_| Errno::EMFILE)?;
+            user.handles[user.handle_count as usize] = handle;
+            user.handle_count += 1;
+        }
+        Ok(())
+    })?;
+
+    if let Err(error) = write_user(ptr, &user) {
+        let installed = &user.handles[..user.handle_count as usize];
+        let _ = with_current(|process| {
+            for &handle in installed {
+                process.handles.remove(handle);
+            }
+        });
+        return Err(error);
+    }
+    Ok(user.len as usize)
+}
+
+pub fn sys_handle_close(handle: usize) -> SyscallResult {
+    current(|process| {
+        process
+            .handles
+            .remove(handle as Handle)
+            .map(|_| 0)
+            .ok_or(Errno::EBADF)
+    })
+}
+
+pub fn sys_channel_create(out_ptr: usize) -> SyscallResult {
+    let (a, b) = channel::create();
+
+    let handles = current(|process| {
+        let first = process
+            .handles
+            .insert(KernelObject::Channel(Arc::new(a)))
+            .map_err(|_| Errno::EMFILE)?;
+        match process.handles.insert(KernelObject::Channel(Arc::new(b))) {
+            Ok(second) => Ok([first, second]),
+            Err(_) => {
+                process.handles.remove(first);
+                Err(Errno::EMFILE)
+            }
+        }
+    })?;
+
+    if let Err(e) = write_user(out_ptr, &handles) {
+        let _ = current(|process| {
+            process.handles.remove(handles[0]);
+            process.handles.remove(handles[1]);
+            Ok(())
+        });
+        return Err(e);
+    }
+    Ok(0)
+}
+
+pub fn sys_channel_send(handle: usize, msg_ptr: usize) -> SyscallResult {
+    let user: UserMessage = read_user(msg_ptr)?;
+
+    let (endpoint, taken, message) = current(|process| {
+        let endpoint = get_endpoint(process, handle as Handle)?;
+        let (taken, message) = build_message(process, &endpoint, &user)?;
+        Ok((endpoint, taken, message))
+    })?;
+
+    match endpoint.send(message) {
+        Ok(()) => Ok(0),
+        Err((error, message)) => {
+            restore_handles(taken, message);
+            Err(error.into())
+        }
+    }
+}
+
+pub fn sys_channel_recv(handle: usize, msg_ptr: usize, flags: usize) -> SyscallResult {
+    check_user_message(msg_ptr)?;
+    let endpoint = current(|process| get_endpoint(process, handle as Handle))?;
+
+    let message = if flags & RECV_NONBLOCK != 0 {
+        endpoint.try_recv()?
+    } else {
+        endpoint.recv()?
+    };
+
+    deliver(message, msg_ptr)
+}
+
+pub fn sys_channel_call(handle: usize, msg_ptr: usize, reply_ptr: usize) -> SyscallResult {
+    check_user_message(reply_ptr)?;
+    let user: UserMessage = read_user(msg_ptr)?;
+
+    let (endpoint, taken, message) = current(|process| {
+        let endpoint = get_endpoint(process, handle as Handle)?;
+        let (taken, message) = build_message(process, &endpoint, &user)?;
+        Ok((endpoint, taken, message))
+    })?;
+
+    match endpoint.call(message) {
+        Ok(reply) => deliver(reply, reply_ptr),
+        Err((error, Some(message))) => {
+            restore_handles(taken, message);
+            Err(error.into())
+        }
+        Err((error, None)) => Err(error.into()),
+    }
+}
+
+pub fn sys_shm_create(size: usize) -> SyscallResult {
+    let object = SharedMemory::new(size).map_err(|_| {
+        if size == 0 || size > shm::MAX_SHM_SIZE {
+            Errno::EINVAL
+        } else {
+            Errno::ENOMEM
+        }
+    })?;
+
+    current(|process| {
+        process
+            .handles
+            .insert(KernelObject::SharedMemory(Arc::new(object)))
+            .map(|handle| handle as usize)
+            .map_err(|_| Errno::EMFILE)
+    })
+}
+
+pub fn sys_shm_map(handle: usize, flags: usize) -> SyscallResult {
+    current(|process| {
+        let object = match process.handles.get(handle as Handle) {
+            Some(KernelObject::SharedMemory(object)) => object.clone(),
+            Some(_) => return Err(Errno::EINVAL),
+            None => return Err(Errno::EBADF),
+        };
+
+        let writable = flags & SHM_MAP_WRITE != 0;
+        let base = process
+            .map_shared(object, writable)
+            .map_err(|_| Errno::ENOMEM)?;
+        Ok(base.as_u64() as usize)
+    })
+}
+
+pub fn sys_shm_unmap(addr: usize) -> SyscallResult {
+    current(|process| {
+        let addr = VirtAddr::try_new(addr as u64).map_err(|_| Errno::EINVAL)?;
+        let index = process
+            .shm_mappings
+            .iter()
+            .position(|m| m.base == addr)
+            .ok_or(Errno::EINVAL)?;
+
+        let mapping = process.shm_mappings.remove(index);
+        shm::unmap(process.address_space, mapping);
+        Ok(0)
+    })
+}
+
+pub fn sys_poll(fds_ptr: usize, nfds: usize, timeout_ms: usize) -> SyscallResult {
+    if nfds > MAX_HANDLES {
+        return Err(Errno::EINVAL);
+    }
+
+    let size = nfds * core::mem::size_of::<UserPollFd>();
+    let bytes = user_slice_mut(fds_ptr, size)?;
+    let fds =
+        unsafe { core::slice::from_raw_parts_mut(bytes.as_mut_ptr() as *mut UserPollFd, nfds) };
+
+    let mut entries: Vec<PollEntry> = current(|process| {
+        Ok(fds
+            .iter()
+            .map(|fd| PollEntry {
+                object: u32::try_from(fd.handle)
+                    .ok()
+                    .and_then(|h| process.handles.get(h).cloned()),
+                events: PollFlags::from_bits_truncate(fd.events),
+                revents: PollFlags::empty(),
+            })
+            .collect())
+    })?;
+
+    // A negative timeout waits forever.
+    let timeout = match timeout_ms as isize {
+        t if t < 0 => None,
+        t => Some(t as u64),
+    };
+    let ready = poll::poll(&mut entries, timeout);
+
+    for (fd, entry) in fds.iter_mut().zip(&entries) {
+        fd.revents = entry.revents.bits();
+    }
+    Ok(ready)
+}
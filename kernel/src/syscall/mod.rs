@@ -0,0 +1,264 @@
+use core::arch::global_asm;
+use x86_64::VirtAddr;
+use x86_64::registers::model_specific::{Efer, EferFlags, KernelGsBase, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+
+use crate::gdt;
+use crate::memory;
+
+mod fs;
+mod ipc;
+
+pub const SYS_PRINT: usize = 1;
+pub const SYS_HANDLE_CLOSE: usize = 2;
+pub const SYS_CHANNEL_CREATE: usize = 3;
+pub const SYS_CHANNEL_SEND: usize = 4;
+pub const SYS_CHANNEL_RECV: usize = 5;
+pub const SYS_CHANNEL_CALL: usize = 6;
+pub const SYS_SHM_CREATE: usize = 7;
+pub const SYS_SHM_MAP: usize = 8;
+pub const SYS_SHM_UNMAP: usize = 9;
+pub const SYS_POLL: usize = 10;
+pub const SYS_OPEN: usize = 11;
+pub const SYS_READ: usize = 12;
+pub const SYS_WRITE: usize = 13;
+pub const SYS_LSEEK: usize = 14;
+pub const SYS_FSTAT: usize = 15;
+pub const SYS_DUP: usize = 16;
+pub const SYS_DUP2: usize = 17;
+pub const SYS_GETDENTS: usize = 18;
+pub const SYS_CHDIR: usize = 19;
+pub const SYS_GETCWD: usize = 20;
+pub const SYS_MKDIR: usize = 21;
+pub const SYS_RMDIR: usize = 22;
+pub const SYS_UNLINK: usize = 23;
+pub const SYS_RENAME: usize = 24;
+pub const SYS_FTRUNCATE: usize = 25;
+pub const SYS_SYNC: usize = 26;
+pub const SYS_FSCK: usize = 27;
+pub const SYS_SYMLINK: usize = 28;
+pub const SYS_READLINK: usize = 29;
+pub const SYS_LINK: usize = 30;
+pub const SYS_CHMOD: usize = 31;
+pub const SYS_CHOWN: usize = 32;
+pub const SYS_IOCTL: usize = 33;
+pub const SYS_MMAP: usize = 34;
+
+/// Descriptors and handles share one table, so `close` is `handle_close`.
+pub const SYS_CLOSE: usize = SYS_HANDLE_CLOSE;
+/// File mappings are kept with shared memory ones, so `munmap` is
+/// `shm_unmap`.
+pub const SYS_MUNMAP: usize = SYS_SHM_UNMAP;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(isize)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    EIO = 5,
+    EBADF = 9,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EACCES = 13,
+    EFAULT = 14,
+    EBUSY = 16,
+    EEXIST = 17,
+    EXDEV = 18,
+    ENODEV = 19,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    EMFILE = 24,
+    ENOTTY = 25,
+    EFBIG = 27,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EROFS = 30,
+    EMLINK = 31,
+    ERANGE = 34,
+    EPIPE = 32,
+    ENAMETOOLONG = 36,
+    ENOSYS = 38,
+    ENOTEMPTY = 39,
+    ELOOP = 40,
+    EMSGSIZE = 90,
+    EOPNOTSUPP = 95,
+}
+
+pub type SyscallResult = Result<usize, Errno>;
+
+fn encode_result(result: SyscallResult) -> usize {
+    match result {
+        Ok(value) => value,
+        Err(errno) => (-(errno as isize)) as usize,
+    }
+}
+
+pub(crate) fn user_slice<'a>(ptr: usize, len: usize) -> Result<&'a [u8], Errno> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if !memory::is_user_readable(VirtAddr::new(ptr as u64), len) {
+        return Err(Errno::EFAULT);
+    }
+    Ok(unsafe { core::slice::from_raw_parts(ptr as *const u8, len) })
+}
+
+pub(crate) fn user_slice_mut<'a>(ptr: usize, len: usize) -> Result<&'a mut [u8], Errno> {
+    if len == 0 {
+        return Ok(&mut []);
+    }
+    if !memory::is_user_writable(VirtAddr::new(ptr as u64), len) {
+        return Err(Errno::EFAULT);
+    }
+    Ok(unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, len) })
+}
+
+pub(crate) fn read_user<T: Copy>(ptr: usize) -> Result<T, Errno> {
+    let bytes = user_slice(ptr, core::mem::size_of::<T>())?;
+    Ok(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+pub(crate) fn write_user<T: Copy>(ptr: usize, value: &T) -> Result<(), Errno> {
+    let bytes = user_slice_mut(ptr, core::mem::size_of::<T>())?;
+    unsafe { core::ptr::write_unaligned(bytes.as_mut_ptr() as *mut T, *value) };
+    Ok(())
+}
+
+#[repr(C)]
+pub struct KernelScratch {
+    pub kernel_stack_top: u64,
+    pub user_stack_scratch: u64,
+}
+
+const SYSCALL_STACK_SIZE: usize = 4096 * 4;
+static mut SYSCALL_STACK: [u8; SYSCALL_STACK_SIZE] = [0; SYSCALL_STACK_SIZE];
+
+static mut KERNEL_SCRATCH: KernelScratch = KernelScratch {
+    kernel_stack_top: 0,
+    user_stack_scratch: 0,
+};
+
+pub fn init_syscall() {
+    unsafe {
+        Efer::update(|flags| {
+            flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS);
+        });
+
+        LStar::write(VirtAddr::new(syscall_dispatcher as *const () as u64));
+
+        let code_selector = gdt::get_kernel_code_selector();
+        let data_selector = gdt::get_kernel_data_selector();
+        let (user_code_selector, user_data_selector) = gdt::get_user_selectors();
+
+        Star::write(
+            user_code_selector,
+            user_data_selector,
+            code_selector,
+            data_selector,
+        )
+        .unwrap();
+
+        SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::TRAP_FLAG);
+
+        let stack_top = VirtAddr::from_ptr(&raw const SYSCALL_STACK) + SYSCALL_STACK_SIZE as u64;
+        KERNEL_SCRATCH.kernel_stack_top = stack_top.as_u64();
+
+        let scratch_addr = VirtAddr::from_ptr(&raw const KERNEL_SCRATCH);
+        KernelGsBase::write(scratch_addr);
+    }
+}
+
+pub unsafe fn enter_userspace(entry_point: u64, stack_pointer: u64) -> ! {
+    let (user_code_selector, user_data_selector) = crate::gdt::get_user_selectors();
+    let rflags = (RFlags::INTERRUPT_FLAG | RFlags::from_bits_truncate(1 << 1)).bits();
+
+    unsafe {
+        core::arch::asm!(
+            "push {ss:r}",
+            "push {rsp}",
+            "push {rflags}",
+            "push {cs:r}",
+            "push {rip}",
+            "iretq",
+            ss = in(reg) user_data_selector.0,
+            rsp = in(reg) stack_pointer,
+            rflags = in(reg) rflags,
+            cs = in(reg) user_code_selector.0,
+            rip = in(reg) entry_point,
+            options(noreturn)
+        );
+    }
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn syscall_rust_handler(
+    syscall_id: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+    arg4: usize,
+    _arg5: usize,
+    _arg6: usize,
+) -> usize {
+    let result = match syscall_id {
+        SYS_PRINT => sys_print(arg1, arg2),
+        SYS_HANDLE_CLOSE => ipc::sys_handle_close(arg1),
+        SYS_CHANNEL_CREATE => ipc::sys_channel_create(arg1),
+        SYS_CHANNEL_SEND => ipc::sys_channel_send(arg1, arg2),
+        SYS_CHANNEL_RECV => ipc::sys_channel_recv(arg1, arg2, arg3),
+        SYS_CHANNEL_CALL => ipc::sys_channel_call(arg1, arg2, arg3),
+        SYS_SHM_CREATE => ipc::sys_shm_create(arg1),
+        SYS_SHM_MAP => ipc::sys_shm_map(arg1, arg2),
+        SYS_SHM_UNMAP => ipc::sys_shm_unmap(arg1),
+        SYS_POLL => ipc::sys_poll(arg1, arg2, arg3),
+        SYS_OPEN => fs::sys_open(arg1, arg2, arg3),
+        SYS_READ => fs::sys_read(arg1, arg2, arg3),
+        SYS_WRITE => fs::sys_write(arg1, arg2, arg3),
+        SYS_LSEEK => fs::sys_lseek(arg1, arg2, arg3),
+        SYS_FSTAT => fs::sys_fstat(arg1, arg2),
+        SYS_DUP => fs::sys_dup(arg1),
+        SYS_DUP2 => fs::sys_dup2(arg1, arg2),
+        SYS_GETDENTS => fs::sys_getdents(arg1, arg2, arg3),
+        SYS_CHDIR => fs::sys_chdir(arg1, arg2),
+        SYS_GETCWD => fs::sys_getcwd(arg1, arg2),
+        SYS_MKDIR => fs::sys_mkdir(arg1, arg2),
+        SYS_RMDIR => fs::sys_rmdir(arg1, arg2),
+        SYS_UNLINK => fs::sys_unlink(arg1, arg2),
+        SYS_RENAME => fs::sys_rename(arg1, arg2, arg3, arg4),
+        SYS_FTRUNCATE => fs::sys_ftruncate(arg1, arg2),
+        SYS_SYNC => fs::sys_sync(),
+        SYS_FSCK => fs::sys_fsck(arg1, arg2, arg3),
+        SYS_SYMLINK => fs::sys_symlink(arg1, arg2, arg3, arg4),
+        SYS_READLINK => fs::sys_readlink(arg1, arg2, arg3, arg4),
+        SYS_LINK => fs::sys_link(arg1, arg2, arg3, arg4),
+        SYS_CHMOD => fs::sys_chmod(arg1, arg2, arg3),
+        SYS_CHOWN => fs::sys_chown(arg1, arg2, arg3, arg4),
+        SYS_IOCTL => fs::sys_ioctl(arg1, arg2, arg3, arg4),
+        SYS_MMAP => fs::sys_mmap(arg1, arg2),
+        _ => {
+            crate::serial_println!(
+                "SYSCALL: unknown ID={}, arg1={:#x}, arg2={:#x}",
+                syscall_id,
+                arg1,
+                arg2
+            );
+            Err(Errno::ENOSYS)
+        }
+    };
+
+    encode_result(result)
+}
+
+fn sys_print(ptr: usize, len: usize) -> SyscallResult {
+    let bytes = user_slice(ptr, len)?;
+    let text = core::str::from_utf8(bytes).map_err(|_| Errno::EINVAL)?;
+    crate::serial_print!("{}", text);
+    Ok(len)
+}
+
+global_asm!(include_str!("syscall_asm.asm"));
+
+unsafe extern "C" {
+    fn syscall_dispatcher();
+}
@@ -7,27 +7,57 @@ use crossbeam_queue::ArrayQueue;
 use futures_util::stream::Stream;
 use futures_util::task::AtomicWaker;
 
+use crate::ipc::wait::WaitQueue;
+
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+/// A copy of every scancode for readers of the raw keyboard device, so that
+/// they and the console do not take keys from each other. When it is full
+/// the oldest scancodes are dropped.
+static RAW_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
+pub static KEYBOARD_WAITERS: WaitQueue = WaitQueue::new();
 
 pub(crate) fn add_scancode(scancode: u8) {
+    if let Ok(queue) = RAW_QUEUE.try_get() {
+        queue.force_push(scancode);
+        KEYBOARD_WAITERS.wake_all();
+    }
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
         if queue.push(scancode).is_err() {
         } else {
             WAKER.wake();
+            KEYBOARD_WAITERS.wake_all();
         }
     }
 }
 
+pub fn init_queue() {
+    let _ = SCANCODE_QUEUE.try_init_once(|| ArrayQueue::new(100));
+}
+
+pub fn pop_scancode() -> Option<u8> {
+    SCANCODE_QUEUE.try_get().ok()?.pop()
+}
+
+pub fn init_raw_queue() {
+    let _ = RAW_QUEUE.try_init_once(|| ArrayQueue::new(100));
+}
+
+pub fn pop_raw_scancode() -> Option<u8> {
+    RAW_QUEUE.try_get().ok()?.pop()
+}
+
+pub fn has_raw_scancode() -> bool {
+    RAW_QUEUE.try_get().is_ok_and(|queue| !queue.is_empty())
+}
+
 pub struct ScancodeStream {
     _private: (),
 }
 
 impl ScancodeStream {
     pub fn new() -> Self {
-        SCANCODE_QUEUE
-            .try_init_once(|| ArrayQueue::new(100))
-            .expect("ScancodeStream::new should only be called once");
+        init_queue();
         ScancodeStream { _private: () }
     }
 }
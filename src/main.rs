@@ -8,32 +8,52 @@ use std::process::{Command, ExitStatus};
 
 const DISK_SIZE_MB: usize = 64;
 const INPUT_DIR: &str = "disk";
-const USER_PROGRAM_DIR: &str = "user_programs";
+/// `disk` plus the user programs, assembled here so that building the image
+/// never writes into `disk`, which `build.rs` watches.
+const STAGING_DIR: &str = "target/disk_staging";
 const IMAGE_FILE: &str = "user_disk.img";
 const OUTPUT_DIR: &str = "disk_modified";
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Volume {
+    Fat32,
+    Ext2,
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let uefi_path = env!("UEFI_PATH");
     let bios_path = env!("BIOS_PATH");
 
     let args: Vec<String> = env::args().collect();
+    let usage = "Usage: cargo run -- [uefi|bios] [fat|ext2|none]";
     let uefi = match args.get(1).map(|s| s.as_str()) {
         Some("uefi") => true,
         Some("bios") => false,
-        _ => return Err("Usage: cargo run -- [uefi|bios]".into()),
+        _ => return Err(usage.into()),
+    };
+    // Without a disk the kernel boots from the initramfs alone.
+    let volume = match args.get(2).map(|s| s.as_str()) {
+        None | Some("fat") => Some(Volume::Fat32),
+        Some("ext2") => Some(Volume::Ext2),
+        Some("none") => None,
+        _ => return Err(usage.into()),
     };
 
-    prepare_disk_image()?;
+    if let Some(volume) = volume {
+        prepare_disk_image(volume)?;
+    }
 
     let mut cmd = Command::new("qemu-system-x86_64");
     cmd.arg("-serial").arg("mon:stdio");
     cmd.arg("-device")
         .arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
 
-    cmd.arg("-drive").arg(format!(
-        "file={},format=raw,if=ide,index=1,media=disk",
-        IMAGE_FILE
-    ));
+    if volume.is_some() {
+        cmd.arg("-drive").arg(format!(
+            "file={},format=raw,if=ide,index=1,media=disk",
+            IMAGE_FILE
+        ));
+    }
 
     if uefi {
         let prebuilt = Prebuilt::fetch(Source::LATEST, "target/ovmf")?;
@@ -58,57 +78,63 @@ fn main() -> Result<(), Box<dyn Error>> {
     let mut child = cmd.spawn()?;
     child.wait()?;
 
-    extract_disk_image()?;
+    if let Some(volume) = volume {
+        extract_disk_image(volume)?;
+    }
     Ok(())
 }
 
-fn prepare_user_programs() -> Result<(), Box<dyn Error>> {
-    let user_programs_path = Path::new(USER_PROGRAM_DIR);
-    if !user_programs_path.exists() {
-        fs::create_dir_all(user_programs_path)?;
+/// Fills the staging folder with a copy of `disk` and the user programs,
+/// which `build.rs` compiles for the initramfs.
+fn stage_disk_contents() -> Result<(), Box<dyn Error>> {
+    let staging = Path::new(STAGING_DIR);
+    if staging.exists() {
+        fs::remove_dir_all(staging)?;
     }
+    copy_tree(Path::new(INPUT_DIR), staging)?;
 
-    for entry in fs::read_dir(user_programs_path)? {
+    if let Ok(entries) = fs::read_dir(env!("USER_PROGRAMS_PATH")) {
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+
+            println!("Copying {} user program...", name.to_string_lossy());
+            fs::copy(entry.path(), staging.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
         let entry = entry?;
-        let path = entry.path();
-
-        if path.extension().map_or(false, |e| e == "rs") {
-            let stem = path
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .ok_or("Invalid filename")?;
-            let output_path = format!("{}/{}", INPUT_DIR, stem);
-
-            println!("Compiling {} user program...", stem);
-            let status = Command::new("rustc")
-                .arg("-O")
-                .arg("--target")
-                .arg("x86_64-unknown-none")
-                .arg("--crate-type")
-                .arg("bin")
-                .arg(&path)
-                .arg("-C")
-                .arg("link-arg=--image-base=0x400000")
-                .arg("-o")
-                .arg(&output_path)
-                .status()?;
-
-            if !status.success() {
-                return Err(format!("Failed to compile {:?}", path).into());
-            }
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            copy_tree(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
         }
     }
     Ok(())
 }
 
-fn prepare_disk_image() -> Result<(), Box<dyn Error>> {
+fn prepare_disk_image(volume: Volume) -> Result<(), Box<dyn Error>> {
     let disk_path = Path::new(INPUT_DIR);
     if !disk_path.exists() {
         fs::create_dir(disk_path)?;
         fs::write(disk_path.join("README.txt"), "Put files in this folder!")?;
     }
 
-    let _ = prepare_user_programs();
+    stage_disk_contents()?;
+
+    if volume == Volume::Ext2 {
+        return prepare_ext2_image();
+    }
 
     println!("Creating {}MB FAT32 disk image...", DISK_SIZE_MB);
 
@@ -126,13 +152,13 @@ fn prepare_disk_image() -> Result<(), Box<dyn Error>> {
         return Err("mkfs.fat failed".into());
     }
 
-    println!("Copying files from '{}' to disk image...", INPUT_DIR);
+    println!("Copying files from '{}' to disk image...", STAGING_DIR);
 
     let mut mcopy_cmd = Command::new("mcopy");
     mcopy_cmd.arg("-i").arg(IMAGE_FILE).arg("-s");
 
     let mut has_files = false;
-    for entry in fs::read_dir(INPUT_DIR)? {
+    for entry in fs::read_dir(STAGING_DIR)? {
         let entry = entry?;
         mcopy_cmd.arg(entry.path());
         has_files = true;
@@ -145,12 +171,42 @@ fn prepare_disk_image() -> Result<(), Box<dyn Error>> {
             return Err("mcopy failed to copy files".into());
         }
     } else {
-        println!("No files found in '{}', skipping copy.", INPUT_DIR);
+        println!("No files found in '{}', skipping copy.", STAGING_DIR);
     }
     Ok(())
 }
 
-fn extract_disk_image() -> Result<(), Box<dyn Error>> {
+/// `mke2fs -d` populates the filesystem while creating it, so no separate
+/// copy step is needed.
+fn prepare_ext2_image() -> Result<(), Box<dyn Error>> {
+    println!(
+        "Creating {}MB ext2 disk image from '{}'...",
+        DISK_SIZE_MB, STAGING_DIR
+    );
+
+    if Path::new(IMAGE_FILE).exists() {
+        fs::remove_file(IMAGE_FILE)?;
+    }
+
+    let status = Command::new("mke2fs")
+        .arg("-q")
+        .arg("-t")
+        .arg("ext2")
+        .arg("-d")
+        .arg(STAGING_DIR)
+        .arg("-F")
+        .arg(IMAGE_FILE)
+        .arg(format!("{}M", DISK_SIZE_MB))
+        .output()?;
+
+    if !status.status.success() {
+        io::stderr().write_all(&status.stderr)?;
+        return Err("mke2fs failed".into());
+    }
+    Ok(())
+}
+
+fn extract_disk_image(volume: Volume) -> Result<(), Box<dyn Error>> {
     println!("Extracting disk state to '{}'...", OUTPUT_DIR);
     let output_path = Path::new(OUTPUT_DIR);
 
@@ -159,14 +215,22 @@ fn extract_disk_image() -> Result<(), Box<dyn Error>> {
     }
     fs::create_dir(output_path)?;
 
-    let status = Command::new("mcopy")
-        .arg("-i")
-        .arg(IMAGE_FILE)
-        .arg("-s")
-        .arg("-n")
-        .arg("::/")
-        .arg(OUTPUT_DIR)
-        .status()?;
+    let status: ExitStatus = match volume {
+        Volume::Fat32 => Command::new("mcopy")
+            .arg("-i")
+            .arg(IMAGE_FILE)
+            .arg("-s")
+            .arg("-n")
+            .arg("-m")
+            .arg("::/")
+            .arg(OUTPUT_DIR)
+            .status()?,
+        Volume::Ext2 => Command::new("debugfs")
+            .arg("-R")
+            .arg(format!("rdump / {}", OUTPUT_DIR))
+            .arg(IMAGE_FILE)
+            .status()?,
+    };
 
     if !status.success() {
         return Err("Failed to extract disk image".into());